@@ -19,7 +19,24 @@ from .rlgym_learn import (
     Timestep,
 )
 from .rlgym_learn import env_process as rust_env_process
-from .rlgym_learn import recvfrom_byte, sendto_byte
+from .rlgym_learn import (
+    ActionSpaceDescriptor,
+    ActionSpaceKind,
+    AgentOptions,
+    BufferLayoutConfig,
+    BufferOptions,
+    DebugOptions,
+    EpisodeOptions,
+    ObsOptions,
+    RenderOptions,
+    SerdeOptions,
+    StepOptions,
+    buffer_layout,
+    recvfrom_byte,
+    roundtrip_check,
+    run_env_steps,
+    sendto_byte,
+)
 
 try:
     from . import rocket_league
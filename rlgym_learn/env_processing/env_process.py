@@ -6,7 +6,7 @@ import socket
 from collections.abc import Callable
 from dataclasses import dataclass
 from datetime import timedelta
-from typing import Optional
+from typing import Any, Dict, Optional
 
 try:
     import numpy as np
@@ -68,6 +68,7 @@ def env_process(
     render_this_proc: bool,
     render_delay: Optional[float],
     recalculate_agent_id_every_step: bool,
+    env_process_options: Dict[str, Any],
 ):
     signal.signal(signal.SIGINT, signal.SIG_IGN)
     child_end = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
@@ -99,4 +100,5 @@ def env_process(
         render_this_proc,
         timedelta(seconds=render_delay),
         recalculate_agent_id_every_step,
+        **env_process_options,
     )
@@ -1,11 +1,13 @@
 from __future__ import annotations
 
+import copy
 import multiprocessing as mp
 import os
 import socket
 import time
 import traceback
 from collections.abc import Callable
+from datetime import timedelta
 from typing import Any, Dict, Generic, List, Optional, Tuple
 from uuid import uuid4
 
@@ -23,9 +25,25 @@ from rlgym.api import (
 
 from ..api import ActionAssociatedLearningData
 from ..learning_coordinator_config import SerdeTypesModel
-from ..rlgym_learn import EnvAction
+from ..rlgym_learn import EnvAction, EpisodeBoundaryReason
 from ..rlgym_learn import EnvProcessInterface as RustEnvProcessInterface
-from ..rlgym_learn import PickleablePyAnySerdeType, Timestep, recvfrom_byte, sendto_byte
+from ..rlgym_learn import (
+    ActionSpaceDescriptor,
+    AgentOptions,
+    BufferOptions,
+    DebugOptions,
+    EpisodeOptions,
+    ObsOptions,
+    RenderOptions,
+    SerdeOptions,
+    StepOptions,
+    PickleablePyAnySerdeType,
+    PyAnySerdeType,
+    Timestep,
+    recvfrom_byte,
+    sendto_byte,
+)
+from . import env_process_options  # registers the reducers which pickle the options objects
 from .env_process import PickleableSerdeTypeConfig, env_process
 
 try:
@@ -70,6 +88,14 @@ class EnvProcessInterface(
         shm_buffer_size: int,
         seed: int,
         recalculate_agent_id_every_step: bool,
+        agent_options: Optional[AgentOptions] = None,
+        step_options: Optional[StepOptions] = None,
+        episode_options: Optional[EpisodeOptions] = None,
+        obs_options: Optional[ObsOptions] = None,
+        buffer_options: Optional[BufferOptions] = None,
+        render_options: Optional[RenderOptions] = None,
+        debug_options: Optional[DebugOptions] = None,
+        serde_options: Optional[SerdeOptions] = None,
     ):
         self.build_env_fn = build_env_fn
         self.serde_type_config = PickleableSerdeTypeConfig(
@@ -87,6 +113,22 @@ class EnvProcessInterface(
         self.shm_buffer_size = shm_buffer_size
         self.seed = seed
         self.recalculate_agent_id_every_step = recalculate_agent_id_every_step
+        # The same options objects are given to every env process and to the Rust interface, so
+        # that both sides agree on the fields in each message
+        self.env_process_options = {
+            name: options
+            for name, options in [
+                ("agent_options", agent_options),
+                ("step_options", step_options),
+                ("episode_options", episode_options),
+                ("obs_options", obs_options),
+                ("buffer_options", buffer_options),
+                ("render_options", render_options),
+                ("debug_options", debug_options),
+                ("serde_options", serde_options),
+            ]
+            if options is not None
+        }
         self.n_procs = 0
 
         os.makedirs(flinks_folder, exist_ok=True)
@@ -104,8 +146,22 @@ class EnvProcessInterface(
             self.recalculate_agent_id_every_step,
             flinks_folder,
             min_process_steps_per_inference,
+            agent_options=agent_options,
+            episode_options=episode_options,
+            obs_options=obs_options,
+            buffer_options=buffer_options,
+            serde_options=serde_options,
         )
 
+    def _env_process_options(self, pid_idx: int) -> Dict[str, Any]:
+        # With a partitioned buffer, each env process is given the base offset of its own partition
+        base_offset = self.rust_env_process_interface.partition_base_offset(pid_idx)
+        if base_offset is None:
+            return self.env_process_options
+        buffer_options = copy.copy(self.env_process_options["buffer_options"])
+        buffer_options.base_offset_option = base_offset
+        return {**self.env_process_options, "buffer_options": buffer_options}
+
     def init_processes(
         self,
         n_processes: int,
@@ -158,6 +214,7 @@ class EnvProcessInterface(
                     render_this_proc,
                     render_delay,
                     self.recalculate_agent_id_every_step,
+                    self._env_process_options(proc_idx),
                 ),
                 daemon=True,
             )
@@ -221,6 +278,7 @@ class EnvProcessInterface(
                 False,
                 0,
                 self.recalculate_agent_id_every_step,
+                self._env_process_options(self.n_procs - 1),
             ),
             daemon=True,
         )
@@ -271,6 +329,182 @@ class EnvProcessInterface(
             print("Unable to close parent connection")
             traceback.print_exc()
 
+    def episode_boundary_reason(self, proc_id: str) -> Optional[EpisodeBoundaryReason]:
+        """
+        Get why the most recent reset-style message from the given process was written (explicit reset, set state, or
+        reset after an agent terminated / truncated). Only available if episode_boundary_reasons is enabled.
+        :return: The reason, or None if no reset-style message with a reason has been received from the process.
+        """
+        return self.rust_env_process_interface.episode_boundary_reason(proc_id)
+
+    def set_seed(self, proc_id: str, seed: int):
+        """
+        Reseed the env of the given process without resetting it.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        """
+        self.rust_env_process_interface.set_seed(proc_id, seed)
+
+    def dump_state(self, proc_id: str, path: str):
+        """
+        Write the current state of the env of the given process to a file, serialized using the state serde. The bytes
+        can later be deserialized with the same serde and used with SET_STATE to replay the state.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        """
+        self.rust_env_process_interface.dump_state(proc_id, path)
+
+    def dump_buffer(self, proc_id: str):
+        """
+        Make the given process write a hex dump of its last env action response to the buffer dump dir it was started
+        with, for debugging deserialization errors. The response can no longer be collected afterwards.
+        """
+        self.rust_env_process_interface.dump_buffer(proc_id)
+
+    def action_space_descriptor(self) -> Optional[ActionSpaceDescriptor]:
+        """
+        Get a normalized description of the action space (its kind and its nvec or low and high bounds), which can be
+        used to configure policy output heads. Only available after init_processes.
+        """
+        return self.rust_env_process_interface.action_space_descriptor()
+
+    def reconfigure(
+        self,
+        obs_serde_type: Optional[PyAnySerdeType[ObsType]] = None,
+        action_serde_type: Optional[PyAnySerdeType[ActionType]] = None,
+        reward_serde_type: Optional[PyAnySerdeType[RewardType]] = None,
+    ):
+        """
+        Replace the obs, action and reward serdes used by all processes, e.g. to switch observation encodings mid-run.
+        Serdes which are not provided are kept, and processes added afterwards use the new serdes.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        """
+        self.rust_env_process_interface.reconfigure(
+            obs_serde_type, action_serde_type, reward_serde_type
+        )
+        if obs_serde_type is not None:
+            self.serde_type_config.obs_serde_type = PickleablePyAnySerdeType(
+                obs_serde_type
+            )
+        if action_serde_type is not None:
+            self.serde_type_config.action_serde_type = PickleablePyAnySerdeType(
+                action_serde_type
+            )
+        if reward_serde_type is not None:
+            self.serde_type_config.reward_serde_type = PickleablePyAnySerdeType(
+                reward_serde_type
+            )
+
+    def action_history(
+        self, proc_id: str
+    ) -> List[List[Tuple[AgentID, ActionType, RewardType]]]:
+        """
+        Get the recent action history of the given process, which is only recorded if the process was started with an
+        action history length.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        :return: For each of the last steps, oldest first, a list of (agent id, action, reward) for each agent.
+        """
+        return self.rust_env_process_interface.action_history(proc_id)
+
+    def env_config(self, proc_id: str) -> Optional[Any]:
+        """
+        Get the config of the given process' env, which is the env's config attribute (or its metadata attribute if it
+        has no config attribute) as read at startup. It is serialized with the shared info serde, so one must be provided.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        :return: The config, or None if the env has neither attribute.
+        """
+        return self.rust_env_process_interface.env_config(proc_id)
+
+    def set_state_batch(
+        self, proc_id: str, states: List[StateType]
+    ) -> List[Tuple[List[AgentID], List[ObsType]]]:
+        """
+        Set the env of the given process to each of the given states in turn, collecting the resulting observations in a
+        single exchange with the process. The env is left in the last state, so the next env action sent to the process
+        should be a reset or set state.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        :return: For each state, the list of agent ids and the parallel list of observations.
+        """
+        return self.rust_env_process_interface.set_state_batch(proc_id, states)
+
+    def sample_actions(self, proc_id: str) -> List[ActionType]:
+        """
+        Sample a valid action from each agent's action space in the env of the given process, serialized and deserialized
+        using the action serde. Useful for verifying action serialization.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        :return: A list of sampled actions, parallel to the current agent id list of the process.
+        """
+        return self.rust_env_process_interface.sample_actions(proc_id)
+
+    def observe(self, proc_id: str) -> List[ObsType]:
+        """
+        Get the current observation of each agent in the env of the given process without stepping it, using the env's
+        build_obs method.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        :return: A list of observations, parallel to the current agent id list of the process.
+        """
+        return self.rust_env_process_interface.observe(proc_id)
+
+    def agent_count(
+        self, proc_id: str, include_agent_ids: bool = False
+    ) -> Tuple[int, Optional[List[AgentID]]]:
+        """
+        Get the number of agents currently in the env of the given process without stepping it.
+        It is expected that this method is called after collect_step_data and before send_env_actions
+        :param include_agent_ids: whether to also return the current agent id list of the process.
+        :return: A tuple of the number of agents and, if requested, the list of agent ids.
+        """
+        return self.rust_env_process_interface.agent_count(proc_id, include_agent_ids)
+
+    def set_pool_state(self, pool_index: int, state: StateType):
+        """
+        Serialize a state into the given slot of the state pool, which is only available if the state pool was created
+        with a nonzero number of slots.
+        """
+        self.rust_env_process_interface.set_pool_state(pool_index, state)
+
+    def send_set_state_from_pool(
+        self,
+        proc_id: str,
+        pool_index: int,
+        send_state: bool = False,
+        prev_timestep_id_dict: Optional[Dict[AgentID, Optional[int]]] = None,
+        send_shared_info: bool = True,
+    ):
+        """
+        Send a SET_STATE env action to the given process, which reads the desired state from the given slot of the
+        state pool instead of receiving it in the message. The response is collected by collect_step_data.
+        """
+        self.rust_env_process_interface.send_set_state_from_pool(
+            proc_id, pool_index, send_state, prev_timestep_id_dict, send_shared_info
+        )
+
+    def set_pool_states(self, states: List[StateType], start_index: int = 0):
+        """
+        Serialize a batch of states into consecutive slots of the state pool, starting at start_index.
+        """
+        self.rust_env_process_interface.set_pool_states(states, start_index)
+
+    def send_set_states_from_pool(
+        self,
+        proc_id_pool_index_dict: Dict[str, int],
+        send_state: bool = False,
+        send_shared_info: bool = True,
+    ):
+        """
+        Send SET_STATE env actions to a group of processes, each of which reads its desired state from the slot of
+        the state pool it is assigned to. The responses are collected by collect_step_data.
+        """
+        self.rust_env_process_interface.send_set_states_from_pool(
+            proc_id_pool_index_dict, send_state, send_shared_info
+        )
+
+    def send_resync(self, proc_id: str, send_shared_info: bool = True):
+        """
+        Make the given process discard its loop state and reset its env, to realign with a process whose protocol
+        state was lost (e.g. after an error mid-exchange). The process must be waiting for a message. Its reset
+        response is collected by collect_step_data.
+        """
+        self.rust_env_process_interface.send_resync(proc_id, send_shared_info)
+
     def send_env_actions(self, env_actions: Dict[str, EnvAction]):
         """
         Send env actions to environment processes.
@@ -306,11 +540,12 @@ class EnvProcessInterface(
         """
         return self.rust_env_process_interface.collect_step_data()
 
-    def cleanup(self):
+    def cleanup(self, stop_ack_timeout: Optional[timedelta] = None):
         """
         Clean up resources and terminate processes.
+        :param stop_ack_timeout: if provided, wait up to this long for each process to acknowledge that it has closed its env and released its shared memory before joining it.
         """
-        self.rust_env_process_interface.cleanup()
+        self.rust_env_process_interface.cleanup(stop_ack_timeout)
         for _ in range(len(self.processes)):
             (process, parent_end, _, _) = self.processes.pop()
 
@@ -0,0 +1,122 @@
+"""
+Pickling support for the env process options objects, which are passed to env processes started
+with the forkserver or spawn start methods. The options objects and the option values they hold are
+Rust classes which cannot be pickled by default, so reducers are registered for them here.
+"""
+
+from __future__ import annotations
+
+import copyreg
+import inspect
+from typing import Any, Dict, Tuple
+
+from .. import rlgym_learn as rust_module
+from ..rlgym_learn import PickleablePyAnySerdeType, PyAnySerdeType
+
+OPTIONS_CLASS_NAMES = [
+    "AgentOptions",
+    "StepOptions",
+    "EpisodeOptions",
+    "ObsOptions",
+    "BufferOptions",
+    "RenderOptions",
+    "DebugOptions",
+    "SerdeOptions",
+]
+SIMPLE_ENUM_CLASS_NAMES = [
+    "ActionInterface",
+    "MacroRewardAggregation",
+    "RewardBoundsPolicy",
+    "BufferFullPolicy",
+    "ObsFormat",
+    "TorchDtype",
+]
+COMPLEX_ENUM_CLASS_NAMES = [
+    "CompressionCodec",
+    "DoneReasonSource",
+    "LegalActionMaskSource",
+    "MessageTraceMode",
+    "OutputCapture",
+    "RenderSync",
+]
+
+
+def _attribute_names(cls) -> list[str]:
+    return [
+        name
+        for name, attr in vars(cls).items()
+        if inspect.isgetsetdescriptor(attr) and not name.startswith("_")
+    ]
+
+
+def _pickleable_value(value):
+    # Serde types can only be pickled through their pickleable wrapper, which the env process
+    # accepts in their place
+    if isinstance(value, PyAnySerdeType):
+        return PickleablePyAnySerdeType(value)
+    if isinstance(value, dict):
+        return {k: _pickleable_value(v) for k, v in value.items()}
+    return value
+
+
+def rebuild_options(class_name: str, state: Dict[str, Any]):
+    options = getattr(rust_module, class_name)()
+    for name, value in state.items():
+        setattr(options, name, value)
+    return options
+
+
+def rebuild_simple_enum(class_name: str, member_name: str):
+    return getattr(getattr(rust_module, class_name), member_name)
+
+
+def rebuild_complex_enum(class_name: str, variant_name: str, args: Tuple):
+    return getattr(getattr(rust_module, class_name), variant_name)(*args)
+
+
+def rebuild_memory_watch_config(state: Dict[str, Any]):
+    return rust_module.MemoryWatchConfig(**state)
+
+
+def _reduce_options(options):
+    cls = type(options)
+    state = {
+        name: _pickleable_value(getattr(options, name))
+        for name in _attribute_names(cls)
+    }
+    return rebuild_options, (cls.__name__, state)
+
+
+def _reduce_simple_enum(member):
+    class_name, member_name = repr(member).split(".")
+    return rebuild_simple_enum, (class_name, member_name)
+
+
+def _make_reduce_complex_enum(class_name: str, variant_name: str):
+    def reduce_complex_enum(variant):
+        args = tuple(getattr(variant, name) for name in type(variant).__match_args__)
+        return rebuild_complex_enum, (class_name, variant_name, args)
+
+    return reduce_complex_enum
+
+
+def _reduce_memory_watch_config(config):
+    state = {
+        name: getattr(config, name)
+        for name in _attribute_names(rust_module.MemoryWatchConfig)
+    }
+    return rebuild_memory_watch_config, (state,)
+
+
+for class_name in OPTIONS_CLASS_NAMES:
+    copyreg.pickle(getattr(rust_module, class_name), _reduce_options)
+for class_name in SIMPLE_ENUM_CLASS_NAMES:
+    copyreg.pickle(getattr(rust_module, class_name), _reduce_simple_enum)
+for class_name in COMPLEX_ENUM_CLASS_NAMES:
+    enum_class = getattr(rust_module, class_name)
+    for variant_name, variant_class in vars(enum_class).items():
+        if inspect.isclass(variant_class) and issubclass(variant_class, enum_class):
+            copyreg.pickle(
+                variant_class, _make_reduce_complex_enum(class_name, variant_name)
+            )
+copyreg.pickle(rust_module.MemoryWatchConfig, _reduce_memory_watch_config)
@@ -12,6 +12,7 @@ from typing import (
     Callable,
     Dict,
     Generic,
+    Iterable,
     List,
     Optional,
     Set,
@@ -46,21 +47,266 @@ from rlgym.rocket_league.api import Car, GameConfig, GameState, PhysicsObject
 
 from rlgym_learn.api import ActionAssociatedLearningData, AgentController
 
-class EnvAction: ...
+class EnvAction:
+    @staticmethod
+    def step_from_iter(
+        shared_info_setter_option: Optional[Dict[str, Any]],
+        send_state: bool,
+        action_iter: Iterable[ActionType],
+        n_agents: int,
+        action_associated_learning_data: Any,
+        send_shared_info: bool = True,
+        send_obs: bool = True,
+    ) -> EnvAction: ...
 
 class EnvActionResponseType:
     STEP = ...
     RESET = ...
     SET_STATE = ...
 
+class MacroRewardAggregation:
+    SUM = ...
+    LAST = ...
+    MEAN = ...
+    MAX = ...
+
+class ActionInterface:
+    DICT = ...
+    STACKED_TENSOR = ...
+
+class RewardBoundsPolicy:
+    ERROR = ...
+    CLAMP = ...
+
+class BufferFullPolicy:
+    ERROR = ...
+    TRUNCATE = ...
+
+class TorchDtype:
+    BFLOAT16 = ...
+    FLOAT16 = ...
+    FLOAT64 = ...
+
+class ObsFormat:
+    SERDE = ...
+    ARROW_IPC = ...
+    FIXED_LAYOUT = ...
+
+class MemoryWatchConfig:
+    interval_steps: int
+    rss_threshold_bytes_option: Optional[int]
+    growth_window: int
+    def __new__(
+        cls,
+        interval_steps: int = 1000,
+        rss_threshold_bytes_option: Optional[int] = None,
+        growth_window: int = 10,
+    ) -> MemoryWatchConfig: ...
+
+class ActionSpaceKind:
+    DISCRETE = ...
+    MULTI_DISCRETE = ...
+    BOX = ...
+    UNKNOWN = ...
+
+class ActionSpaceDescriptor:
+    kind: ActionSpaceKind
+    nvec_option: Optional[List[int]]
+    low_option: Optional[List[float]]
+    high_option: Optional[List[float]]
+
+class BufferLayoutConfig:
+    recalculate_agent_id_every_step: bool
+    has_shared_info_serde: bool
+    has_state_serde: bool
+    stable_agent_indices: bool
+    episode_boundary_reasons: bool
+    compressed_shared_info: bool
+    has_reward_component_serde: bool
+    has_critic_obs_serde: bool
+    obs_in_file: bool
+    done_reasons: bool
+    bootstrap_flags: bool
+    dedup_set_state_obs: bool
+    reuse_identical_obs: bool
+    arrow_obs: bool
+    fixed_layout_obs: bool
+    cuda_ipc_obs: bool
+    global_obs: bool
+    legal_action_masks: bool
+    state_hashes: bool
+    env_step_counts: bool
+    chunked_obs: bool
+    prev_actions: bool
+    packed_done_flags: bool
+    region_alignment: Optional[int]
+    include_counters: bool
+    new_agent_flags: bool
+    rng_states: bool
+    def __new__(cls) -> BufferLayoutConfig: ...
+
+class AgentOptions:
+    sort_agent_ids: bool
+    agent_id_sort_key_fn_option: Optional[Callable[[AgentID], Any]]
+    stable_agent_indices: bool
+    agent_order_permutation_option: Optional[List[int]]
+    expected_n_agents_option: Optional[int]
+    strict_agent_keys: bool
+    check_step_dict_keys: bool
+    def __new__(cls) -> AgentOptions: ...
+
+class StepOptions:
+    action_interface: ActionInterface
+    default_action_option: Optional[ActionType]
+    default_action_on_first_step: bool
+    pre_step_fn_option: Optional[Callable[[Any], Any]]
+    macro_reward_aggregation_option: Optional[MacroRewardAggregation]
+    frame_skip_option: Optional[int]
+    frame_skip_reward_aggregation: MacroRewardAggregation
+    action_repeat_option: Optional[int]
+    reward_bounds_option: Optional[Tuple[float, float]]
+    reward_bounds_policy: RewardBoundsPolicy
+    shared_info_setter_keys_option: Optional[List[str]]
+    initial_shared_info_setter_bytes_option: Optional[bytes]
+    max_message_rate_hz_option: Optional[float]
+    def __new__(cls) -> StepOptions: ...
+
+class EpisodeOptions:
+    max_episode_duration_option: Optional[timedelta]
+    agent_max_steps_dict_option: Optional[Dict[AgentID, int]]
+    done_reason_source_option: Optional[DoneReasonSource]
+    reset_settle_steps_option: Optional[int]
+    reset_loop_threshold_option: Optional[int]
+    reset_loop_errors: bool
+    continue_from_final_state: bool
+    keepalive_idle_threshold_option: Optional[timedelta]
+    keepalive_fn_option: Optional[Callable[[Any], Any]]
+    def __new__(cls) -> EpisodeOptions: ...
+
+class ObsOptions:
+    obs_transform_fn_option: Optional[Callable[[ObsType], ObsType]]
+    obs_dtype_cast_option: Optional[TorchDtype]
+    obs_force_f64: bool
+    frame_stack_option: Optional[int]
+    obs_format: ObsFormat
+    cuda_ipc_obs: bool
+    fixed_layout_obs_shape_option: Optional[List[int]]
+    obs_file_dir_option: Optional[str]
+    obs_chunk_size_option: Optional[int]
+    reuse_identical_obs: bool
+    dedup_set_state_obs: bool
+    global_obs_fn_option: Optional[Callable[[Dict[AgentID, ObsType]], Any]]
+    legal_action_mask_source_option: Optional[LegalActionMaskSource]
+    def __new__(cls) -> ObsOptions: ...
+
+class BufferOptions:
+    episode_boundary_reasons: bool
+    bootstrap_flags: bool
+    state_hashes: bool
+    env_step_counts: bool
+    prev_actions: bool
+    packed_done_flags: bool
+    include_counters: bool
+    new_agent_flags: bool
+    per_agent_bytes_option: Optional[int]
+    overhead_bytes: int
+    region_alignment_option: Optional[int]
+    buffer_full_policy: BufferFullPolicy
+    layout_schema_path_option: Optional[str]
+    mqueue_max_message_bytes_option: Optional[int]
+    partition_bytes_option: Optional[int]
+    n_partitions: int
+    base_offset_option: Optional[int]
+    def __new__(cls) -> BufferOptions: ...
+
+class RenderOptions:
+    render_sync: RenderSync
+    render_every_option: Optional[int]
+    render_predicate_fn_option: Optional[Callable[[Any], bool]]
+    render_errors_fatal: bool
+    render_thread: bool
+    def __new__(cls) -> RenderOptions: ...
+
+class DebugOptions:
+    output_capture: OutputCapture
+    strict_protocol: bool
+    sync_selfcheck: bool
+    slow_step_threshold_option: Optional[timedelta]
+    buffer_dump_dir_option: Optional[str]
+    message_trace_mode_option: Optional[MessageTraceMode]
+    memory_watch_config_option: Optional[MemoryWatchConfig]
+    action_history_len_option: Optional[int]
+    def __new__(cls) -> DebugOptions: ...
+
+class SerdeOptions:
+    reward_component_serde_option: Optional[PyAnySerdeType[Dict[str, Any]]]
+    critic_obs_serde_option: Optional[PyAnySerdeType[ObsType]]
+    global_obs_serde_option: Optional[PyAnySerdeType]
+    legal_action_mask_serde_option: Optional[PyAnySerdeType]
+    agent_obs_serdes_option: Optional[Dict[AgentID, PyAnySerdeType[ObsType]]]
+    rng_state_serde_option: Optional[PyAnySerdeType]
+    serde_fallback_fn_option: Optional[Callable[[Any], Any]]
+    shared_info_compression_option: Optional[CompressionCodec]
+    def __new__(cls) -> SerdeOptions: ...
+
+class EpisodeBoundaryReason:
+    EXPLICIT_RESET = ...
+    SET_STATE = ...
+    TERMINATED_AUTORESET = ...
+    TRUNCATED_AUTORESET = ...
+    KEEPALIVE_RESET = ...
+
+class DoneReasonSource:
+    ATTRIBUTE = DoneReasonSource_ATTRIBUTE
+    SHARED_INFO = DoneReasonSource_SHARED_INFO
+
+class DoneReasonSource_ATTRIBUTE(DoneReasonSource):
+    name: str
+    def __new__(cls, name: str) -> DoneReasonSource_ATTRIBUTE: ...
+
+class DoneReasonSource_SHARED_INFO(DoneReasonSource):
+    key: str
+    def __new__(cls, key: str) -> DoneReasonSource_SHARED_INFO: ...
+
+class LegalActionMaskSource:
+    ATTRIBUTE = LegalActionMaskSource_ATTRIBUTE
+    SHARED_INFO = LegalActionMaskSource_SHARED_INFO
+
+class LegalActionMaskSource_ATTRIBUTE(LegalActionMaskSource):
+    name: str
+    def __new__(cls, name: str) -> LegalActionMaskSource_ATTRIBUTE: ...
+
+class LegalActionMaskSource_SHARED_INFO(LegalActionMaskSource):
+    key: str
+    def __new__(cls, key: str) -> LegalActionMaskSource_SHARED_INFO: ...
+
+class MessageTraceMode:
+    RECORD = MessageTraceMode_RECORD
+    REPLAY = MessageTraceMode_REPLAY
+
+class MessageTraceMode_RECORD(MessageTraceMode):
+    path: str
+    def __new__(cls, path: str) -> MessageTraceMode_RECORD: ...
+
+class MessageTraceMode_REPLAY(MessageTraceMode):
+    path: str
+    def __new__(cls, path: str) -> MessageTraceMode_REPLAY: ...
+
 class EnvActionResponse_STEP:
     def __new__(
-        cls, shared_info_setter: Optional[Dict[str, Any]] = None, send_state=False
+        cls,
+        shared_info_setter: Optional[Dict[str, Any]] = None,
+        send_state=False,
+        send_shared_info=True,
+        send_obs=True,
     ) -> EnvActionResponse_STEP: ...
 
 class EnvActionResponse_RESET:
     def __new__(
-        cls, shared_info_setter: Optional[Dict[str, Any]] = None, send_state=False
+        cls,
+        shared_info_setter: Optional[Dict[str, Any]] = None,
+        send_state=False,
+        send_shared_info=True,
     ) -> EnvActionResponse_RESET: ...
 
 class EnvActionResponse_SET_STATE(Generic[AgentID, StateType]):
@@ -70,6 +316,8 @@ class EnvActionResponse_SET_STATE(Generic[AgentID, StateType]):
         shared_info_setter: Optional[Dict[str, Any]] = None,
         send_state=False,
         prev_timestep_id_dict: Optional[Dict[AgentID, Optional[int]]] = None,
+        send_shared_info=True,
+        rng_state: Optional[Any] = None,
     ) -> EnvActionResponse_SET_STATE[AgentID, StateType]: ...
 
 class EnvActionResponse(Generic[AgentID, StateType]):
@@ -81,6 +329,48 @@ class EnvActionResponse(Generic[AgentID, StateType]):
     def desired_state(self) -> Optional[StateType]: ...
     def prev_timestep_id_dict(self) -> Optional[Dict[AgentID, Optional[int]]]: ...
 
+class OutputCapture:
+    OFF = OutputCapture_OFF
+    PREFIX = OutputCapture_PREFIX
+    FILE = OutputCapture_FILE
+
+class OutputCapture_OFF(OutputCapture):
+    def __new__(cls) -> OutputCapture_OFF: ...
+
+class OutputCapture_PREFIX(OutputCapture):
+    def __new__(cls) -> OutputCapture_PREFIX: ...
+
+class OutputCapture_FILE(OutputCapture):
+    def __new__(cls, folder: str) -> OutputCapture_FILE: ...
+
+class RenderSync:
+    OFF = RenderSync_OFF
+    LEADER = RenderSync_LEADER
+    FOLLOWER = RenderSync_FOLLOWER
+
+class RenderSync_OFF(RenderSync):
+    def __new__(cls) -> RenderSync_OFF: ...
+
+class RenderSync_LEADER(RenderSync):
+    def __new__(cls) -> RenderSync_LEADER: ...
+
+class RenderSync_FOLLOWER(RenderSync):
+    def __new__(cls) -> RenderSync_FOLLOWER: ...
+
+class CompressionCodec:
+    ZLIB = CompressionCodec_ZLIB
+    LZMA = CompressionCodec_LZMA
+    BZ2 = CompressionCodec_BZ2
+
+class CompressionCodec_ZLIB(CompressionCodec):
+    def __new__(cls, level: int = 6) -> CompressionCodec_ZLIB: ...
+
+class CompressionCodec_LZMA(CompressionCodec):
+    def __new__(cls) -> CompressionCodec_LZMA: ...
+
+class CompressionCodec_BZ2(CompressionCodec):
+    def __new__(cls) -> CompressionCodec_BZ2: ...
+
 class EnvProcessInterface(
     Generic[
         AgentID,
@@ -106,6 +396,13 @@ class EnvProcessInterface(
         recalculate_agent_id_every_step: bool,
         flinks_folder_option: str,
         min_process_steps_per_inference: int,
+        state_pool_slots: int = 0,
+        state_pool_slot_bytes: int = 0,
+        agent_options: Optional[AgentOptions] = None,
+        episode_options: Optional[EpisodeOptions] = None,
+        obs_options: Optional[ObsOptions] = None,
+        buffer_options: Optional[BufferOptions] = None,
+        serde_options: Optional[SerdeOptions] = None,
     ) -> EnvProcessInterface: ...
     def init_processes(
         self, proc_package_defs: List[Process, socket, _RetAddress, str]
@@ -119,7 +416,40 @@ class EnvProcessInterface(
     def delete_process(self): ...
     def increase_min_process_steps_per_inference(self) -> int: ...
     def decrease_min_process_steps_per_inference(self) -> int: ...
-    def cleanup(self): ...
+    def cleanup(self, stop_ack_timeout_option: Optional[timedelta] = None): ...
+    def episode_boundary_reason(
+        self, proc_id: str
+    ) -> Optional[EpisodeBoundaryReason]: ...
+    def legal_action_masks(self, proc_id: str) -> Optional[List[Any]]: ...
+    def prev_actions(self, proc_id: str) -> Optional[List[Optional[ActionType]]]: ...
+    def counters(self, proc_id: str) -> Optional[Tuple[int, int]]: ...
+    def rng_state(self, proc_id: str) -> Optional[Any]: ...
+    def new_agent_flags(self, proc_id: str) -> Optional[List[bool]]: ...
+    def agent_indices(self, proc_id: str) -> Optional[List[int]]: ...
+    def partition_base_offset(self, pid_idx: int) -> Optional[int]: ...
+    def set_seed(self, proc_id: str, seed: int): ...
+    def warm_start(self, proc_id: str, warm_start_steps: int): ...
+    def dump_state(self, proc_id: str, path: str): ...
+    def dump_buffer(self, proc_id: str): ...
+    def action_space_descriptor(self) -> Optional[ActionSpaceDescriptor]: ...
+    def reconfigure(
+        self,
+        obs_serde_type_option: Optional[PyAnySerdeType[ObsType]] = None,
+        action_serde_type_option: Optional[PyAnySerdeType[ActionType]] = None,
+        reward_serde_type_option: Optional[PyAnySerdeType[RewardType]] = None,
+    ): ...
+    def action_history(
+        self, proc_id: str
+    ) -> List[List[Tuple[AgentID, ActionType, RewardType]]]: ...
+    def env_config(self, proc_id: str) -> Optional[Any]: ...
+    def set_state_batch(
+        self, proc_id: str, states: List[StateType]
+    ) -> List[Tuple[List[AgentID], List[ObsType]]]: ...
+    def sample_actions(self, proc_id: str) -> List[ActionType]: ...
+    def observe(self, proc_id: str) -> List[ObsType]: ...
+    def agent_count(
+        self, proc_id: str, include_agent_ids: bool = False
+    ) -> Tuple[int, Optional[List[AgentID]]]: ...
     def collect_step_data(
         self,
     ) -> Tuple[
@@ -144,6 +474,23 @@ class EnvProcessInterface(
             ],
         ],
     ]: ...
+    def set_pool_state(self, pool_index: int, state: StateType): ...
+    def send_set_state_from_pool(
+        self,
+        proc_id: str,
+        pool_index: int,
+        send_state: bool = False,
+        prev_timestep_id_dict_option: Optional[Dict[AgentID, Optional[int]]] = None,
+        send_shared_info: bool = True,
+    ): ...
+    def set_pool_states(self, states: List[StateType], start_index: int = 0): ...
+    def send_set_states_from_pool(
+        self,
+        proc_id_pool_index_dict: Dict[str, int],
+        send_state: bool = False,
+        send_shared_info: bool = True,
+    ): ...
+    def send_resync(self, proc_id: str, send_shared_info: bool = True): ...
     def send_env_actions(self, env_actions: Dict[str, EnvAction]): ...
 
 class AgentManager(
@@ -182,18 +529,20 @@ def env_process(
     proc_id: str,
     child_end,
     parent_sockname,
-    build_env_fn: Callable[
-        [],
-        RLGym[
-            AgentID,
-            ObsType,
-            ActionType,
-            EngineActionType,
-            RewardType,
-            StateType,
-            ObsSpaceType,
-            ActionSpaceType,
-        ],
+    build_env_fn: Optional[
+        Callable[
+            [],
+            RLGym[
+                AgentID,
+                ObsType,
+                ActionType,
+                EngineActionType,
+                RewardType,
+                StateType,
+                ObsSpaceType,
+                ActionSpaceType,
+            ],
+        ]
     ],
     flinks_folder: str,
     shm_buffer_size: int,
@@ -204,9 +553,31 @@ def env_process(
     obs_space_serde: PyAnySerdeType[ObsSpaceType],
     action_space_serde: PyAnySerdeType[ActionSpaceType],
     shared_info_serde_option: Optional[PyAnySerdeType[Dict[str, Any]]],
-    render: bool,
-    render_delay_option: Optional[timedelta],
-    recalculate_agent_id_every_step: bool,
+    shared_info_setter_serde_option: Optional[PyAnySerdeType[Dict[str, Any]]],
+    state_serde_option: Optional[PyAnySerdeType[StateType]],
+    render: bool = False,
+    render_delay_option: Optional[timedelta] = None,
+    recalculate_agent_id_every_step: bool = False,
+    env_option: Optional[
+        RLGym[
+            AgentID,
+            ObsType,
+            ActionType,
+            EngineActionType,
+            RewardType,
+            StateType,
+            ObsSpaceType,
+            ActionSpaceType,
+        ]
+    ] = None,
+    agent_options: Optional[AgentOptions] = None,
+    step_options: Optional[StepOptions] = None,
+    episode_options: Optional[EpisodeOptions] = None,
+    obs_options: Optional[ObsOptions] = None,
+    buffer_options: Optional[BufferOptions] = None,
+    render_options: Optional[RenderOptions] = None,
+    debug_options: Optional[DebugOptions] = None,
+    serde_options: Optional[SerdeOptions] = None,
 ): ...
 def recvfrom_byte(socket: socket): ...
 def sendto_byte(socket: socket, address: _RetAddress): ...
@@ -215,6 +586,38 @@ T = TypeVar("T")
 KeysT = TypeVar("KeysT")
 ValuesT = TypeVar("ValuesT")
 
+def roundtrip_check(serde: PyAnySerdeType[T], obj: T) -> Tuple[bool, int]: ...
+def run_env_steps(
+    build_env_fn: Callable[
+        [],
+        RLGym[
+            AgentID,
+            ObsType,
+            ActionType,
+            EngineActionType,
+            RewardType,
+            StateType,
+            ObsSpaceType,
+            ActionSpaceType,
+        ],
+    ],
+    actions_per_step_list: List[List[ActionType]],
+    shared_info_setter_per_step_list_option: Optional[List[Optional[Dict[str, Any]]]] = None,
+    recalculate_agent_id_every_step: bool = False,
+    sort_agent_ids: bool = False,
+    agent_id_sort_key_fn_option: Optional[Callable[[AgentID], Any]] = None,
+    shared_info_setter_keys_option: Optional[List[str]] = None,
+) -> Tuple[
+    List[AgentID],
+    List[ObsType],
+    List[
+        Tuple[
+            List[AgentID], List[ObsType], List[RewardType], List[bool], List[bool]
+        ]
+    ],
+]: ...
+def buffer_layout(config: BufferLayoutConfig) -> Dict[str, List[Dict[str, Any]]]: ...
+
 class PythonSerde(Generic[T]):
     @abstractmethod
     def append(self, buf: bytes, offset: int, obj: T) -> int:
@@ -423,6 +826,16 @@ class Timestep(Generic[AgentID, ObsType, ActionType, RewardType]):
         "reward",
         "terminated",
         "truncated",
+        "reward_components",
+        "critic_obs",
+        "next_critic_obs",
+        "done_reason",
+        "should_bootstrap",
+        "global_obs",
+        "next_global_obs",
+        "state_hash",
+        "next_state_hash",
+        "n_env_steps",
     )
     env_id: str
     timestep_id: int
@@ -434,6 +847,16 @@ class Timestep(Generic[AgentID, ObsType, ActionType, RewardType]):
     reward: RewardType
     terminated: bool
     truncated: bool
+    reward_components: Optional[Dict[str, Any]]
+    critic_obs: Optional[Any]
+    next_critic_obs: Optional[Any]
+    done_reason: Optional[int]
+    should_bootstrap: Optional[bool]
+    global_obs: Optional[Any]
+    next_global_obs: Optional[Any]
+    state_hash: Optional[int]
+    next_state_hash: Optional[int]
+    n_env_steps: Optional[int]
     def __new__(
         env_id: str,
         timestep_id: int,
@@ -445,4 +868,14 @@ class Timestep(Generic[AgentID, ObsType, ActionType, RewardType]):
         reward: RewardType,
         terminated: bool,
         truncated: bool,
+        reward_components: Optional[Dict[str, Any]] = None,
+        critic_obs: Optional[Any] = None,
+        next_critic_obs: Optional[Any] = None,
+        done_reason: Optional[int] = None,
+        should_bootstrap: Optional[bool] = None,
+        global_obs: Optional[Any] = None,
+        next_global_obs: Optional[Any] = None,
+        state_hash: Optional[int] = None,
+        next_state_hash: Optional[int] = None,
+        n_env_steps: Optional[int] = None,
     ) -> Timestep[AgentID, ObsType, ActionType, RewardType]: ...
@@ -0,0 +1,258 @@
+use std::mem::size_of;
+
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use pyo3::{intern, PyTypeInfo};
+
+use crate::communication::{append_usize_le, retrieve_slice, retrieve_u8, retrieve_usize_le};
+
+// The kind of action space, as far as it can be determined from the space's attributes
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActionSpaceKind {
+    DISCRETE,
+    MULTI_DISCRETE,
+    BOX,
+    UNKNOWN,
+}
+
+// A normalized description of an action space which the parent can use to configure policy output
+// heads without parsing the space itself. Discrete spaces are described as a multi-discrete space
+// with a single dimension.
+#[pyclass(module = "rlgym_learn", get_all)]
+#[derive(Clone, Debug)]
+pub struct ActionSpaceDescriptor {
+    pub kind: ActionSpaceKind,
+    // The number of options for each dimension of a (multi-)discrete space
+    pub nvec_option: Option<Vec<usize>>,
+    // The flattened bounds of a box space
+    pub low_option: Option<Vec<f64>>,
+    pub high_option: Option<Vec<f64>>,
+}
+
+impl ActionSpaceDescriptor {
+    fn unknown() -> Self {
+        ActionSpaceDescriptor {
+            kind: ActionSpaceKind::UNKNOWN,
+            nvec_option: None,
+            low_option: None,
+            high_option: None,
+        }
+    }
+
+    // Introspects gym-style spaces (with nvec, n, or low and high attributes) and rlgym-style
+    // ("discrete", n) tuples. Any other space, or any space whose attributes don't have the
+    // expected types, is described as UNKNOWN.
+    pub fn from_action_space(action_space: &Bound<'_, PyAny>) -> Self {
+        Self::try_from_action_space(action_space)
+            .ok()
+            .flatten()
+            .unwrap_or_else(Self::unknown)
+    }
+
+    fn try_from_action_space(action_space: &Bound<'_, PyAny>) -> PyResult<Option<Self>> {
+        let py = action_space.py();
+        if action_space.hasattr(intern!(py, "nvec"))? {
+            return Ok(Some(ActionSpaceDescriptor {
+                kind: ActionSpaceKind::MULTI_DISCRETE,
+                nvec_option: Some(extract_flat_list(
+                    &action_space.getattr(intern!(py, "nvec"))?,
+                )?),
+                low_option: None,
+                high_option: None,
+            }));
+        }
+        if action_space.hasattr(intern!(py, "n"))? {
+            return Ok(Some(Self::discrete(
+                action_space.getattr(intern!(py, "n"))?.extract()?,
+            )));
+        }
+        if action_space.hasattr(intern!(py, "low"))? && action_space.hasattr(intern!(py, "high"))? {
+            return Ok(Some(ActionSpaceDescriptor {
+                kind: ActionSpaceKind::BOX,
+                nvec_option: None,
+                low_option: Some(extract_flat_list(
+                    &action_space.getattr(intern!(py, "low"))?,
+                )?),
+                high_option: Some(extract_flat_list(
+                    &action_space.getattr(intern!(py, "high"))?,
+                )?),
+            }));
+        }
+        if PyTuple::is_type_of(action_space) && action_space.len()? == 2 {
+            let space_type = action_space.get_item(0)?.extract::<String>()?;
+            if space_type.eq_ignore_ascii_case("discrete") {
+                return Ok(Some(Self::discrete(action_space.get_item(1)?.extract()?)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn discrete(n: usize) -> Self {
+        ActionSpaceDescriptor {
+            kind: ActionSpaceKind::DISCRETE,
+            nvec_option: Some(vec![n]),
+            low_option: None,
+            high_option: None,
+        }
+    }
+}
+
+// Extracts a list from either a sequence or a numpy array of any shape, which is flattened
+fn extract_flat_list<'py, T: FromPyObject<'py>>(obj: &Bound<'py, PyAny>) -> PyResult<Vec<T>> {
+    let py = obj.py();
+    if obj.hasattr(intern!(py, "flatten"))? {
+        obj.call_method0(intern!(py, "flatten"))?
+            .call_method0(intern!(py, "tolist"))?
+            .extract()
+    } else {
+        obj.extract()
+    }
+}
+
+fn append_f64_list(buf: &mut [u8], offset: usize, values: &[f64]) -> usize {
+    let mut offset = append_usize_le(buf, offset, values.len());
+    for value in values.iter() {
+        let end = offset + size_of::<f64>();
+        buf[offset..end].copy_from_slice(&value.to_le_bytes());
+        offset = end;
+    }
+    offset
+}
+
+fn retrieve_f64_list(buf: &[u8], offset: usize) -> PyResult<(Vec<f64>, usize)> {
+    let (len, mut offset) = retrieve_usize_le(buf, offset)?;
+    let mut values = Vec::with_capacity(len);
+    for _ in 0..len {
+        let bytes = retrieve_slice(buf, offset, size_of::<f64>(), "f64")?;
+        values.push(f64::from_le_bytes(bytes.try_into()?));
+        offset += size_of::<f64>();
+    }
+    Ok((values, offset))
+}
+
+// Encoded as a kind byte followed by the nvec of (multi-)discrete spaces or the low and high bounds
+// of box spaces, each as a length prefixed list
+pub fn append_action_space_descriptor(
+    buf: &mut [u8],
+    offset: usize,
+    descriptor: &ActionSpaceDescriptor,
+) -> usize {
+    buf[offset] = match descriptor.kind {
+        ActionSpaceKind::DISCRETE => 0,
+        ActionSpaceKind::MULTI_DISCRETE => 1,
+        ActionSpaceKind::BOX => 2,
+        ActionSpaceKind::UNKNOWN => 3,
+    };
+    let mut offset = offset + 1;
+    match descriptor.kind {
+        ActionSpaceKind::DISCRETE | ActionSpaceKind::MULTI_DISCRETE => {
+            let nvec = descriptor.nvec_option.as_ref().unwrap();
+            offset = append_usize_le(buf, offset, nvec.len());
+            for &n in nvec.iter() {
+                offset = append_usize_le(buf, offset, n);
+            }
+        }
+        ActionSpaceKind::BOX => {
+            offset = append_f64_list(buf, offset, descriptor.low_option.as_ref().unwrap());
+            offset = append_f64_list(buf, offset, descriptor.high_option.as_ref().unwrap());
+        }
+        ActionSpaceKind::UNKNOWN => (),
+    }
+    offset
+}
+
+pub fn retrieve_action_space_descriptor(
+    buf: &[u8],
+    offset: usize,
+) -> PyResult<(ActionSpaceDescriptor, usize)> {
+    let (kind_byte, mut offset) = retrieve_u8(buf, offset)?;
+    let kind = match kind_byte {
+        0 => ActionSpaceKind::DISCRETE,
+        1 => ActionSpaceKind::MULTI_DISCRETE,
+        2 => ActionSpaceKind::BOX,
+        3 => ActionSpaceKind::UNKNOWN,
+        v => Err(InvalidStateError::new_err(format!(
+            "tried to deserialize ActionSpaceKind but got {}",
+            v
+        )))?,
+    };
+    let mut descriptor = ActionSpaceDescriptor::unknown();
+    match kind {
+        ActionSpaceKind::DISCRETE | ActionSpaceKind::MULTI_DISCRETE => {
+            let len;
+            (len, offset) = retrieve_usize_le(buf, offset)?;
+            let mut nvec = Vec::with_capacity(len);
+            for _ in 0..len {
+                let n;
+                (n, offset) = retrieve_usize_le(buf, offset)?;
+                nvec.push(n);
+            }
+            descriptor.nvec_option = Some(nvec);
+        }
+        ActionSpaceKind::BOX => {
+            let (low, high);
+            (low, offset) = retrieve_f64_list(buf, offset)?;
+            (high, offset) = retrieve_f64_list(buf, offset)?;
+            descriptor.low_option = Some(low);
+            descriptor.high_option = Some(high);
+        }
+        ActionSpaceKind::UNKNOWN => (),
+    }
+    descriptor.kind = kind;
+    Ok((descriptor, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(descriptor: &ActionSpaceDescriptor) -> ActionSpaceDescriptor {
+        let mut buf = vec![0; 256];
+        let end = append_action_space_descriptor(&mut buf, 3, descriptor);
+        let (retrieved, offset) = retrieve_action_space_descriptor(&buf, 3).unwrap();
+        assert_eq!(offset, end);
+        if descriptor.kind != ActionSpaceKind::UNKNOWN {
+            assert!(retrieve_action_space_descriptor(&buf[..end - 1], 3).is_err());
+        }
+        retrieved
+    }
+
+    #[test]
+    fn descriptor_round_trip() {
+        let retrieved = round_trip(&ActionSpaceDescriptor::discrete(5));
+        assert_eq!(retrieved.kind, ActionSpaceKind::DISCRETE);
+        assert_eq!(retrieved.nvec_option, Some(vec![5]));
+
+        let retrieved = round_trip(&ActionSpaceDescriptor {
+            kind: ActionSpaceKind::MULTI_DISCRETE,
+            nvec_option: Some(vec![3, 2, 0]),
+            low_option: None,
+            high_option: None,
+        });
+        assert_eq!(retrieved.kind, ActionSpaceKind::MULTI_DISCRETE);
+        assert_eq!(retrieved.nvec_option, Some(vec![3, 2, 0]));
+
+        let retrieved = round_trip(&ActionSpaceDescriptor {
+            kind: ActionSpaceKind::BOX,
+            nvec_option: None,
+            low_option: Some(vec![-1.0, f64::NEG_INFINITY]),
+            high_option: Some(vec![1.5, f64::INFINITY]),
+        });
+        assert_eq!(retrieved.kind, ActionSpaceKind::BOX);
+        assert_eq!(retrieved.low_option, Some(vec![-1.0, f64::NEG_INFINITY]));
+        assert_eq!(retrieved.high_option, Some(vec![1.5, f64::INFINITY]));
+
+        let retrieved = round_trip(&ActionSpaceDescriptor::unknown());
+        assert_eq!(retrieved.kind, ActionSpaceKind::UNKNOWN);
+        assert_eq!(retrieved.nvec_option, None);
+    }
+
+    #[test]
+    fn invalid_kind_is_error() {
+        assert!(retrieve_action_space_descriptor(&[4], 0).is_err());
+        assert!(retrieve_action_space_descriptor(&[], 0).is_err());
+    }
+}
@@ -265,6 +265,8 @@ impl AgentManager {
                     EnvActionResponse::STEP {
                         shared_info_setter,
                         send_state,
+                        send_shared_info,
+                        send_obs,
                     } => {
                         should_get_actions = true;
                         let Some((env_agent_id_list, env_obs_list)) =
@@ -278,6 +280,8 @@ impl AgentManager {
                             env_id,
                             shared_info_setter,
                             send_state,
+                            send_shared_info,
+                            send_obs,
                             total_len,
                             total_len + env_agent_id_list.len(),
                         ));
@@ -288,11 +292,13 @@ impl AgentManager {
                     EnvActionResponse::RESET {
                         shared_info_setter,
                         send_state,
+                        send_shared_info,
                     } => env_actions.push((
                         env_id,
                         EnvAction::RESET {
                             shared_info_setter_option: shared_info_setter,
                             send_state,
+                            send_shared_info,
                         },
                     )),
                     EnvActionResponse::SET_STATE {
@@ -300,6 +306,8 @@ impl AgentManager {
                         shared_info_setter,
                         send_state,
                         prev_timestep_id_dict,
+                        send_shared_info,
+                        rng_state,
                     } => env_actions.push((
                         env_id,
                         EnvAction::SET_STATE {
@@ -307,6 +315,8 @@ impl AgentManager {
                             shared_info_setter_option: shared_info_setter,
                             send_state,
                             prev_timestep_id_dict_option: prev_timestep_id_dict,
+                            send_shared_info,
+                            rng_state_option: rng_state,
                         },
                     )),
                 };
@@ -316,8 +326,15 @@ impl AgentManager {
                 let obs_list = env_obs_list_list.into_iter().flatten().collect_vec();
                 let (action_list, action_associated_learning_data) =
                     self.get_actions(py, agent_id_list, obs_list)?;
-                for (env_id, shared_info_setter_option, send_state, start, stop) in
-                    env_id_list_range_list.into_iter()
+                for (
+                    env_id,
+                    shared_info_setter_option,
+                    send_state,
+                    send_shared_info,
+                    send_obs,
+                    start,
+                    stop,
+                ) in env_id_list_range_list.into_iter()
                 {
                     env_actions.push((
                         env_id,
@@ -334,6 +351,8 @@ impl AgentManager {
                                     list[start..stop].into_py_any(py)?
                                 }
                             },
+                            send_shared_info,
+                            send_obs,
                         },
                     ))
                 }
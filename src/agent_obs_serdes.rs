@@ -0,0 +1,46 @@
+use pyany_serde::PyAnySerde;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+// Obs serdes selected by agent id, for envs where the obs of different agents are structurally
+// different. Agents without an entry use the obs serde. The env process and its parent must be
+// given the same mapping.
+pub struct AgentObsSerdes {
+    // Maps each agent id to its index in serde_list
+    agent_id_idx_dict: Py<PyDict>,
+    serde_list: Vec<Box<dyn PyAnySerde>>,
+}
+
+impl AgentObsSerdes {
+    pub fn new<'py>(agent_obs_serde_dict: &Bound<'py, PyDict>) -> PyResult<Self> {
+        let py = agent_obs_serde_dict.py();
+        let agent_id_idx_dict = PyDict::new(py);
+        let mut serde_list = Vec::with_capacity(agent_obs_serde_dict.len());
+        for (agent_id, serde) in agent_obs_serde_dict.iter() {
+            agent_id_idx_dict.set_item(agent_id, serde_list.len())?;
+            serde_list.push(serde.extract::<Box<dyn PyAnySerde>>()?);
+        }
+        Ok(AgentObsSerdes {
+            agent_id_idx_dict: agent_id_idx_dict.unbind(),
+            serde_list,
+        })
+    }
+}
+
+// Returns the obs serde to use for the agent id
+pub fn select_obs_serde<'a, 'py>(
+    agent_obs_serdes_option: &'a mut Option<AgentObsSerdes>,
+    obs_serde: &'a mut Box<dyn PyAnySerde>,
+    agent_id: &Bound<'py, PyAny>,
+) -> PyResult<&'a mut Box<dyn PyAnySerde>> {
+    if let Some(agent_obs_serdes) = agent_obs_serdes_option {
+        if let Some(serde_idx) = agent_obs_serdes
+            .agent_id_idx_dict
+            .bind(agent_id.py())
+            .get_item(agent_id)?
+        {
+            return Ok(&mut agent_obs_serdes.serde_list[serde_idx.extract::<usize>()?]);
+        }
+    }
+    Ok(obs_serde)
+}
@@ -0,0 +1,74 @@
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use pyo3::types::{PyBytes, PyDict};
+
+// How the observations of a message are written. With ARROW_IPC, the obs of all agents in a message
+// are written together after the per-agent fields as a length prefixed Apache Arrow IPC stream,
+// containing a single record batch with an obs column holding the obs of each agent in agent id list
+// order. This lets parents which aren't written in Python (or which want columnar access) read
+// observations without implementing the obs serde. The observations must be convertible by
+// pyarrow, which is imported on first use. With FIXED_LAYOUT, the obs are likewise written after the
+// per-agent fields, as aligned fixed size f32 records of a shape given at startup, see
+// fixed_layout.rs.
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObsFormat {
+    SERDE,
+    ARROW_IPC,
+    FIXED_LAYOUT,
+}
+
+fn pyarrow(py: Python<'_>) -> PyResult<&Bound<'_, PyAny>> {
+    static INTERNED_PYARROW: GILOnceCell<PyObject> = GILOnceCell::new();
+    Ok(INTERNED_PYARROW
+        .get_or_try_init::<_, PyErr>(py, || Ok(py.import("pyarrow")?.into_any().unbind()))?
+        .bind(py))
+}
+
+#[derive(Default)]
+pub struct ArrowObsWriter {
+    schema_option: Option<PyObject>,
+}
+
+impl ArrowObsWriter {
+    // The schema is inferred from the obs of the first message and reused for all later messages,
+    // so that every stream has the same schema
+    pub fn write(&mut self, py: Python<'_>, obs_list: Vec<Bound<'_, PyAny>>) -> PyResult<Vec<u8>> {
+        let pa = pyarrow(py)?;
+        let data = PyDict::new(py);
+        data.set_item(intern!(py, "obs"), obs_list)?;
+        let batch = pa.getattr(intern!(py, "RecordBatch"))?.call_method1(
+            intern!(py, "from_pydict"),
+            (
+                data,
+                self.schema_option.as_ref().map(|schema| schema.bind(py)),
+            ),
+        )?;
+        let schema = batch.getattr(intern!(py, "schema"))?;
+        let sink = pa.call_method0(intern!(py, "BufferOutputStream"))?;
+        let writer = pa
+            .getattr(intern!(py, "ipc"))?
+            .call_method1(intern!(py, "new_stream"), (&sink, &schema))?;
+        writer.call_method1(intern!(py, "write_batch"), (batch,))?;
+        writer.call_method0(intern!(py, "close"))?;
+        if self.schema_option.is_none() {
+            self.schema_option = Some(schema.unbind());
+        }
+        sink.call_method0(intern!(py, "getvalue"))?
+            .call_method0(intern!(py, "to_pybytes"))?
+            .extract()
+    }
+}
+
+// Reads the obs column of a stream written by ArrowObsWriter::write, as Python objects
+pub fn read_arrow_obs_list<'py>(py: Python<'py>, bytes: &[u8]) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    pyarrow(py)?
+        .getattr(intern!(py, "ipc"))?
+        .call_method1(intern!(py, "open_stream"), (PyBytes::new(py, bytes),))?
+        .call_method0(intern!(py, "read_all"))?
+        .call_method1(intern!(py, "column"), (intern!(py, "obs"),))?
+        .call_method0(intern!(py, "to_pylist"))?
+        .extract()
+}
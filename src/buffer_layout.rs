@@ -0,0 +1,443 @@
+use std::mem::size_of;
+
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+// Describes how a single field is encoded in the shared memory buffer
+#[derive(Clone, Debug)]
+pub enum FieldEncoding {
+    U8,
+    Bool,
+    Usize,
+    Serde(&'static str),
+    // A Usize length prefix followed by the compressed bytes of the serde's output
+    CompressedSerde(&'static str),
+    // The Usize offset and Usize length of the serde's output in the process' obs file
+    FileSerde(&'static str),
+    // A kind byte followed by length prefixed lists, see action_space_descriptor.rs
+    ActionSpaceDescriptor,
+    // A Usize length prefix followed by an Arrow IPC stream, see arrow_ipc.rs
+    ArrowIpc,
+    // A Usize length prefix followed by the pickled IPC handle of a CUDA tensor, see cuda_ipc.rs
+    CudaIpcHandle,
+    // Padding to an aligned address followed by one f32 record per agent, see fixed_layout.rs
+    FixedLayoutRecords,
+    // A Usize length prefix followed by raw bytes
+    Bytes,
+    // One bit per agent, least significant bit first, padded to whole bytes
+    Bitset,
+    // Padding up to the next address which is a multiple of the alignment
+    AlignmentPadding(usize),
+}
+
+impl FieldEncoding {
+    fn name(&self) -> &'static str {
+        match self {
+            FieldEncoding::U8 => "u8",
+            FieldEncoding::Bool => "bool",
+            FieldEncoding::Usize => "u64_le",
+            FieldEncoding::Serde(_) => "serde",
+            FieldEncoding::CompressedSerde(_) => "compressed_serde",
+            FieldEncoding::FileSerde(_) => "file_serde",
+            FieldEncoding::ActionSpaceDescriptor => "action_space_descriptor",
+            FieldEncoding::ArrowIpc => "arrow_ipc",
+            FieldEncoding::CudaIpcHandle => "cuda_ipc_handle",
+            FieldEncoding::FixedLayoutRecords => "fixed_layout_f32_records",
+            FieldEncoding::Bytes => "bytes",
+            FieldEncoding::Bitset => "bitset",
+            FieldEncoding::AlignmentPadding(_) => "alignment_padding",
+        }
+    }
+
+    fn size(&self) -> Option<usize> {
+        match self {
+            FieldEncoding::U8 => Some(size_of::<u8>()),
+            FieldEncoding::Bool => Some(size_of::<u8>()),
+            FieldEncoding::Usize => Some(size_of::<u64>()),
+            FieldEncoding::FileSerde(_) => Some(2 * size_of::<u64>()),
+            FieldEncoding::Serde(_)
+            | FieldEncoding::CompressedSerde(_)
+            | FieldEncoding::ActionSpaceDescriptor
+            | FieldEncoding::ArrowIpc
+            | FieldEncoding::CudaIpcHandle
+            | FieldEncoding::FixedLayoutRecords
+            | FieldEncoding::Bytes
+            | FieldEncoding::Bitset
+            | FieldEncoding::AlignmentPadding(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub encoding: FieldEncoding,
+    pub per_agent: bool,
+    pub condition_option: Option<&'static str>,
+}
+
+impl FieldLayout {
+    fn new(name: &'static str, encoding: FieldEncoding) -> Self {
+        FieldLayout {
+            name,
+            encoding,
+            per_agent: false,
+            condition_option: None,
+        }
+    }
+
+    fn per_agent(mut self) -> Self {
+        self.per_agent = true;
+        self
+    }
+
+    fn when(mut self, condition: &'static str) -> Self {
+        self.condition_option = Some(condition);
+        self
+    }
+
+    fn to_py_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let field = PyDict::new(py);
+        field.set_item("name", self.name)?;
+        field.set_item("encoding", self.encoding.name())?;
+        if let FieldEncoding::Serde(serde_name)
+        | FieldEncoding::CompressedSerde(serde_name)
+        | FieldEncoding::FileSerde(serde_name) = self.encoding
+        {
+            field.set_item("serde", serde_name)?;
+        }
+        if let FieldEncoding::AlignmentPadding(alignment) = self.encoding {
+            field.set_item("alignment", alignment)?;
+        }
+        field.set_item("size", self.encoding.size())?;
+        field.set_item("per_agent", self.per_agent)?;
+        field.set_item("condition", self.condition_option)?;
+        Ok(field)
+    }
+}
+
+// The byte layout of the messages written by env_process into shared memory, in write order.
+// Fields with a condition are only present when that condition holds for the message in question.
+#[derive(Clone, Debug)]
+pub struct BufferLayout {
+    pub env_shapes: Vec<FieldLayout>,
+    pub reset: Vec<FieldLayout>,
+    pub step: Vec<FieldLayout>,
+    // The response to a STEP env action with send_obs = false
+    pub step_without_obs: Vec<FieldLayout>,
+    // With chunked obs, the obs of a reset or step message are left out of it and sent afterwards
+    // as a series of these messages, the concatenated bytes of which are the obs of all agents
+    // serialized with the obs serde. Empty otherwise.
+    pub obs_chunk: Vec<FieldLayout>,
+}
+
+// The env_process options which affect the buffer layout
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug, Default)]
+pub struct BufferLayoutConfig {
+    pub recalculate_agent_id_every_step: bool,
+    pub has_shared_info_serde: bool,
+    pub has_state_serde: bool,
+    pub stable_agent_indices: bool,
+    pub episode_boundary_reasons: bool,
+    pub compressed_shared_info: bool,
+    pub has_reward_component_serde: bool,
+    pub has_critic_obs_serde: bool,
+    pub obs_in_file: bool,
+    pub done_reasons: bool,
+    pub bootstrap_flags: bool,
+    pub dedup_set_state_obs: bool,
+    pub reuse_identical_obs: bool,
+    pub arrow_obs: bool,
+    pub fixed_layout_obs: bool,
+    pub cuda_ipc_obs: bool,
+    pub global_obs: bool,
+    pub legal_action_masks: bool,
+    pub state_hashes: bool,
+    pub env_step_counts: bool,
+    pub chunked_obs: bool,
+    pub prev_actions: bool,
+    pub packed_done_flags: bool,
+    pub region_alignment: Option<usize>,
+    pub include_counters: bool,
+    pub new_agent_flags: bool,
+    pub rng_states: bool,
+}
+
+#[pymethods]
+impl BufferLayoutConfig {
+    // All options start out disabled, and are set as attributes
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+impl BufferLayout {
+    pub fn new(config: &BufferLayoutConfig) -> Self {
+        let env_shapes = vec![
+            FieldLayout::new("obs_space", FieldEncoding::Serde("obs_space_serde")),
+            FieldLayout::new("action_space", FieldEncoding::Serde("action_space_serde")),
+            FieldLayout::new(
+                "action_space_descriptor",
+                FieldEncoding::ActionSpaceDescriptor,
+            ),
+        ];
+
+        let agent_id_fields = || {
+            let agent_id =
+                FieldLayout::new("agent_id", FieldEncoding::Serde("agent_id_serde")).per_agent();
+            let mut fields = vec![agent_id];
+            if config.stable_agent_indices {
+                fields.push(FieldLayout::new("agent_index", FieldEncoding::Usize).per_agent());
+            }
+            if config.new_agent_flags {
+                fields.push(FieldLayout::new("is_new_agent", FieldEncoding::Bool).per_agent());
+            }
+            fields
+        };
+
+        let obs_fields = |is_step: bool| {
+            let mut fields = Vec::new();
+            let obs_flag = FieldLayout::new("obs_same_as_prev", FieldEncoding::Bool).per_agent();
+            let has_obs_flag = if config.reuse_identical_obs {
+                fields.push(obs_flag);
+                true
+            } else if config.dedup_set_state_obs && !is_step {
+                fields.push(obs_flag.when("set_state"));
+                true
+            } else {
+                false
+            };
+            let mut obs = if config.obs_in_file {
+                FieldLayout::new("obs", FieldEncoding::FileSerde("obs_serde")).per_agent()
+            } else {
+                FieldLayout::new("obs", FieldEncoding::Serde("obs_serde")).per_agent()
+            };
+            if config.cuda_ipc_obs {
+                let mut obs_flag =
+                    FieldLayout::new("obs_is_cuda_ipc", FieldEncoding::Bool).per_agent();
+                if has_obs_flag {
+                    obs_flag = obs_flag.when("not obs_same_as_prev");
+                }
+                fields.push(obs_flag);
+                fields.push(
+                    FieldLayout::new("obs", FieldEncoding::CudaIpcHandle)
+                        .per_agent()
+                        .when(if has_obs_flag {
+                            "not obs_same_as_prev and obs_is_cuda_ipc"
+                        } else {
+                            "obs_is_cuda_ipc"
+                        }),
+                );
+                obs = obs.when(if has_obs_flag {
+                    "not obs_same_as_prev and not obs_is_cuda_ipc"
+                } else {
+                    "not obs_is_cuda_ipc"
+                });
+            } else if has_obs_flag {
+                obs = obs.when("not obs_same_as_prev");
+            }
+            // Arrow IPC and fixed layout obs are written after the per-agent fields instead, and
+            // chunked obs after the message
+            if !config.arrow_obs && !config.fixed_layout_obs && !config.chunked_obs {
+                fields.push(obs);
+            }
+            if config.has_critic_obs_serde {
+                fields.push(
+                    FieldLayout::new("critic_obs", FieldEncoding::Serde("critic_obs_serde"))
+                        .per_agent(),
+                );
+            }
+            if config.legal_action_masks {
+                fields.push(
+                    FieldLayout::new(
+                        "legal_action_mask",
+                        FieldEncoding::Serde("legal_action_mask_serde"),
+                    )
+                    .per_agent(),
+                );
+            }
+            fields
+        };
+
+        let mut reset = Vec::new();
+        if config.episode_boundary_reasons {
+            reset.push(FieldLayout::new(
+                "episode_boundary_reason",
+                FieldEncoding::U8,
+            ));
+        }
+        reset.push(FieldLayout::new("n_agents", FieldEncoding::Usize));
+        reset.append(&mut agent_id_fields());
+        reset.append(&mut obs_fields(false));
+
+        let mut step_fields = Vec::new();
+        step_fields
+            .push(FieldLayout::new("reward", FieldEncoding::Serde("reward_serde")).per_agent());
+        if config.has_reward_component_serde {
+            step_fields.push(
+                FieldLayout::new(
+                    "reward_components",
+                    FieldEncoding::Serde("reward_component_serde"),
+                )
+                .per_agent(),
+            );
+        }
+        if !config.packed_done_flags {
+            step_fields.push(FieldLayout::new("terminated", FieldEncoding::Bool).per_agent());
+            step_fields.push(FieldLayout::new("truncated", FieldEncoding::Bool).per_agent());
+        }
+        if config.bootstrap_flags {
+            step_fields.push(FieldLayout::new("should_bootstrap", FieldEncoding::Bool).per_agent());
+        }
+        if config.done_reasons {
+            step_fields.push(FieldLayout::new("done_reason", FieldEncoding::U8).per_agent());
+        }
+        if config.prev_actions {
+            step_fields.push(FieldLayout::new("has_prev_action", FieldEncoding::Bool).per_agent());
+            step_fields.push(
+                FieldLayout::new("prev_action", FieldEncoding::Serde("action_serde"))
+                    .per_agent()
+                    .when("has_prev_action"),
+            );
+        }
+
+        let mut step = Vec::new();
+        let mut step_without_obs = Vec::new();
+        if config.recalculate_agent_id_every_step {
+            step.append(&mut agent_id_fields());
+            step_without_obs.append(&mut agent_id_fields());
+        }
+        step.append(&mut obs_fields(true));
+        if config.packed_done_flags {
+            step_fields.push(FieldLayout::new("terminated", FieldEncoding::Bitset));
+            step_fields.push(FieldLayout::new("truncated", FieldEncoding::Bitset));
+        }
+        if config.env_step_counts {
+            step_fields.push(FieldLayout::new("n_env_steps", FieldEncoding::Usize));
+        }
+        step.extend(step_fields.iter().cloned());
+        step_without_obs.append(&mut step_fields);
+
+        for (fields, has_obs) in [
+            (&mut reset, true),
+            (&mut step, true),
+            (&mut step_without_obs, false),
+        ] {
+            if config.include_counters {
+                fields.push(FieldLayout::new("total_env_steps", FieldEncoding::Usize));
+                fields.push(FieldLayout::new("n_episodes", FieldEncoding::Usize));
+            }
+            if has_obs && config.arrow_obs {
+                fields.push(FieldLayout::new("obs", FieldEncoding::ArrowIpc));
+            }
+            if has_obs && config.fixed_layout_obs {
+                fields.push(FieldLayout::new("obs", FieldEncoding::FixedLayoutRecords));
+            }
+            if has_obs && config.global_obs {
+                fields.push(FieldLayout::new(
+                    "global_obs",
+                    FieldEncoding::Serde("global_obs_serde"),
+                ));
+            }
+            if config.state_hashes {
+                fields.push(FieldLayout::new("state_hash", FieldEncoding::Usize));
+            }
+            if config.has_shared_info_serde && config.compressed_shared_info {
+                fields.push(FieldLayout::new(
+                    "shared_info",
+                    FieldEncoding::CompressedSerde("shared_info_serde"),
+                ));
+            } else if config.has_shared_info_serde {
+                fields.push(FieldLayout::new(
+                    "shared_info",
+                    FieldEncoding::Serde("shared_info_serde"),
+                ));
+            }
+            if config.has_state_serde {
+                fields.push(
+                    FieldLayout::new("state", FieldEncoding::Serde("state_serde"))
+                        .when("send_state"),
+                );
+                if config.rng_states {
+                    fields.push(
+                        FieldLayout::new("has_rng_state", FieldEncoding::Bool).when("send_state"),
+                    );
+                    fields.push(
+                        FieldLayout::new("rng_state", FieldEncoding::Serde("rng_state_serde"))
+                            .when("has_rng_state"),
+                    );
+                }
+            }
+        }
+
+        // With a region alignment, the region of each agent is padded to start at an aligned address
+        if let Some(region_alignment) = config.region_alignment {
+            for fields in [&mut reset, &mut step, &mut step_without_obs] {
+                if let Some(idx) = fields.iter().position(|field| field.per_agent) {
+                    fields.insert(
+                        idx,
+                        FieldLayout::new(
+                            "padding",
+                            FieldEncoding::AlignmentPadding(region_alignment),
+                        )
+                        .per_agent(),
+                    );
+                }
+            }
+        }
+
+        let mut obs_chunk = Vec::new();
+        if config.chunked_obs {
+            obs_chunk.push(FieldLayout::new("chunk_idx", FieldEncoding::Usize));
+            obs_chunk.push(FieldLayout::new("n_chunks", FieldEncoding::Usize));
+            obs_chunk.push(FieldLayout::new("obs_bytes", FieldEncoding::Bytes));
+        }
+
+        BufferLayout {
+            env_shapes,
+            reset,
+            step,
+            step_without_obs,
+            obs_chunk,
+        }
+    }
+
+    pub fn to_py_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let layout = PyDict::new(py);
+        for (message_name, fields) in [
+            ("env_shapes", &self.env_shapes),
+            ("reset", &self.reset),
+            ("step", &self.step),
+            ("step_without_obs", &self.step_without_obs),
+            ("obs_chunk", &self.obs_chunk),
+        ] {
+            let py_fields = PyList::empty(py);
+            for field in fields.iter() {
+                py_fields.append(field.to_py_dict(py)?)?;
+            }
+            layout.set_item(message_name, py_fields)?;
+        }
+        Ok(layout)
+    }
+
+    pub fn write_json<'py>(&self, py: Python<'py>, path: &str) -> PyResult<()> {
+        let json = py
+            .import("json")?
+            .call_method1("dumps", (self.to_py_dict(py)?,))?
+            .extract::<String>()?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+// Describes the messages env_process writes with the given options, as returned by
+// BufferLayout::to_py_dict, so that parents can check that their decoder matches the build
+#[pyfunction]
+pub fn buffer_layout<'py>(
+    py: Python<'py>,
+    config: BufferLayoutConfig,
+) -> PyResult<Bound<'py, PyDict>> {
+    BufferLayout::new(&config).to_py_dict(py)
+}
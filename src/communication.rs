@@ -0,0 +1,358 @@
+use std::mem::size_of;
+
+use pyany_serde::communication::{append_bool_vec, retrieve_bool};
+use pyany_serde::{PickleablePyAnySerdeType, PyAnySerde, PyAnySerdeType};
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+
+// The pyany_serde communication helpers use native endianness and native usize width. The
+// integers written by this crate into the shared memory buffer instead use these helpers, which
+// always encode usize values as little-endian u64s so that the parent and env processes agree on
+// the layout even across architectures.
+
+pub fn append_usize_le(buf: &mut [u8], offset: usize, val: usize) -> usize {
+    let end = offset + size_of::<u64>();
+    buf[offset..end].copy_from_slice(&(val as u64).to_le_bytes());
+    end
+}
+
+pub fn append_usize_le_vec(v: &mut Vec<u8>, val: usize) {
+    v.extend_from_slice(&(val as u64).to_le_bytes());
+}
+
+// Returns the len bytes of buf starting at offset, or an error naming what was being retrieved if
+// they run past the end of the buffer
+pub fn retrieve_slice<'a>(
+    buf: &'a [u8],
+    offset: usize,
+    len: usize,
+    what: &str,
+) -> PyResult<&'a [u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| buf.get(offset..end))
+        .ok_or_else(|| {
+            InvalidStateError::new_err(format!(
+                "tried to retrieve {} of {} bytes from shared_memory at offset {} which runs past the end of the buffer (length {})",
+                what,
+                len,
+                offset,
+                buf.len()
+            ))
+        })
+}
+
+pub fn retrieve_usize_le(buf: &[u8], offset: usize) -> PyResult<(usize, usize)> {
+    let (val, end) = retrieve_u64_le(buf, offset)?;
+    let val = usize::try_from(val).map_err(|_| {
+        InvalidStateError::new_err(format!(
+            "tried to retrieve usize from shared_memory but got value {} which does not fit in a usize on this architecture",
+            val
+        ))
+    })?;
+    Ok((val, end))
+}
+
+pub fn append_u64_le(buf: &mut [u8], offset: usize, val: u64) -> usize {
+    let end = offset + size_of::<u64>();
+    buf[offset..end].copy_from_slice(&val.to_le_bytes());
+    end
+}
+
+pub fn append_u64_le_vec(v: &mut Vec<u8>, val: u64) {
+    v.extend_from_slice(&val.to_le_bytes());
+}
+
+pub fn retrieve_u64_le(buf: &[u8], offset: usize) -> PyResult<(u64, usize)> {
+    let bytes = retrieve_slice(buf, offset, size_of::<u64>(), "u64")?;
+    Ok((
+        u64::from_le_bytes(bytes.try_into()?),
+        offset + size_of::<u64>(),
+    ))
+}
+
+// Returns the first offset at or after offset whose address in buf is a multiple of alignment. Since
+// the shared memory is mapped page aligned in both processes, the padding is the same for the env
+// process and the parent.
+pub fn aligned_offset(buf: &[u8], offset: usize, alignment: usize) -> usize {
+    let addr = buf.as_ptr() as usize + offset;
+    offset + (alignment - addr % alignment) % alignment
+}
+
+// Pads v with zeros until the address the next byte will be copied to is a multiple of alignment,
+// given the address the start of v will be copied to
+pub fn append_alignment_padding_vec(v: &mut Vec<u8>, start_addr: usize, alignment: usize) {
+    let addr = start_addr + v.len();
+    v.resize(v.len() + (alignment - addr % alignment) % alignment, 0);
+}
+
+// 64-bit FNV-1a, used where a hash must be the same in every process and on every platform
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn append_bytes_le(buf: &mut [u8], offset: usize, bytes: &[u8]) -> usize {
+    let offset = append_usize_le(buf, offset, bytes.len());
+    let end = offset + bytes.len();
+    buf[offset..end].copy_from_slice(bytes);
+    end
+}
+
+pub fn append_bytes_le_vec(v: &mut Vec<u8>, bytes: &[u8]) {
+    append_usize_le_vec(v, bytes.len());
+    v.extend_from_slice(bytes);
+}
+
+pub fn retrieve_bytes_le(buf: &[u8], offset: usize) -> PyResult<(&[u8], usize)> {
+    let (len, offset) = retrieve_usize_le(buf, offset)?;
+    Ok((retrieve_slice(buf, offset, len, "bytes")?, offset + len))
+}
+
+// Single bytes have no endianness, but are written with these helpers for symmetry with the rest of
+// the buffer
+pub fn append_u8(buf: &mut [u8], offset: usize, val: u8) -> usize {
+    buf[offset] = val;
+    offset + 1
+}
+
+pub fn retrieve_u8(buf: &[u8], offset: usize) -> PyResult<(u8, usize)> {
+    let val = *buf.get(offset).ok_or_else(|| {
+        InvalidStateError::new_err(format!(
+            "tried to retrieve u8 from shared_memory at offset {} which is past the end of the buffer",
+            offset
+        ))
+    })?;
+    Ok((val, offset + 1))
+}
+
+// Bitsets are written as one bit per flag, least significant bit first, padded to whole bytes
+pub fn append_bitset(buf: &mut [u8], offset: usize, flags: &[bool]) -> usize {
+    let end = offset + flags.len().div_ceil(8);
+    buf[offset..end].fill(0);
+    for (idx, flag) in flags.iter().enumerate() {
+        if *flag {
+            buf[offset + idx / 8] |= 1 << (idx % 8);
+        }
+    }
+    end
+}
+
+pub fn append_bitset_vec(v: &mut Vec<u8>, flags: &[bool]) {
+    let start = v.len();
+    v.resize(start + flags.len().div_ceil(8), 0);
+    append_bitset(v, start, flags);
+}
+
+pub fn retrieve_bitset(buf: &[u8], offset: usize, n_flags: usize) -> PyResult<(Vec<bool>, usize)> {
+    let n_bytes = n_flags.div_ceil(8);
+    let bytes = retrieve_slice(buf, offset, n_bytes, "bitset")?;
+    Ok((
+        (0..n_flags)
+            .map(|idx| bytes[idx / 8] & (1 << (idx % 8)) != 0)
+            .collect(),
+        offset + n_bytes,
+    ))
+}
+
+// The fields written after each agent id: the stable agent index if stable_agent_indices is set,
+// then whether the agent is new if new_agent_flags is set
+pub fn append_agent_id_fields_vec(
+    v: &mut Vec<u8>,
+    agent_index_option: Option<usize>,
+    is_new_agent_option: Option<bool>,
+) {
+    if let Some(agent_index) = agent_index_option {
+        append_usize_le_vec(v, agent_index);
+    }
+    if let Some(is_new_agent) = is_new_agent_option {
+        append_bool_vec(v, is_new_agent);
+    }
+}
+
+pub fn retrieve_agent_id_fields(
+    buf: &[u8],
+    mut offset: usize,
+    stable_agent_indices: bool,
+    new_agent_flags: bool,
+) -> PyResult<(Option<usize>, Option<bool>, usize)> {
+    let mut agent_index_option = None;
+    if stable_agent_indices {
+        let agent_index;
+        (agent_index, offset) = retrieve_usize_le(buf, offset)?;
+        agent_index_option = Some(agent_index);
+    }
+    let mut is_new_agent_option = None;
+    if new_agent_flags {
+        let is_new_agent;
+        (is_new_agent, offset) = retrieve_bool(buf, offset)?;
+        is_new_agent_option = Some(is_new_agent);
+    }
+    Ok((agent_index_option, is_new_agent_option, offset))
+}
+
+// Serde types are sent using their pickle state, so that the receiving process can reconstruct the
+// serde
+pub fn append_serde_type_option_vec(
+    v: &mut Vec<u8>,
+    serde_type_option: Option<PyAnySerdeType>,
+) -> PyResult<()> {
+    append_bool_vec(v, serde_type_option.is_some());
+    if let Some(serde_type) = serde_type_option {
+        let state = PickleablePyAnySerdeType(Some(Some(serde_type))).__getstate__()?;
+        append_bytes_le_vec(v, &state[..]);
+    }
+    Ok(())
+}
+
+pub fn retrieve_serde_option(
+    buf: &[u8],
+    offset: usize,
+) -> PyResult<(Option<Box<dyn PyAnySerde>>, usize)> {
+    let (is_some, offset) = retrieve_bool(buf, offset)?;
+    if !is_some {
+        return Ok((None, offset));
+    }
+    let (state, offset) = retrieve_bytes_le(buf, offset)?;
+    let mut pickleable_serde_type = PickleablePyAnySerdeType(None);
+    pickleable_serde_type.__setstate__(state.to_vec())?;
+    let serde_type = pickleable_serde_type.0.flatten().ok_or_else(|| {
+        InvalidStateError::new_err(
+            "Received a serde type payload which did not contain a serde type",
+        )
+    })?;
+    Ok((Some(serde_type.try_into()?), offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usize_le_round_trip() {
+        let mut buf = vec![0; 3 * size_of::<u64>()];
+        let mut offset = 0;
+        for val in [0, 1, usize::MAX] {
+            offset = append_usize_le(&mut buf, offset, val);
+        }
+        assert_eq!(offset, buf.len());
+        assert_eq!(&buf[..size_of::<u64>()], &[0; 8]);
+        assert_eq!(buf[size_of::<u64>()], 1);
+        let mut offset = 0;
+        for val in [0, 1, usize::MAX] {
+            let retrieved;
+            (retrieved, offset) = retrieve_usize_le(&buf, offset).unwrap();
+            assert_eq!(retrieved, val);
+        }
+        let mut v = Vec::new();
+        append_usize_le_vec(&mut v, 1);
+        assert_eq!(&v[..], &buf[size_of::<u64>()..2 * size_of::<u64>()]);
+    }
+
+    #[test]
+    fn retrieve_past_end_is_error() {
+        let buf = vec![0; size_of::<u64>() + 4];
+        assert!(retrieve_usize_le(&buf, 5).is_err());
+        assert!(retrieve_usize_le(&buf, buf.len()).is_err());
+        assert!(retrieve_usize_le(&buf, usize::MAX).is_err());
+        assert!(retrieve_u64_le(&buf, 5).is_err());
+        assert!(retrieve_u8(&buf, buf.len()).is_err());
+        // A length prefix which claims more bytes than the buffer holds
+        let mut buf = vec![0; size_of::<u64>() + 2];
+        append_usize_le(&mut buf, 0, 3);
+        assert!(retrieve_bytes_le(&buf, 0).is_err());
+        append_usize_le(&mut buf, 0, usize::MAX);
+        assert!(retrieve_bytes_le(&buf, 0).is_err());
+    }
+
+    #[test]
+    fn bytes_le_round_trip() {
+        let mut buf = vec![0; 2 * size_of::<u64>() + 5];
+        let offset = append_bytes_le(&mut buf, 0, b"abc");
+        let end = append_bytes_le(&mut buf, offset, b"");
+        assert_eq!(end, 2 * size_of::<u64>() + 3);
+        let (bytes, offset) = retrieve_bytes_le(&buf, 0).unwrap();
+        assert_eq!(bytes, b"abc");
+        let (bytes, offset) = retrieve_bytes_le(&buf, offset).unwrap();
+        assert_eq!(bytes, b"");
+        assert_eq!(offset, end);
+        let mut v = Vec::new();
+        append_bytes_le_vec(&mut v, b"abc");
+        assert_eq!(&v[..], &buf[..size_of::<u64>() + 3]);
+    }
+
+    #[test]
+    fn bitset_round_trip() {
+        for n_flags in [0usize, 1, 7, 8, 9, 16, 17] {
+            let flags: Vec<bool> = (0..n_flags).map(|idx| idx % 3 == 0).collect();
+            // Stale bytes must not leak into the padding bits
+            let mut buf = vec![0xff; 4];
+            let end = append_bitset(&mut buf, 1, &flags);
+            assert_eq!(end, 1 + n_flags.div_ceil(8));
+            let (retrieved, offset) = retrieve_bitset(&buf, 1, n_flags).unwrap();
+            assert_eq!(retrieved, flags);
+            assert_eq!(offset, end);
+        }
+        let mut buf = vec![0; 1];
+        append_bitset(&mut buf, 0, &[true, false, true]);
+        assert_eq!(buf[0], 0b101);
+        let mut v = vec![0xff];
+        append_bitset_vec(&mut v, &[true, false, true]);
+        assert_eq!(v, [0xff, 0b101]);
+        assert!(retrieve_bitset(&buf, 0, 9).is_err());
+    }
+
+    #[test]
+    fn fnv1a_64_known_vectors() {
+        assert_eq!(fnv1a_64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a_64(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn aligned_offset_is_aligned() {
+        let buf = vec![0; 256];
+        for alignment in [1, 2, 8, 64] {
+            for offset in 0..100 {
+                let aligned = aligned_offset(&buf, offset, alignment);
+                assert!(aligned >= offset && aligned - offset < alignment);
+                assert_eq!((buf.as_ptr() as usize + aligned) % alignment, 0);
+                // Padding a vec which will be copied to buf gives the same offset
+                let mut v = vec![0; offset];
+                append_alignment_padding_vec(&mut v, buf.as_ptr() as usize, alignment);
+                assert_eq!(v.len(), aligned);
+            }
+        }
+    }
+
+    #[test]
+    fn agent_id_fields_round_trip() {
+        for (agent_index_option, is_new_agent_option) in [
+            (None, None),
+            (Some(7), None),
+            (None, Some(true)),
+            (Some(3), Some(false)),
+        ] {
+            // A trailing byte stands in for the field written after the agent id fields
+            let mut buf = Vec::new();
+            append_agent_id_fields_vec(&mut buf, agent_index_option, is_new_agent_option);
+            let end = buf.len();
+            buf.push(0xab);
+            let (retrieved_index_option, retrieved_flag_option, offset) = retrieve_agent_id_fields(
+                &buf,
+                0,
+                agent_index_option.is_some(),
+                is_new_agent_option.is_some(),
+            )
+            .unwrap();
+            assert_eq!(retrieved_index_option, agent_index_option);
+            assert_eq!(retrieved_flag_option, is_new_agent_option);
+            assert_eq!(offset, end);
+            assert_eq!(buf[offset], 0xab);
+        }
+    }
+}
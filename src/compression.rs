@@ -0,0 +1,54 @@
+use pyo3::sync::GILOnceCell;
+use pyo3::types::PyBytes;
+use pyo3::{intern, prelude::*};
+
+// Compression codecs from the Python standard library which can be applied to payloads in the
+// shared memory buffer
+#[allow(non_camel_case_types)]
+#[pyclass(module = "rlgym_learn")]
+#[derive(Clone, Debug)]
+pub enum CompressionCodec {
+    #[pyo3(constructor = (level = 6))]
+    ZLIB {
+        level: i32,
+    },
+    LZMA {},
+    BZ2 {},
+}
+
+impl CompressionCodec {
+    fn module<'py>(&self, py: Python<'py>) -> PyResult<&Bound<'py, PyAny>> {
+        static INTERNED_ZLIB: GILOnceCell<PyObject> = GILOnceCell::new();
+        static INTERNED_LZMA: GILOnceCell<PyObject> = GILOnceCell::new();
+        static INTERNED_BZ2: GILOnceCell<PyObject> = GILOnceCell::new();
+        let (cell, module_name) = match self {
+            CompressionCodec::ZLIB { .. } => (&INTERNED_ZLIB, "zlib"),
+            CompressionCodec::LZMA {} => (&INTERNED_LZMA, "lzma"),
+            CompressionCodec::BZ2 {} => (&INTERNED_BZ2, "bz2"),
+        };
+        Ok(cell
+            .get_or_try_init::<_, PyErr>(py, || Ok(py.import(module_name)?.into_any().unbind()))?
+            .bind(py))
+    }
+
+    pub fn compress<'py>(&self, py: Python<'py>, data: &[u8]) -> PyResult<Vec<u8>> {
+        let module = self.module(py)?;
+        let data = PyBytes::new(py, data);
+        let compressed = match self {
+            CompressionCodec::ZLIB { level } => {
+                module.call_method1(intern!(py, "compress"), (data, *level))?
+            }
+            _ => module.call_method1(intern!(py, "compress"), (data,))?,
+        };
+        Ok(compressed.downcast::<PyBytes>()?.as_bytes().to_vec())
+    }
+
+    pub fn decompress<'py>(&self, py: Python<'py>, data: &[u8]) -> PyResult<Vec<u8>> {
+        Ok(self
+            .module(py)?
+            .call_method1(intern!(py, "decompress"), (PyBytes::new(py, data),))?
+            .downcast::<PyBytes>()?
+            .as_bytes()
+            .to_vec())
+    }
+}
@@ -0,0 +1,91 @@
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use pyo3::types::{PyBytes, PyTuple};
+
+use crate::communication::{append_bytes_le, append_bytes_le_vec, retrieve_bytes_le};
+
+// CUDA tensor observations can be shared with the parent through torch's CUDA IPC mechanism instead
+// of being copied to the CPU and serialized. Only the pickled rebuild function and arguments returned
+// by torch.multiprocessing.reductions.reduce_tensor (which contain the IPC handle of the tensor's
+// storage) are written to the buffer, and the parent rebuilds a tensor backed by the same GPU memory.
+// torch is imported on first use.
+
+fn reduce_tensor(py: Python<'_>) -> PyResult<&Bound<'_, PyAny>> {
+    static INTERNED_REDUCE_TENSOR: GILOnceCell<PyObject> = GILOnceCell::new();
+    Ok(INTERNED_REDUCE_TENSOR
+        .get_or_try_init::<_, PyErr>(py, || {
+            Ok(py
+                .import("torch.multiprocessing.reductions")?
+                .getattr("reduce_tensor")?
+                .unbind())
+        })?
+        .bind(py))
+}
+
+fn pickle(py: Python<'_>) -> PyResult<&Bound<'_, PyAny>> {
+    static INTERNED_PICKLE: GILOnceCell<PyObject> = GILOnceCell::new();
+    Ok(INTERNED_PICKLE
+        .get_or_try_init::<_, PyErr>(py, || Ok(py.import("pickle")?.into_any().unbind()))?
+        .bind(py))
+}
+
+pub fn is_cuda_tensor(obs: &Bound<'_, PyAny>) -> PyResult<bool> {
+    let py = obs.py();
+    Ok(obs.hasattr(intern!(py, "is_cuda"))? && obs.getattr(intern!(py, "is_cuda"))?.is_truthy()?)
+}
+
+#[derive(Default)]
+pub struct CudaIpcObsWriter {
+    // The tensors shared in the current message, which are kept alive until the next message so that
+    // the parent can open their IPC handles
+    shared_tensor_list: Vec<PyObject>,
+}
+
+impl CudaIpcObsWriter {
+    pub fn start_message(&mut self) {
+        self.shared_tensor_list.clear();
+    }
+
+    // Shares the CUDA tensor and returns its pickled IPC handle
+    fn share<'py>(&mut self, obs: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyBytes>> {
+        let py = obs.py();
+        let reduced = reduce_tensor(py)?.call1((obs,))?;
+        let reduced_bytes = pickle(py)?
+            .call_method1(intern!(py, "dumps"), (reduced,))?
+            .downcast_into::<PyBytes>()?;
+        self.shared_tensor_list.push(obs.clone().unbind());
+        Ok(reduced_bytes)
+    }
+
+    // Writes the IPC handle of the CUDA tensor to buf, as a length prefixed pickle
+    pub fn append(
+        &mut self,
+        buf: &mut [u8],
+        offset: usize,
+        obs: &Bound<'_, PyAny>,
+    ) -> PyResult<usize> {
+        let reduced_bytes = self.share(obs)?;
+        Ok(append_bytes_le(buf, offset, reduced_bytes.as_bytes()))
+    }
+
+    pub fn append_vec(&mut self, v: &mut Vec<u8>, obs: &Bound<'_, PyAny>) -> PyResult<()> {
+        let reduced_bytes = self.share(obs)?;
+        append_bytes_le_vec(v, reduced_bytes.as_bytes());
+        Ok(())
+    }
+}
+
+// Rebuilds a tensor from the IPC handle written by CudaIpcObsWriter::append
+pub fn retrieve_cuda_ipc_obs<'py>(
+    py: Python<'py>,
+    buf: &[u8],
+    offset: usize,
+) -> PyResult<(Bound<'py, PyAny>, usize)> {
+    let (reduced_bytes, offset) = retrieve_bytes_le(buf, offset)?;
+    let reduced = pickle(py)?
+        .call_method1(intern!(py, "loads"), (PyBytes::new(py, reduced_bytes),))?
+        .downcast_into::<PyTuple>()?;
+    let (rebuild_fn, args) = reduced.extract::<(Bound<'py, PyAny>, Bound<'py, PyTuple>)>()?;
+    Ok((rebuild_fn.call1(args)?, offset))
+}
@@ -9,22 +9,27 @@ use pyany_serde::{
 #[pyclass]
 #[derive(Clone, Debug)]
 pub enum EnvActionResponse {
-    #[pyo3(constructor = (shared_info_setter = None, send_state = false))]
+    #[pyo3(constructor = (shared_info_setter = None, send_state = false, send_shared_info = true, send_obs = true))]
     STEP {
         shared_info_setter: Option<PyObject>,
         send_state: bool,
+        send_shared_info: bool,
+        send_obs: bool,
     },
-    #[pyo3(constructor = (shared_info_setter = None, send_state = false))]
+    #[pyo3(constructor = (shared_info_setter = None, send_state = false, send_shared_info = true))]
     RESET {
         shared_info_setter: Option<PyObject>,
         send_state: bool,
+        send_shared_info: bool,
     },
-    #[pyo3(constructor = (desired_state, shared_info_setter = None, send_state = false, prev_timestep_id_dict = None))]
+    #[pyo3(constructor = (desired_state, shared_info_setter = None, send_state = false, prev_timestep_id_dict = None, send_shared_info = true, rng_state = None))]
     SET_STATE {
         desired_state: PyObject,
         shared_info_setter: Option<PyObject>,
         send_state: bool,
         prev_timestep_id_dict: Option<PyObject>,
+        send_shared_info: bool,
+        rng_state: Option<PyObject>,
     },
 }
 
@@ -37,6 +42,118 @@ pub enum EnvActionResponseType {
     SET_STATE,
 }
 
+// How rewards from each env step of a macro-action or frame skip are combined into the reward that
+// is sent
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroRewardAggregation {
+    SUM,
+    LAST,
+    MEAN,
+    MAX,
+}
+
+// How the actions of all agents are passed to the env's step. STACKED_TENSOR passes a single tensor
+// with the actions of all agents stacked along the first dimension, in agent id list order, for envs
+// which don't take a dict keyed by agent id.
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActionInterface {
+    DICT,
+    STACKED_TENSOR,
+}
+
+// What to do with a reward outside of the configured reward bounds. Rewards which are NaN always
+// raise an error, since they can't be clamped.
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum RewardBoundsPolicy {
+    ERROR,
+    CLAMP,
+}
+
+// Why a reset-style message was written. Resets which follow a step where any agent terminated or
+// truncated are attributed to the end of that episode rather than to an explicit reset.
+// KEEPALIVE_RESET is written in response to a STEP when the env was reset while idle.
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum EpisodeBoundaryReason {
+    EXPLICIT_RESET,
+    SET_STATE,
+    TERMINATED_AUTORESET,
+    TRUNCATED_AUTORESET,
+    KEEPALIVE_RESET,
+}
+
+// Where env_process reads the per-agent done reasons from. Either way, the source is a dict from
+// agent id to an int done reason in 0..=255 whose meaning is up to the env, with 0 used for agents
+// missing from the dict.
+#[allow(non_camel_case_types)]
+#[pyclass(module = "rlgym_learn")]
+#[derive(Clone, Debug)]
+pub enum DoneReasonSource {
+    // An attribute of the env
+    ATTRIBUTE { name: String },
+    // An entry of the env's shared info
+    SHARED_INFO { key: String },
+}
+
+// Where env_process reads the per-agent legal action masks from. Either way, the source is a dict
+// from agent id to the legal action mask of that agent for its current obs, which is written with the
+// legal action mask serde.
+#[allow(non_camel_case_types)]
+#[pyclass(module = "rlgym_learn")]
+#[derive(Clone, Debug)]
+pub enum LegalActionMaskSource {
+    // An attribute of the env
+    ATTRIBUTE { name: String },
+    // An entry of the env's shared info
+    SHARED_INFO { key: String },
+}
+
+pub fn append_episode_boundary_reason(
+    buf: &mut [u8],
+    offset: usize,
+    reason: &EpisodeBoundaryReason,
+) -> usize {
+    buf[offset] = match reason {
+        EpisodeBoundaryReason::EXPLICIT_RESET => 0,
+        EpisodeBoundaryReason::SET_STATE => 1,
+        EpisodeBoundaryReason::TERMINATED_AUTORESET => 2,
+        EpisodeBoundaryReason::TRUNCATED_AUTORESET => 3,
+        EpisodeBoundaryReason::KEEPALIVE_RESET => 4,
+    };
+    offset + 1
+}
+
+pub fn append_episode_boundary_reason_vec(v: &mut Vec<u8>, reason: &EpisodeBoundaryReason) {
+    v.push(0);
+    let offset = v.len() - 1;
+    append_episode_boundary_reason(v, offset, reason);
+}
+
+pub fn retrieve_episode_boundary_reason(
+    buf: &[u8],
+    offset: usize,
+) -> PyResult<(EpisodeBoundaryReason, usize)> {
+    let reason = match buf[offset] {
+        0 => Ok(EpisodeBoundaryReason::EXPLICIT_RESET),
+        1 => Ok(EpisodeBoundaryReason::SET_STATE),
+        2 => Ok(EpisodeBoundaryReason::TERMINATED_AUTORESET),
+        3 => Ok(EpisodeBoundaryReason::TRUNCATED_AUTORESET),
+        4 => Ok(EpisodeBoundaryReason::KEEPALIVE_RESET),
+        v => Err(InvalidStateError::new_err(format!(
+            "tried to retrieve episode boundary reason from shared_memory but got value {}",
+            v
+        ))),
+    }?;
+    Ok((reason, offset + 1))
+}
+
 #[pymethods]
 impl EnvActionResponse {
     #[getter]
@@ -90,49 +207,138 @@ impl EnvActionResponse {
 #[pyclass]
 #[derive(Clone, Debug)]
 pub enum EnvAction {
+    // With send_obs = false, the env is stepped as usual but the response omits all observations
+    // (including critic obs, global obs and legal action masks), for rollouts which only need the
+    // rewards and dones
+    #[pyo3(constructor = (shared_info_setter_option, send_state, action_list, action_associated_learning_data, send_shared_info = true, send_obs = true))]
     STEP {
         shared_info_setter_option: Option<PyObject>,
         send_state: bool,
         action_list: Py<PyList>,
         action_associated_learning_data: PyObject,
+        send_shared_info: bool,
+        send_obs: bool,
     },
+    #[pyo3(constructor = (shared_info_setter_option, send_state, send_shared_info = true))]
     RESET {
         shared_info_setter_option: Option<PyObject>,
         send_state: bool,
+        send_shared_info: bool,
     },
+    // The rng state, if provided, is restored with the env's set_rng_state method after setting the
+    // state, for an exact reproduction of a captured snapshot
+    #[pyo3(constructor = (desired_state, shared_info_setter_option, send_state, prev_timestep_id_dict_option, send_shared_info = true, rng_state_option = None))]
     SET_STATE {
         desired_state: PyObject,
         shared_info_setter_option: Option<PyObject>,
         send_state: bool,
         prev_timestep_id_dict_option: Option<PyObject>,
+        send_shared_info: bool,
+        rng_state_option: Option<PyObject>,
     },
 }
 
+impl EnvAction {
+    // Whether the env process should read and serialize the env's shared info in its response to
+    // this env action
+    pub fn send_shared_info(&self) -> bool {
+        match self {
+            EnvAction::STEP {
+                send_shared_info, ..
+            } => *send_shared_info,
+            EnvAction::RESET {
+                send_shared_info, ..
+            } => *send_shared_info,
+            EnvAction::SET_STATE {
+                send_shared_info, ..
+            } => *send_shared_info,
+        }
+    }
+
+    // Whether the env process should serialize observations in its response to this env action.
+    // Responses to RESET and SET_STATE always include observations, since they start an episode.
+    pub fn send_obs(&self) -> bool {
+        match self {
+            EnvAction::STEP { send_obs, .. } => *send_obs,
+            EnvAction::RESET { .. } | EnvAction::SET_STATE { .. } => true,
+        }
+    }
+}
+
+#[pymethods]
+impl EnvAction {
+    // Convenience constructor which builds a STEP env action by pulling exactly n_agents actions from
+    // an iterable. The actions are collected eagerly into the action list here, since the env
+    // process is sent the whole list.
+    #[staticmethod]
+    #[pyo3(signature = (shared_info_setter_option, send_state, action_iter, n_agents, action_associated_learning_data, send_shared_info = true, send_obs = true))]
+    pub fn step_from_iter<'py>(
+        shared_info_setter_option: Option<PyObject>,
+        send_state: bool,
+        action_iter: Bound<'py, PyAny>,
+        n_agents: usize,
+        action_associated_learning_data: PyObject,
+        send_shared_info: bool,
+        send_obs: bool,
+    ) -> PyResult<Self> {
+        let py = action_iter.py();
+        let mut action_list = Vec::with_capacity(n_agents);
+        let mut action_iter = action_iter.try_iter()?;
+        for idx in 0..n_agents {
+            action_list.push(action_iter.next().ok_or_else(|| {
+                InvalidStateError::new_err(format!(
+                    "Tried to pull {} actions from the action iterable, but it was exhausted after {}",
+                    n_agents, idx
+                ))
+            })??);
+        }
+        Ok(EnvAction::STEP {
+            shared_info_setter_option,
+            send_state,
+            action_list: PyList::new(py, action_list)?.unbind(),
+            action_associated_learning_data,
+            send_shared_info,
+            send_obs,
+        })
+    }
+}
+
+// The serdes used to write and read env actions. The optional serdes are only needed for env actions
+// which carry the corresponding values.
+pub struct EnvActionSerdes<'a> {
+    pub action_serde: &'a mut Box<dyn PyAnySerde>,
+    pub shared_info_setter_serde_option: Option<&'a mut Box<dyn PyAnySerde>>,
+    pub state_serde_option: Option<&'a mut Box<dyn PyAnySerde>>,
+    pub rng_state_serde_option: Option<&'a mut Box<dyn PyAnySerde>>,
+}
+
 pub fn append_env_action<'py>(
     py: Python<'py>,
     buf: &mut [u8],
     mut offset: usize,
     env_action: &EnvAction,
-    action_serde: &mut Box<dyn PyAnySerde>,
-    shared_info_setter_serde_option: &mut Option<&mut Box<dyn PyAnySerde>>,
-    state_serde_option: &mut Option<&mut Box<dyn PyAnySerde>>,
+    serdes: &mut EnvActionSerdes,
 ) -> PyResult<usize> {
     match env_action {
         EnvAction::STEP {
             shared_info_setter_option,
             send_state,
             action_list,
+            send_shared_info,
+            send_obs,
             ..
         } => {
             buf[offset] = 0;
             offset += 1;
             offset = append_bool(buf, offset, *send_state);
+            offset = append_bool(buf, offset, *send_shared_info);
+            offset = append_bool(buf, offset, *send_obs);
             offset = append_python_option(
                 py,
                 buf,
                 offset,
                 &shared_info_setter_option.as_ref(),
-                shared_info_setter_serde_option,
+                &mut serdes.shared_info_setter_serde_option,
                 || {
                     InvalidStateError::new_err(
                     "Received STEP EnvAction with shared_info_setter, but no shared_info_setter serde was provided",
@@ -141,22 +347,31 @@ pub fn append_env_action<'py>(
             )?;
             let action_list = action_list.bind(py);
             for action in action_list.iter() {
-                offset = action_serde.append(buf, offset, &action)?;
+                offset = serdes.action_serde.append(buf, offset, &action)?;
             }
         }
         EnvAction::RESET {
             shared_info_setter_option,
             send_state,
+            send_shared_info,
         } => {
+            // Fail here rather than in the env process, which would otherwise only discover the
+            // missing state serde after resetting the env
+            if *send_state && serdes.state_serde_option.is_none() {
+                return Err(InvalidStateError::new_err(
+                    "Received RESET EnvAction from agent controllers with send_state = true, but no state serde was provided",
+                ));
+            }
             buf[offset] = 1;
             offset += 1;
             offset = append_bool(buf, offset, *send_state);
+            offset = append_bool(buf, offset, *send_shared_info);
             offset = append_python_option(
                 py,
                 buf,
                 offset,
                 &shared_info_setter_option.as_ref(),
-                shared_info_setter_serde_option,
+                &mut serdes.shared_info_setter_serde_option,
                 || {
                     InvalidStateError::new_err(
                     "Received RESET EnvAction from agent controllers with shared_info_setter, but no shared_info_setter serde was provided",
@@ -168,12 +383,15 @@ pub fn append_env_action<'py>(
             desired_state,
             shared_info_setter_option,
             send_state,
+            send_shared_info,
+            rng_state_option,
             ..
         } => {
             buf[offset] = 2;
             offset += 1;
             offset = append_bool(buf, offset, *send_state);
-            offset = state_serde_option.as_deref_mut()
+            offset = append_bool(buf, offset, *send_shared_info);
+            offset = serdes.state_serde_option.as_deref_mut()
                 .ok_or_else(|| {
                     InvalidStateError::new_err(
                         "Received SET_STATE EnvAction from agent controllers but no state serde was provided",
@@ -185,13 +403,25 @@ pub fn append_env_action<'py>(
                 buf,
                 offset,
                 &shared_info_setter_option.as_ref(),
-                shared_info_setter_serde_option,
+                &mut serdes.shared_info_setter_serde_option,
                 || {
                     InvalidStateError::new_err(
                         "Received SET_STATE EnvAction from agent controllers with shared_info_setter, but no shared_info_setter serde was provided",
                     )
                 },
             )?;
+            offset = append_python_option(
+                py,
+                buf,
+                offset,
+                &rng_state_option.as_ref(),
+                &mut serdes.rng_state_serde_option,
+                || {
+                    InvalidStateError::new_err(
+                        "Received SET_STATE EnvAction from agent controllers with an rng state, but no rng state serde was provided",
+                    )
+                },
+            )?;
         }
     }
     Ok(offset)
@@ -202,9 +432,7 @@ pub fn retrieve_env_action<'py>(
     buf: &mut [u8],
     offset: usize,
     n_actions: usize,
-    action_serde: &mut Box<dyn PyAnySerde>,
-    shared_info_setter_serde_option: &mut Option<&mut Box<dyn PyAnySerde>>,
-    state_serde_option: &mut Option<&mut Box<dyn PyAnySerde>>,
+    serdes: &mut EnvActionSerdes,
 ) -> PyResult<(EnvAction, usize)> {
     let env_action_type = buf[offset];
     let mut offset = offset + 1;
@@ -212,12 +440,16 @@ pub fn retrieve_env_action<'py>(
         0 => {
             let send_state;
             (send_state, offset) = retrieve_bool(buf, offset)?;
+            let send_shared_info;
+            (send_shared_info, offset) = retrieve_bool(buf, offset)?;
+            let send_obs;
+            (send_obs, offset) = retrieve_bool(buf, offset)?;
             let shared_info_setter_option;
             (shared_info_setter_option, offset) = retrieve_python_option(
                 py,
                 buf,
                 offset,
-                shared_info_setter_serde_option,
+                &mut serdes.shared_info_setter_serde_option,
                 || {
                     InvalidStateError::new_err(
                     "Received STEP EnvAction in env process with shared_info_setter, but no shared_info_setter serde was provided",
@@ -227,7 +459,7 @@ pub fn retrieve_env_action<'py>(
             let mut action_list = Vec::with_capacity(n_actions);
             for _ in 0..n_actions {
                 let action;
-                (action, offset) = action_serde.retrieve(py, buf, offset)?;
+                (action, offset) = serdes.action_serde.retrieve(py, buf, offset)?;
                 action_list.push(action);
             }
             Ok((
@@ -237,6 +469,8 @@ pub fn retrieve_env_action<'py>(
                     action_list: pyo3::types::PyList::new(py, action_list)?.unbind(),
                     action_associated_learning_data: pyo3::types::PyNone::get(py)
                         .into_py_any(py)?,
+                    send_shared_info,
+                    send_obs,
                 },
                 offset,
             ))
@@ -244,12 +478,14 @@ pub fn retrieve_env_action<'py>(
         1 => {
             let send_state;
             (send_state, offset) = retrieve_bool(buf, offset)?;
+            let send_shared_info;
+            (send_shared_info, offset) = retrieve_bool(buf, offset)?;
             let shared_info_setter_option;
             (shared_info_setter_option, offset) = retrieve_python_option(
                 py,
                 buf,
                 offset,
-                shared_info_setter_serde_option,
+                &mut serdes.shared_info_setter_serde_option,
                 || {
                     InvalidStateError::new_err(
                     "Received RESET EnvAction in env process with shared_info_setter, but no shared_info_setter serde was provided",
@@ -260,6 +496,7 @@ pub fn retrieve_env_action<'py>(
                 EnvAction::RESET {
                     shared_info_setter_option: shared_info_setter_option.map(|v| v.unbind()),
                     send_state,
+                    send_shared_info,
                 },
                 offset,
             ))
@@ -267,8 +504,10 @@ pub fn retrieve_env_action<'py>(
         2 => {
             let send_state;
             (send_state, offset) = retrieve_bool(buf, offset)?;
+            let send_shared_info;
+            (send_shared_info, offset) = retrieve_bool(buf, offset)?;
             let state;
-            (state, offset) = state_serde_option.as_deref_mut()
+            (state, offset) = serdes.state_serde_option.as_deref_mut()
                 .ok_or_else(|| {
                     InvalidStateError::new_err(
                         "Received SET_STATE EnvAction in env process but no state serde was provided",
@@ -280,19 +519,33 @@ pub fn retrieve_env_action<'py>(
                 py,
                 buf,
                 offset,
-                shared_info_setter_serde_option,
+                &mut serdes.shared_info_setter_serde_option,
                 || {
                     InvalidStateError::new_err(
                         "Received SET_STATE EnvAction in env process with shared_info_setter, but no shared_info_setter serde was provided",
                     )
                 },
             )?;
+            let rng_state_option;
+            (rng_state_option, offset) = retrieve_python_option(
+                py,
+                buf,
+                offset,
+                &mut serdes.rng_state_serde_option,
+                || {
+                    InvalidStateError::new_err(
+                        "Received SET_STATE EnvAction in env process with an rng state, but no rng state serde was provided",
+                    )
+                },
+            )?;
             Ok((
                 EnvAction::SET_STATE {
                     desired_state: state.unbind(),
                     shared_info_setter_option: shared_info_setter_option.map(|v| v.unbind()),
                     prev_timestep_id_dict_option: None,
                     send_state,
+                    send_shared_info,
+                    rng_state_option: rng_state_option.map(|v| v.unbind()),
                 },
                 offset,
             ))
@@ -1,17 +1,59 @@
-use pyany_serde::communication::{append_bool, append_usize};
-use pyany_serde::{DynPyAnySerdeOption, PyAnySerde};
+use pyany_serde::communication::{append_bool, append_bool_vec, retrieve_bool};
+use pyany_serde::PyAnySerde;
 use pyo3::exceptions::asyncio::InvalidStateError;
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyTuple};
+use pyo3::types::{PyDict, PyFloat, PyList, PyTuple};
 use pyo3::{intern, PyAny, Python};
-use raw_sync::events::{Event, EventInit, EventState};
+use raw_sync::events::{Event, EventImpl, EventInit, EventState};
 use raw_sync::Timeout;
-use shared_memory::ShmemConf;
+use shared_memory::{ShmemConf, ShmemError};
+use std::cmp::min;
+use std::collections::{HashSet, VecDeque};
+use std::mem::size_of;
+use std::path::Path;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::env_action::{retrieve_env_action, EnvAction};
-use crate::synchronization::{get_flink, recvfrom_byte, retrieve_header, sendto_byte, Header};
+use crate::action_space_descriptor::{append_action_space_descriptor, ActionSpaceDescriptor};
+use crate::agent_obs_serdes::{select_obs_serde, AgentObsSerdes};
+use crate::arrow_ipc::{ArrowObsWriter, ObsFormat};
+use crate::buffer_layout::{BufferLayout, BufferLayoutConfig};
+use crate::communication::{
+    append_agent_id_fields_vec, append_alignment_padding_vec, append_bitset_vec, append_bytes_le,
+    append_bytes_le_vec, append_u64_le_vec, append_usize_le, append_usize_le_vec, fnv1a_64,
+    retrieve_bytes_le, retrieve_serde_option, retrieve_usize_le,
+};
+use crate::cuda_ipc::{is_cuda_tensor, CudaIpcObsWriter};
+use crate::env_action::{
+    append_episode_boundary_reason_vec, retrieve_env_action, ActionInterface, DoneReasonSource,
+    EnvAction, EnvActionSerdes, EpisodeBoundaryReason, LegalActionMaskSource,
+    MacroRewardAggregation, RewardBoundsPolicy,
+};
+use crate::env_process_options::{
+    AgentOptions, BufferOptions, DebugOptions, EpisodeOptions, ObsOptions, RenderOptions,
+    SerdeOptions, StepOptions,
+};
+use crate::fixed_layout::FixedLayoutObsWriter;
+use crate::memory_watch::MemoryWatch;
+use crate::message_trace::{MessageTraceMode, MessageTraceReader, MessageTraceWriter};
+use crate::misc::{
+    jax_to_numpy, set_current_thread_name, torch_cast_float, torch_cat, torch_stack, TorchDtype,
+};
+use crate::obs_file::ObsFileWriter;
+use crate::offset_tracker::{write_message, OffsetTracker};
+use crate::output_capture::install_output_capture;
+use crate::partitioned_buffer::{append_base_offset_option, PartitionedBuffer, ShmemRegion};
+use crate::render_clock::{RenderClock, RenderSync};
+use crate::render_thread::RenderThread;
+use crate::serde_version::{
+    extract_serde_option, SerdeVersionList, VersionedSerde, VersionedSerdeOption,
+};
+use crate::state_pool::StatePool;
+use crate::synchronization::{
+    get_flink, get_resized_flink, recvfrom_byte, retrieve_header, send_buffer_truncated,
+    send_error_report, send_header_not_supported, sendto_byte, Header,
+};
+use crate::transport::{MessageTransport, MqueueTransport, ShmemTransport};
 
 fn sync_with_epi<'py>(socket: &Bound<'py, PyAny>, address: &Bound<'py, PyAny>) -> PyResult<()> {
     sendto_byte(socket, address)?;
@@ -19,12 +61,201 @@ fn sync_with_epi<'py>(socket: &Bound<'py, PyAny>, address: &Bound<'py, PyAny>) -
     Ok(())
 }
 
+// Whether creating a shmem failed because the shared memory quota is exhausted, which shows up as
+// ENOSPC from either shm_open or the ftruncate which sizes the mapping
+fn is_no_space_error(err: &ShmemError) -> bool {
+    match err {
+        ShmemError::MapCreateFailed(errno) | ShmemError::UnknownOsError(errno) => {
+            *errno == libc::ENOSPC as u32
+        }
+        _ => false,
+    }
+}
+
+// Creates a shared memory buffer at the flink, along with the event the parent signals through it.
+// Returns the buffer, the event and the number of bytes used by the event.
+fn create_shmem(
+    proc_id: &str,
+    flink: &str,
+    size: usize,
+) -> PyResult<(ShmemRegion, Box<dyn EventImpl>, usize)> {
+    let shmem = ShmemConf::new()
+        .size(size)
+        .flink(flink)
+        .create()
+        .map_err(|err| {
+            if is_no_space_error(&err) {
+                InvalidStateError::new_err(format!(
+                    "Env process {} was unable to create its shared memory buffer of {} bytes because the shared memory quota is exhausted (no space left on device). Increase the size of /dev/shm (e.g. --shm-size for docker containers), reduce the number of env processes, or lower the buffer size",
+                    proc_id, size
+                ))
+            } else {
+                InvalidStateError::new_err(format!(
+                    "Unable to create shmem flink {}: {}",
+                    flink, err
+                ))
+            }
+        })?;
+    create_event(ShmemRegion::new(shmem))
+}
+
+// Creates the event the parent signals through the buffer at its start. Returns the buffer, the
+// event and the number of bytes used by the event.
+fn create_event(shmem: ShmemRegion) -> PyResult<(ShmemRegion, Box<dyn EventImpl>, usize)> {
+    let (epi_evt, used_bytes) = unsafe {
+        Event::new(shmem.as_ptr(), true).map_err(|err| {
+            InvalidStateError::new_err(format!(
+                "Failed to create event from epi to this process: {}",
+                err
+            ))
+        })?
+    };
+    Ok((shmem, epi_evt, used_bytes))
+}
+
 fn env_reset<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<Bound<'py, PyDict>> {
     Ok(env
         .call_method0(intern!(env.py(), "reset"))?
         .downcast_into()?)
 }
 
+// The interval at which the sub-second remainder of a wait_for_signal timeout is polled
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Waits for the event with a timeout of whole seconds (or zero), returning false if the timeout
+// elapsed first
+fn wait_whole_secs(evt: &dyn EventImpl, timeout: Duration) -> PyResult<bool> {
+    let wait_start = Instant::now();
+    match evt.wait(Timeout::Val(timeout)) {
+        Ok(()) => Ok(true),
+        // raw_sync doesn't distinguish a timeout from other wait errors
+        Err(_) if wait_start.elapsed() >= timeout => Ok(false),
+        Err(err) => Err(InvalidStateError::new_err(err.to_string())),
+    }
+}
+
+// Waits for the event to be signaled, returning false if the timeout elapsed first. raw_sync doesn't
+// normalize the nanoseconds of the deadline it computes, and the wait fails immediately if they
+// overflow, so only the whole seconds of the timeout are waited on directly. The remainder is
+// polled with zero timeout waits until the deadline.
+fn wait_for_signal(evt: &dyn EventImpl, timeout_option: Option<Duration>) -> PyResult<bool> {
+    let Some(timeout) = timeout_option else {
+        evt.wait(Timeout::Infinite)
+            .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+        return Ok(true);
+    };
+    let deadline = Instant::now() + timeout;
+    if timeout.as_secs() > 0 && wait_whole_secs(evt, Duration::from_secs(timeout.as_secs()))? {
+        return Ok(true);
+    }
+    loop {
+        if wait_whole_secs(evt, Duration::ZERO)? {
+            return Ok(true);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        std::thread::sleep(remaining.min(SIGNAL_POLL_INTERVAL));
+    }
+}
+
+// The number of times the event transitions are exercised by the sync self-check
+const SYNC_SELFCHECK_ROUNDS: usize = 3;
+
+// Exercises the event before the parent starts using it, checking that a signal is observed exactly
+// once by an auto-reset wait and that a cleared event is not observed as signaled. A wait with a zero
+// timeout returns immediately, reporting whether the event was signaled. Any unexpected transition
+// is logged as a warning, since it means the platform's event implementation can miss or duplicate
+// wakeups.
+fn run_sync_selfcheck(evt: &dyn EventImpl, proc_id: &str) -> PyResult<()> {
+    let try_wait = || evt.wait(Timeout::Val(Duration::ZERO)).is_ok();
+    let set = |state| {
+        evt.set(state).map_err(|err| {
+            InvalidStateError::new_err(format!(
+                "Env process {} failed to set the event during the sync self-check: {}",
+                proc_id, err
+            ))
+        })
+    };
+    let mut failure_list = Vec::new();
+    for round in 0..SYNC_SELFCHECK_ROUNDS {
+        set(EventState::Clear)?;
+        if try_wait() {
+            failure_list.push(format!(
+                "round {}: a cleared event was observed as signaled",
+                round
+            ));
+        }
+        set(EventState::Signaled)?;
+        if !try_wait() {
+            failure_list.push(format!(
+                "round {}: a signaled event was not observed",
+                round
+            ));
+        }
+        if try_wait() {
+            failure_list.push(format!(
+                "round {}: a signaled event was observed twice despite auto-reset",
+                round
+            ));
+        }
+    }
+    set(EventState::Clear)?;
+    if !failure_list.is_empty() {
+        println!(
+            "Env process {} found the shared memory event unreliable during the sync self-check, which can cause missed wakeups or hangs: {}",
+            proc_id,
+            failure_list.join("; ")
+        );
+    }
+    Ok(())
+}
+
+// raw_sync events aren't Sync, but the event is only ever waited on by the env process' main thread,
+// which allows waiting on it with the GIL released so that other threads (like the render thread)
+// can run in the meantime
+struct UngilEvent<'a>(&'a dyn EventImpl);
+
+unsafe impl Sync for UngilEvent<'_> {}
+
+impl UngilEvent<'_> {
+    fn wait(&self, timeout_option: Option<Duration>) -> PyResult<bool> {
+        wait_for_signal(self.0, timeout_option)
+    }
+}
+
+// Each obs chunk message is the chunk index, the number of chunks and the length prefixed chunk
+const OBS_CHUNK_HEADER_LEN: usize = 3 * size_of::<u64>();
+
+// Sends the serialized obs of a message in chunks of at most obs_chunk_size bytes, each written as
+// its own message. The parent signals the event once it has read the previous message, so that each
+// chunk can reuse the same region of the buffer. At least one chunk is always sent.
+fn send_obs_chunks<'py>(
+    py: Python<'py>,
+    evt: &dyn EventImpl,
+    shm_slice: &mut [u8],
+    obs_bytes: &[u8],
+    obs_chunk_size: usize,
+    socket: &Bound<'py, PyAny>,
+    address: &Bound<'py, PyAny>,
+) -> PyResult<()> {
+    let n_chunks = obs_bytes.len().div_ceil(obs_chunk_size).max(1);
+    for chunk_idx in 0..n_chunks {
+        let ungil_evt = UngilEvent(evt);
+        py.allow_threads(|| ungil_evt.wait(None))?;
+        evt.set(EventState::Clear)
+            .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+        let start = chunk_idx * obs_chunk_size;
+        let end = min(start + obs_chunk_size, obs_bytes.len());
+        let mut offset = append_usize_le(shm_slice, 0, chunk_idx);
+        offset = append_usize_le(shm_slice, offset, n_chunks);
+        append_bytes_le(shm_slice, offset, &obs_bytes[start..end]);
+        sendto_byte(socket, address)?;
+    }
+    Ok(())
+}
+
 fn env_set_state<'py>(
     env: &'py Bound<'py, PyAny>,
     desired_state: &Bound<'py, PyAny>,
@@ -39,14 +270,149 @@ fn env_render<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<()> {
     Ok(())
 }
 
+fn env_seed<'py>(env: &'py Bound<'py, PyAny>, proc_id: &str, seed: usize) -> PyResult<()> {
+    let py = env.py();
+    if !env.hasattr(intern!(py, "seed"))? {
+        return Err(InvalidStateError::new_err(format!(
+            "Env process {} received a SetSeed request, but the env has no seed method",
+            proc_id
+        )));
+    }
+    env.call_method1(intern!(py, "seed"), (seed,))?;
+    Ok(())
+}
+
+fn env_build_obs<'py>(env: &'py Bound<'py, PyAny>, proc_id: &str) -> PyResult<Bound<'py, PyDict>> {
+    let py = env.py();
+    if !env.hasattr(intern!(py, "build_obs"))? {
+        return Err(InvalidStateError::new_err(format!(
+            "Env process {} received an Observe request, but the env has no build_obs method",
+            proc_id
+        )));
+    }
+    Ok(env
+        .call_method0(intern!(py, "build_obs"))?
+        .downcast_into()?)
+}
+
+fn env_build_critic_obs<'py>(
+    env: &'py Bound<'py, PyAny>,
+    proc_id: &str,
+) -> PyResult<Bound<'py, PyDict>> {
+    let py = env.py();
+    if !env.hasattr(intern!(py, "build_critic_obs"))? {
+        return Err(InvalidStateError::new_err(format!(
+            "Env process {} was given a critic obs serde, but the env has no build_critic_obs method",
+            proc_id
+        )));
+    }
+    Ok(env
+        .call_method0(intern!(py, "build_critic_obs"))?
+        .downcast_into()?)
+}
+
 fn env_shared_info<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
     env.getattr(intern!(env.py(), "shared_info"))
 }
 
+// Applies the shared info setter to the env's shared info. If an allow-list of keys is provided, only
+// those keys are taken from the setter and the rest of the setter is ignored.
+fn apply_shared_info_setter<'py>(
+    env: &'py Bound<'py, PyAny>,
+    shared_info_setter: &Bound<'py, PyDict>,
+    shared_info_setter_keys_option: &Option<Vec<String>>,
+) -> PyResult<()> {
+    let shared_info = env_shared_info(env)?.downcast_into::<PyDict>()?;
+    if let Some(shared_info_setter_keys) = shared_info_setter_keys_option {
+        for key in shared_info_setter_keys.iter() {
+            if let Some(value) = shared_info_setter.get_item(key)? {
+                shared_info.set_item(key, value)?;
+            }
+        }
+    } else {
+        shared_info.update(shared_info_setter.as_mapping())?;
+    }
+    Ok(())
+}
+
+fn env_reward_components<'py>(
+    env: &'py Bound<'py, PyAny>,
+    proc_id: &str,
+) -> PyResult<Bound<'py, PyDict>> {
+    let py = env.py();
+    if !env.hasattr(intern!(py, "reward_components"))? {
+        return Err(InvalidStateError::new_err(format!(
+            "Env process {} was given a reward component serde, but the env has no reward_components attribute",
+            proc_id
+        )));
+    }
+    Ok(env
+        .getattr(intern!(py, "reward_components"))?
+        .downcast_into()?)
+}
+
+// The env's config attribute, or its metadata attribute if it has no config attribute
+fn env_config<'py>(env: &Bound<'py, PyAny>) -> PyResult<Option<Bound<'py, PyAny>>> {
+    let py = env.py();
+    for attr_name in [intern!(py, "config"), intern!(py, "metadata")] {
+        if env.hasattr(attr_name)? {
+            return Ok(Some(env.getattr(attr_name)?));
+        }
+    }
+    Ok(None)
+}
+
+fn env_done_reasons<'py>(
+    env: &'py Bound<'py, PyAny>,
+    done_reason_source: &DoneReasonSource,
+) -> PyResult<Bound<'py, PyDict>> {
+    Ok(match done_reason_source {
+        DoneReasonSource::ATTRIBUTE { name } => env.getattr(name)?,
+        DoneReasonSource::SHARED_INFO { key } => env_shared_info(env)?.get_item(key)?,
+    }
+    .downcast_into()?)
+}
+
+fn env_legal_action_masks<'py>(
+    env: &'py Bound<'py, PyAny>,
+    legal_action_mask_source: &LegalActionMaskSource,
+) -> PyResult<Bound<'py, PyDict>> {
+    Ok(match legal_action_mask_source {
+        LegalActionMaskSource::ATTRIBUTE { name } => env.getattr(name)?,
+        LegalActionMaskSource::SHARED_INFO { key } => env_shared_info(env)?.get_item(key)?,
+    }
+    .downcast_into()?)
+}
+
 fn env_state<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
     env.getattr(intern!(env.py(), "state"))
 }
 
+// Returns the env's rng state, or None if the env doesn't expose one
+fn env_rng_state<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<Option<Bound<'py, PyAny>>> {
+    let py = env.py();
+    if !env.hasattr(intern!(py, "get_rng_state"))? {
+        return Ok(None);
+    }
+    Ok(Some(env.call_method0(intern!(py, "get_rng_state"))?))
+}
+
+fn check_n_agents(
+    proc_id: &str,
+    expected_n_agents_option: Option<usize>,
+    n_agents: usize,
+) -> PyResult<()> {
+    if let Some(expected_n_agents) = expected_n_agents_option {
+        if n_agents != expected_n_agents {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given an expected agent count of {}, but the env started an episode with {} agents",
+                proc_id, expected_n_agents, n_agents
+            )));
+        }
+    }
+    Ok(())
+}
+
 fn env_obs_spaces<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<Bound<'py, PyDict>> {
     Ok(env
         .getattr(intern!(env.py(), "observation_spaces"))?
@@ -59,17 +425,75 @@ fn env_action_spaces<'py>(env: &'py Bound<'py, PyAny>) -> PyResult<Bound<'py, Py
         .downcast_into()?)
 }
 
-fn env_step<'py>(
-    env: &'py Bound<'py, PyAny>,
-    actions_dict: Bound<'py, PyDict>,
-) -> PyResult<(
+fn action_space_sample<'py>(
+    action_space: &Bound<'py, PyAny>,
+    proc_id: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    let py = action_space.py();
+    if !action_space.hasattr(intern!(py, "sample"))? {
+        return Err(InvalidStateError::new_err(format!(
+            "Env process {} received a SampleAction request, but the action space {} has no sample method",
+            proc_id,
+            action_space.repr()?
+        )));
+    }
+    action_space.call_method0(intern!(py, "sample"))
+}
+
+// The obs, reward, terminated and truncated dicts returned by the env's step
+type EnvStepResult<'py> = (
     Bound<'py, PyDict>,
     Bound<'py, PyDict>,
     Bound<'py, PyDict>,
     Bound<'py, PyDict>,
-)> {
+);
+
+// Builds the actions argument to the env's step from the (agent id, action) pairs
+fn build_env_actions<'py>(
+    py: Python<'py>,
+    actions_kv_list: Vec<(&Bound<'py, PyAny>, Bound<'py, PyAny>)>,
+    action_interface: &ActionInterface,
+) -> PyResult<Bound<'py, PyAny>> {
+    match action_interface {
+        ActionInterface::DICT => {
+            Ok(PyDict::from_sequence(&actions_kv_list.into_pyobject(py)?)?.into_any())
+        }
+        ActionInterface::STACKED_TENSOR => torch_stack(
+            py,
+            &actions_kv_list
+                .into_iter()
+                .map(|(_, action)| action)
+                .collect::<Vec<_>>()[..],
+        ),
+    }
+}
+
+// Passes the actions to the pre step fn, if any, and returns the actions it returns. The pre step fn
+// vetoes the step by raising.
+fn apply_pre_step_fn<'py>(
+    pre_step_fn_option: &Option<Bound<'py, PyAny>>,
+    actions: Bound<'py, PyAny>,
+    proc_id: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    match pre_step_fn_option {
+        Some(pre_step_fn) => pre_step_fn.call1((actions,)).map_err(|err| {
+            let veto_err = InvalidStateError::new_err(format!(
+                "Env process {} had its step vetoed by the pre step fn",
+                proc_id
+            ));
+            veto_err.set_cause(pre_step_fn.py(), Some(err));
+            veto_err
+        }),
+        None => Ok(actions),
+    }
+}
+
+fn env_step<'py>(
+    env: &'py Bound<'py, PyAny>,
+    actions: Bound<'py, PyAny>,
+) -> PyResult<EnvStepResult<'py>> {
     let result: Bound<'py, PyTuple> = env
-        .call_method1(intern!(env.py(), "step"), (actions_dict,))?
+        .call_method1(intern!(env.py(), "step"), (actions,))?
         .downcast_into()?;
     Ok((
         result.get_item(0)?.downcast_into()?,
@@ -79,6 +503,519 @@ fn env_step<'py>(
     ))
 }
 
+// Resets the env and then steps it settle_steps times with the default action for every agent,
+// returning the obs dict of the last settle step. The obs of the reset and of all but the last
+// settle step are discarded.
+fn env_reset_settled<'py>(
+    env: &'py Bound<'py, PyAny>,
+    settle_steps: usize,
+    default_action: &Bound<'py, PyAny>,
+    action_interface: &ActionInterface,
+) -> PyResult<Bound<'py, PyDict>> {
+    let mut obs_dict = env_reset(env)?;
+    for _ in 0..settle_steps {
+        let agent_id_list = obs_dict.keys().iter().collect::<Vec<_>>();
+        let actions_kv_list = agent_id_list
+            .iter()
+            .map(|agent_id| (agent_id, default_action.clone()))
+            .collect();
+        (obs_dict, _, _, _) = env_step(
+            env,
+            build_env_actions(env.py(), actions_kv_list, action_interface)?,
+        )?;
+    }
+    Ok(obs_dict)
+}
+
+// Steps the env warm_start_steps times with the default action for every agent, discarding the
+// results. The env is reset whenever an episode ends during the warm start.
+fn env_warm_start<'py>(
+    env: &'py Bound<'py, PyAny>,
+    agent_id_list: &[Bound<'py, PyAny>],
+    warm_start_steps: usize,
+    default_action: &Bound<'py, PyAny>,
+    action_interface: &ActionInterface,
+) -> PyResult<()> {
+    let mut agent_id_list = agent_id_list.to_vec();
+    for _ in 0..warm_start_steps {
+        let actions_kv_list = agent_id_list
+            .iter()
+            .map(|agent_id| (agent_id, default_action.clone()))
+            .collect();
+        let (mut obs_dict, _, terminated_dict, truncated_dict) = env_step(
+            env,
+            build_env_actions(env.py(), actions_kv_list, action_interface)?,
+        )?;
+        let mut episode_ended = false;
+        for value in terminated_dict
+            .values()
+            .iter()
+            .chain(truncated_dict.values().iter())
+        {
+            episode_ended |= value.extract::<bool>()?;
+        }
+        if episode_ended {
+            obs_dict = env_reset(env)?;
+        }
+        agent_id_list = obs_dict.keys().iter().collect();
+    }
+    Ok(())
+}
+
+// Steps the env n_sub_steps times with the actions returned for each sub-step. If
+// stop_on_episode_end is set, stepping stops early once any agent terminates or truncates. The
+// obs, terminated and truncated dicts are those of the last env step, and the rewards of all env
+// steps are aggregated per agent. Also returns the number of env steps performed.
+fn env_step_repeated<'py>(
+    env: &'py Bound<'py, PyAny>,
+    n_sub_steps: usize,
+    mut get_actions: impl FnMut(usize) -> PyResult<Bound<'py, PyAny>>,
+    reward_aggregation: &MacroRewardAggregation,
+    stop_on_episode_end: bool,
+) -> PyResult<(EnvStepResult<'py>, usize)> {
+    let mut reward_dict_option: Option<Bound<'py, PyDict>> = None;
+    let mut result_option = None;
+    let mut n_env_steps = 0;
+    for sub_step in 0..n_sub_steps {
+        let (obs_dict, rew_dict, terminated_dict, truncated_dict) =
+            env_step(env, get_actions(sub_step)?)?;
+        n_env_steps += 1;
+        reward_dict_option = Some(match (reward_aggregation, reward_dict_option) {
+            (MacroRewardAggregation::LAST, _) | (_, None) => rew_dict.copy()?,
+            (_, Some(reward_dict)) => {
+                for (agent_id, reward) in rew_dict.iter() {
+                    let aggregate = match reward_dict.get_item(&agent_id)? {
+                        Some(aggregate) => match reward_aggregation {
+                            MacroRewardAggregation::MAX if !reward.gt(&aggregate)? => aggregate,
+                            MacroRewardAggregation::MAX => reward,
+                            _ => aggregate.add(reward)?,
+                        },
+                        None => reward,
+                    };
+                    reward_dict.set_item(agent_id, aggregate)?;
+                }
+                reward_dict
+            }
+        });
+        let mut episode_ended = false;
+        if stop_on_episode_end {
+            for value in terminated_dict
+                .values()
+                .iter()
+                .chain(truncated_dict.values().iter())
+            {
+                episode_ended |= value.extract::<bool>()?;
+            }
+        }
+        result_option = Some((obs_dict, terminated_dict, truncated_dict));
+        if episode_ended {
+            break;
+        }
+    }
+    let (obs_dict, terminated_dict, truncated_dict) = result_option.ok_or_else(|| {
+        InvalidStateError::new_err("Tried to step the env zero times for a single env action")
+    })?;
+    let reward_dict = reward_dict_option.unwrap();
+    if *reward_aggregation == MacroRewardAggregation::MEAN {
+        for (agent_id, total) in reward_dict.iter() {
+            reward_dict.set_item(agent_id, total.div(n_env_steps)?)?;
+        }
+    }
+    Ok((
+        (obs_dict, reward_dict, terminated_dict, truncated_dict),
+        n_env_steps,
+    ))
+}
+
+// Steps the env through each agent's sequence of sub-actions, one sub-action per agent per env step.
+// Agents with shorter sequences repeat their last sub-action. Also returns the number of env steps
+// performed.
+fn env_step_macro<'py>(
+    env: &'py Bound<'py, PyAny>,
+    proc_id: &str,
+    actions_kv_list: Vec<(&Bound<'py, PyAny>, Bound<'py, PyAny>)>,
+    macro_reward_aggregation: &MacroRewardAggregation,
+    pre_step_fn_option: &Option<Bound<'py, PyAny>>,
+) -> PyResult<(EnvStepResult<'py>, usize)> {
+    let py = env.py();
+    let mut sub_actions_kv_list = Vec::with_capacity(actions_kv_list.len());
+    for (agent_id, action_sequence) in actions_kv_list.into_iter() {
+        let sub_actions = action_sequence
+            .try_iter()?
+            .collect::<PyResult<Vec<Bound<'py, PyAny>>>>()?;
+        if sub_actions.is_empty() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} received an empty macro-action for agent id {}",
+                proc_id,
+                agent_id.repr()?
+            )));
+        }
+        sub_actions_kv_list.push((agent_id, sub_actions));
+    }
+    let n_sub_steps = sub_actions_kv_list
+        .iter()
+        .map(|(_, sub_actions)| sub_actions.len())
+        .max()
+        .unwrap_or(1);
+    env_step_repeated(
+        env,
+        n_sub_steps,
+        |sub_step| {
+            let actions_dict = PyDict::new(py);
+            for (agent_id, sub_actions) in sub_actions_kv_list.iter() {
+                actions_dict
+                    .set_item(agent_id, &sub_actions[sub_step.min(sub_actions.len() - 1)])?;
+            }
+            apply_pre_step_fn(pre_step_fn_option, actions_dict.into_any(), proc_id)
+        },
+        macro_reward_aggregation,
+        true,
+    )
+}
+
+// Appends obj with the serde. If the serde fails and a fallback fn is provided, obj is converted
+// by the fallback fn and appended again.
+fn append_with_fallback<'py>(
+    serde: &mut dyn PyAnySerde,
+    buf: &mut [u8],
+    offset: usize,
+    obj: &Bound<'py, PyAny>,
+    serde_fallback_fn_option: &Option<Bound<'py, PyAny>>,
+) -> PyResult<usize> {
+    match (serde.append(buf, offset, obj), serde_fallback_fn_option) {
+        (Err(_), Some(serde_fallback_fn)) => {
+            serde.append(buf, offset, &serde_fallback_fn.call1((obj,))?)
+        }
+        (result, _) => result,
+    }
+}
+
+// The start address is where v will be copied to, if anywhere, which serdes align their data to
+fn append_vec_with_fallback<'py>(
+    serde: &mut dyn PyAnySerde,
+    v: &mut Vec<u8>,
+    start_addr_option: Option<usize>,
+    obj: &Bound<'py, PyAny>,
+    serde_fallback_fn_option: &Option<Bound<'py, PyAny>>,
+) -> PyResult<()> {
+    let start = v.len();
+    match (
+        serde.append_vec(v, start_addr_option, obj),
+        serde_fallback_fn_option,
+    ) {
+        (Err(_), Some(serde_fallback_fn)) => {
+            v.truncate(start);
+            serde.append_vec(v, start_addr_option, &serde_fallback_fn.call1((obj,))?)
+        }
+        (result, _) => result,
+    }
+}
+
+// Appends obs with the obs serde, or writes it to the obs file and appends its handle if the obs
+// file is in use
+fn append_obs<'py>(
+    obs_serde: &mut dyn PyAnySerde,
+    obs_file_writer_option: &mut Option<ObsFileWriter>,
+    cuda_ipc_obs_writer_option: &mut Option<CudaIpcObsWriter>,
+    buf: &mut [u8],
+    mut offset: usize,
+    obs: &Bound<'py, PyAny>,
+    serde_fallback_fn_option: &Option<Bound<'py, PyAny>>,
+) -> PyResult<usize> {
+    // With CUDA IPC obs, each obs is preceded by whether it was shared as a CUDA tensor
+    if let Some(cuda_ipc_obs_writer) = cuda_ipc_obs_writer_option {
+        let is_cuda = is_cuda_tensor(obs)?;
+        offset = append_bool(buf, offset, is_cuda);
+        if is_cuda {
+            return cuda_ipc_obs_writer.append(buf, offset, obs);
+        }
+    }
+    match obs_file_writer_option {
+        Some(obs_file_writer) => {
+            let mut obs_bytes = Vec::new();
+            append_vec_with_fallback(
+                obs_serde,
+                &mut obs_bytes,
+                None,
+                obs,
+                serde_fallback_fn_option,
+            )?;
+            obs_file_writer.append(buf, offset, &obs_bytes[..])
+        }
+        None => append_with_fallback(obs_serde, buf, offset, obs, serde_fallback_fn_option),
+    }
+}
+
+fn append_obs_vec<'py>(
+    obs_serde: &mut dyn PyAnySerde,
+    obs_file_writer_option: &mut Option<ObsFileWriter>,
+    cuda_ipc_obs_writer_option: &mut Option<CudaIpcObsWriter>,
+    v: &mut Vec<u8>,
+    start_addr: usize,
+    obs: &Bound<'py, PyAny>,
+    serde_fallback_fn_option: &Option<Bound<'py, PyAny>>,
+) -> PyResult<()> {
+    if let Some(cuda_ipc_obs_writer) = cuda_ipc_obs_writer_option {
+        let is_cuda = is_cuda_tensor(obs)?;
+        append_bool_vec(v, is_cuda);
+        if is_cuda {
+            return cuda_ipc_obs_writer.append_vec(v, obs);
+        }
+    }
+    match obs_file_writer_option {
+        Some(obs_file_writer) => {
+            let mut obs_bytes = Vec::new();
+            append_vec_with_fallback(
+                obs_serde,
+                &mut obs_bytes,
+                None,
+                obs,
+                serde_fallback_fn_option,
+            )?;
+            obs_file_writer.append_vec(v, &obs_bytes[..])
+        }
+        None => append_vec_with_fallback(
+            obs_serde,
+            v,
+            Some(start_addr),
+            obs,
+            serde_fallback_fn_option,
+        ),
+    }
+}
+
+// Checks that the reward is finite and within the bounds. Rewards outside of the bounds are either
+// rejected or replaced by the nearest bound as a float, depending on the policy.
+fn check_reward_bounds<'py>(
+    reward: Bound<'py, PyAny>,
+    (low, high): (f64, f64),
+    reward_bounds_policy: &RewardBoundsPolicy,
+    proc_id: &str,
+    agent_id: &Bound<'py, PyAny>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let value = reward.extract::<f64>()?;
+    if value >= low && value <= high {
+        return Ok(reward);
+    }
+    if value.is_nan() || *reward_bounds_policy == RewardBoundsPolicy::ERROR {
+        return Err(InvalidStateError::new_err(format!(
+            "Env process {} received a reward of {} for agent id {}, which is outside of the reward bounds ({}, {})",
+            proc_id,
+            value,
+            agent_id.repr()?,
+            low,
+            high
+        )));
+    }
+    Ok(PyFloat::new(reward.py(), value.clamp(low, high)).into_any())
+}
+
+// Sets the element at idx, which may be one past the end of the list
+fn set_or_push<T>(list: &mut Vec<T>, idx: usize, value: T) {
+    if idx < list.len() {
+        list[idx] = value;
+    } else {
+        list.push(value);
+    }
+}
+
+// Pushes obs to the agent's frame history and returns the concatenation of the last frame_stack
+// observations. The history is padded with the first observation of the episode.
+fn stack_obs<'py>(
+    frame_history: &mut VecDeque<PyObject>,
+    obs: Bound<'py, PyAny>,
+    frame_stack: usize,
+) -> PyResult<Bound<'py, PyAny>> {
+    let py = obs.py();
+    if frame_history.is_empty() {
+        frame_history.resize_with(frame_stack - 1, || obs.clone().unbind());
+    }
+    frame_history.push_back(obs.unbind());
+    while frame_history.len() > frame_stack {
+        frame_history.pop_front();
+    }
+    torch_cat(
+        py,
+        &frame_history
+            .iter()
+            .map(|frame| frame.bind(py).clone())
+            .collect::<Vec<_>>()[..],
+    )
+}
+
+// Formats bytes as lines of 16 hex bytes, each prefixed with the buffer offset of its first byte
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            format!(
+                "{:08x}: {}\n",
+                idx * 16,
+                chunk
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+        })
+        .collect()
+}
+
+fn should_render<'py>(
+    render_predicate_fn_option: &Option<Bound<'py, PyAny>>,
+    env: &Bound<'py, PyAny>,
+) -> PyResult<bool> {
+    match render_predicate_fn_option {
+        Some(render_predicate_fn) => render_predicate_fn.call1((env,))?.extract::<bool>(),
+        None => Ok(true),
+    }
+}
+
+// Applies the obs transform fn, if any, to an observation before it is serialized
+fn transform_obs<'py>(
+    obs_transform_fn_option: &Option<Bound<'py, PyAny>>,
+    obs_dtype_cast_option: &Option<TorchDtype>,
+    obs: Bound<'py, PyAny>,
+    proc_id: &str,
+    agent_id: &Bound<'py, PyAny>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let obs = match obs_transform_fn_option {
+        Some(obs_transform_fn) => obs_transform_fn.call1((obs,)).map_err(|err| {
+            let py = agent_id.py();
+            let transform_err = InvalidStateError::new_err(format!(
+                "Env process {} failed to apply the obs transform fn to the obs for agent id {}",
+                proc_id,
+                agent_id
+                    .repr()
+                    .map_or_else(|_| "?".to_owned(), |repr| repr.to_string())
+            ));
+            transform_err.set_cause(py, Some(err));
+            transform_err
+        })?,
+        None => obs,
+    };
+    // JAX arrays are converted to numpy arrays, which the obs serdes support
+    let obs = jax_to_numpy(obs)?;
+    // The cast is applied last so that it also covers float tensors produced by the transform
+    match obs_dtype_cast_option {
+        Some(obs_dtype_cast) => torch_cast_float(obs, obs_dtype_cast),
+        None => Ok(obs),
+    }
+}
+
+// Whether the keys of the dict are exactly the agent ids in the list
+fn agent_keys_match(
+    dict: &Bound<'_, PyDict>,
+    agent_id_list: &[Bound<'_, PyAny>],
+) -> PyResult<bool> {
+    if dict.len() != agent_id_list.len() {
+        return Ok(false);
+    }
+    for agent_id in agent_id_list.iter() {
+        if !dict.contains(agent_id)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Agent ids which compare equal would be indistinguishable once received by the parent, which can
+// happen with custom agent id types whose __eq__ and __hash__ disagree
+fn check_for_duplicate_agent_ids(agent_id_list: &[Bound<'_, PyAny>]) -> PyResult<()> {
+    for (idx, agent_id) in agent_id_list.iter().enumerate() {
+        for other_agent_id in agent_id_list[..idx].iter() {
+            if agent_id.eq(other_agent_id)? {
+                return Err(InvalidStateError::new_err(format!(
+                    "Obs dict contains duplicate agent id {} (it compares equal to agent id {})",
+                    agent_id.repr()?,
+                    other_agent_id.repr()?
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Returns the agent ids of the obs dict, sorted (optionally by a key fn) if requested so that the
+// positional ordering of agents is stable across resets, and then placed in the order given by the
+// agent order permutation if one was provided
+fn get_agent_id_list<'py>(
+    obs_dict: &Bound<'py, PyDict>,
+    sort_agent_ids: bool,
+    agent_id_sort_key_fn_option: &Option<Bound<'py, PyAny>>,
+    agent_order_permutation_option: &Option<Vec<usize>>,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let agent_ids = obs_dict.keys();
+    let agent_id_list: Vec<Bound<'py, PyAny>> = if sort_agent_ids {
+        let py = obs_dict.py();
+        let kwargs = PyDict::new(py);
+        if let Some(agent_id_sort_key_fn) = agent_id_sort_key_fn_option {
+            kwargs.set_item(intern!(py, "key"), agent_id_sort_key_fn)?;
+        }
+        py.import(intern!(py, "builtins"))?
+            .getattr(intern!(py, "sorted"))?
+            .call((agent_ids,), Some(&kwargs))?
+            .downcast_into::<PyList>()?
+            .iter()
+            .collect()
+    } else {
+        agent_ids.iter().collect()
+    };
+    check_for_duplicate_agent_ids(&agent_id_list[..])?;
+    match agent_order_permutation_option {
+        Some(agent_order_permutation) => {
+            permute_agent_id_list(agent_id_list, &agent_order_permutation[..])
+        }
+        None => Ok(agent_id_list),
+    }
+}
+
+// Reorders the agent id list so that position i holds the agent id which was at position
+// agent_order_permutation[i]
+fn permute_agent_id_list<'py>(
+    agent_id_list: Vec<Bound<'py, PyAny>>,
+    agent_order_permutation: &[usize],
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let mut is_used = vec![false; agent_id_list.len()];
+    let is_permutation = agent_order_permutation.len() == agent_id_list.len()
+        && agent_order_permutation
+            .iter()
+            .all(|&idx| idx < is_used.len() && !std::mem::replace(&mut is_used[idx], true));
+    if !is_permutation {
+        return Err(InvalidStateError::new_err(format!(
+            "Agent order permutation {:?} is not a permutation of the {} agent ids [{}]",
+            agent_order_permutation,
+            agent_id_list.len(),
+            agent_id_list
+                .iter()
+                .map(|agent_id| agent_id
+                    .repr()
+                    .map(|agent_id_repr| agent_id_repr.to_string()))
+                .collect::<PyResult<Vec<_>>>()?
+                .join(", ")
+        )));
+    }
+    Ok(agent_order_permutation
+        .iter()
+        .map(|&idx| agent_id_list[idx].clone())
+        .collect())
+}
+
+// Returns the stable index of the agent id, assigning the next index (in order of first appearance)
+// if the agent id has not been seen before
+fn get_agent_index<'py>(
+    agent_index_dict: &Bound<'py, PyDict>,
+    agent_id: &Bound<'py, PyAny>,
+) -> PyResult<usize> {
+    if let Some(agent_index) = agent_index_dict.get_item(agent_id)? {
+        return agent_index.extract();
+    }
+    let agent_index = agent_index_dict.len();
+    agent_index_dict.set_item(agent_id, agent_index)?;
+    Ok(agent_index)
+}
+
 #[pyfunction]
 #[pyo3(signature=(proc_id,
     child_end,
@@ -97,92 +1034,851 @@ fn env_step<'py>(
     state_serde_option,
     render=false,
     render_delay_option=None,
-    recalculate_agent_id_every_step=false))]
+    recalculate_agent_id_every_step=false,
+    env_option=None,
+    agent_options=None,
+    step_options=None,
+    episode_options=None,
+    obs_options=None,
+    buffer_options=None,
+    render_options=None,
+    debug_options=None,
+    serde_options=None))]
 pub fn env_process<'py>(
     proc_id: &str,
     child_end: Bound<'py, PyAny>,
     parent_sockname: Bound<'py, PyAny>,
-    build_env_fn: Bound<'py, PyAny>,
+    build_env_fn: Option<Bound<'py, PyAny>>,
     flinks_folder: &str,
     shm_buffer_size: usize,
-    mut agent_id_serde: Box<dyn PyAnySerde>,
-    mut action_serde: Box<dyn PyAnySerde>,
-    mut obs_serde: Box<dyn PyAnySerde>,
-    mut reward_serde: Box<dyn PyAnySerde>,
-    mut obs_space_serde: Box<dyn PyAnySerde>,
-    mut action_space_serde: Box<dyn PyAnySerde>,
-    shared_info_serde_option: DynPyAnySerdeOption,
-    shared_info_setter_serde_option: DynPyAnySerdeOption,
-    state_serde_option: DynPyAnySerdeOption,
-    render: bool,
+    agent_id_serde: VersionedSerde,
+    action_serde: VersionedSerde,
+    obs_serde: VersionedSerde,
+    reward_serde: VersionedSerde,
+    obs_space_serde: VersionedSerde,
+    action_space_serde: VersionedSerde,
+    shared_info_serde_option: VersionedSerdeOption,
+    shared_info_setter_serde_option: VersionedSerdeOption,
+    state_serde_option: VersionedSerdeOption,
+    mut render: bool,
     render_delay_option: Option<Duration>,
     recalculate_agent_id_every_step: bool,
+    env_option: Option<Bound<'py, PyAny>>,
+    agent_options: Option<AgentOptions>,
+    step_options: Option<StepOptions>,
+    episode_options: Option<EpisodeOptions>,
+    obs_options: Option<ObsOptions>,
+    buffer_options: Option<BufferOptions>,
+    render_options: Option<RenderOptions>,
+    debug_options: Option<DebugOptions>,
+    serde_options: Option<SerdeOptions>,
 ) -> PyResult<()> {
-    let mut shared_info_serde_option: Option<Box<dyn PyAnySerde>> = shared_info_serde_option.into();
+    let py = child_end.py();
+    let bind_option = |obj_option: Option<Py<PyAny>>| obj_option.map(|obj| obj.into_bound(py));
+    let AgentOptions {
+        sort_agent_ids,
+        agent_id_sort_key_fn_option,
+        stable_agent_indices,
+        agent_order_permutation_option,
+        expected_n_agents_option,
+        strict_agent_keys,
+        check_step_dict_keys,
+    } = agent_options.unwrap_or_default();
+    let agent_id_sort_key_fn_option = bind_option(agent_id_sort_key_fn_option);
+    let StepOptions {
+        action_interface,
+        default_action_option,
+        default_action_on_first_step,
+        pre_step_fn_option,
+        macro_reward_aggregation_option,
+        frame_skip_option,
+        frame_skip_reward_aggregation,
+        action_repeat_option,
+        reward_bounds_option,
+        reward_bounds_policy,
+        shared_info_setter_keys_option,
+        initial_shared_info_setter_bytes_option,
+        max_message_rate_hz_option,
+    } = step_options.unwrap_or_default();
+    let default_action_option = bind_option(default_action_option);
+    let pre_step_fn_option = bind_option(pre_step_fn_option);
+    let EpisodeOptions {
+        max_episode_duration_option,
+        agent_max_steps_dict_option,
+        done_reason_source_option,
+        reset_settle_steps_option,
+        reset_loop_threshold_option,
+        reset_loop_errors,
+        continue_from_final_state,
+        keepalive_idle_threshold_option,
+        keepalive_fn_option,
+    } = episode_options.unwrap_or_default();
+    let agent_max_steps_dict_option =
+        agent_max_steps_dict_option.map(|agent_max_steps_dict| agent_max_steps_dict.into_bound(py));
+    let keepalive_fn_option = bind_option(keepalive_fn_option);
+    let ObsOptions {
+        obs_transform_fn_option,
+        obs_dtype_cast_option,
+        obs_force_f64,
+        frame_stack_option,
+        obs_format,
+        cuda_ipc_obs,
+        fixed_layout_obs_shape_option,
+        obs_file_dir_option,
+        obs_chunk_size_option,
+        reuse_identical_obs,
+        dedup_set_state_obs,
+        global_obs_fn_option,
+        legal_action_mask_source_option,
+    } = obs_options.unwrap_or_default();
+    let obs_transform_fn_option = bind_option(obs_transform_fn_option);
+    let global_obs_fn_option = bind_option(global_obs_fn_option);
+    let BufferOptions {
+        episode_boundary_reasons,
+        bootstrap_flags,
+        state_hashes,
+        env_step_counts,
+        prev_actions,
+        packed_done_flags,
+        include_counters,
+        new_agent_flags,
+        per_agent_bytes_option,
+        overhead_bytes,
+        region_alignment_option,
+        buffer_full_policy,
+        layout_schema_path_option,
+        mqueue_max_message_bytes_option,
+        base_offset_option,
+        ..
+    } = buffer_options.unwrap_or_default();
+    let RenderOptions {
+        render_sync,
+        render_every_option,
+        render_predicate_fn_option,
+        render_errors_fatal,
+        render_thread,
+    } = render_options.unwrap_or_default();
+    let render_predicate_fn_option = bind_option(render_predicate_fn_option);
+    let DebugOptions {
+        output_capture,
+        strict_protocol,
+        sync_selfcheck,
+        slow_step_threshold_option,
+        buffer_dump_dir_option,
+        message_trace_mode_option,
+        memory_watch_config_option,
+        action_history_len_option,
+    } = debug_options.unwrap_or_default();
+    let SerdeOptions {
+        reward_component_serde_option,
+        critic_obs_serde_option,
+        global_obs_serde_option,
+        legal_action_mask_serde_option,
+        agent_obs_serdes_option,
+        rng_state_serde_option,
+        serde_fallback_fn_option,
+        shared_info_compression_option,
+    } = serde_options.unwrap_or_default();
+    let reward_component_serde_option = extract_serde_option(py, reward_component_serde_option)?;
+    let critic_obs_serde_option = extract_serde_option(py, critic_obs_serde_option)?;
+    let global_obs_serde_option = extract_serde_option(py, global_obs_serde_option)?;
+    let legal_action_mask_serde_option = extract_serde_option(py, legal_action_mask_serde_option)?;
+    let agent_obs_serdes_option =
+        agent_obs_serdes_option.map(|agent_obs_serdes| agent_obs_serdes.into_bound(py));
+    let mut rng_state_serde_option = extract_serde_option(py, rng_state_serde_option)?
+        .map(|rng_state_serde| rng_state_serde.serde);
+    let serde_fallback_fn_option = bind_option(serde_fallback_fn_option);
+    // The versions of the serdes are written for the parent to check during the startup handshake
+    let serde_version_list = SerdeVersionList::new(
+        [
+            &agent_id_serde,
+            &action_serde,
+            &obs_serde,
+            &reward_serde,
+            &obs_space_serde,
+            &action_space_serde,
+        ],
+        [
+            shared_info_serde_option.0.as_ref(),
+            shared_info_setter_serde_option.0.as_ref(),
+            state_serde_option.0.as_ref(),
+            reward_component_serde_option.as_ref(),
+            critic_obs_serde_option.as_ref(),
+            global_obs_fn_option
+                .as_ref()
+                .map(|_| global_obs_serde_option.as_ref().unwrap_or(&obs_serde)),
+            legal_action_mask_serde_option.as_ref(),
+        ],
+    );
+    let mut agent_id_serde = agent_id_serde.serde;
+    let mut action_serde = action_serde.serde;
+    let mut obs_serde = obs_serde.serde;
+    let mut reward_serde = reward_serde.serde;
+    let mut obs_space_serde = obs_space_serde.serde;
+    let mut action_space_serde = action_space_serde.serde;
+    let mut reward_component_serde_option = reward_component_serde_option.map(|serde| serde.serde);
+    let mut critic_obs_serde_option = critic_obs_serde_option.map(|serde| serde.serde);
+    let mut global_obs_serde_option = global_obs_serde_option.map(|serde| serde.serde);
+    let mut legal_action_mask_serde_option =
+        legal_action_mask_serde_option.map(|serde| serde.serde);
+    let mut shared_info_serde_option: Option<Box<dyn PyAnySerde>> =
+        shared_info_serde_option.0.map(|serde| serde.serde);
     let mut shared_info_serde_option = shared_info_serde_option.as_mut();
+    let mut agent_obs_serdes_option = agent_obs_serdes_option
+        .as_ref()
+        .map(AgentObsSerdes::new)
+        .transpose()?;
     let mut shared_info_setter_serde_option: Option<Box<dyn PyAnySerde>> =
-        shared_info_setter_serde_option.into();
+        shared_info_setter_serde_option.0.map(|serde| serde.serde);
     let mut shared_info_setter_serde_option = shared_info_setter_serde_option.as_mut();
-    let mut state_serde_option: Option<Box<dyn PyAnySerde>> = state_serde_option.into();
+    let mut state_serde_option: Option<Box<dyn PyAnySerde>> =
+        state_serde_option.0.map(|serde| serde.serde);
     let mut state_serde_option = state_serde_option.as_mut();
-    let flink = get_flink(flinks_folder, proc_id);
-    let mut shmem = ShmemConf::new()
-        .size(shm_buffer_size)
-        .flink(flink.clone())
-        .create()
-        .map_err(|err| {
-            InvalidStateError::new_err(format!("Unable to create shmem flink {}: {}", flink, err))
-        })?;
-    let (epi_evt, used_bytes) = unsafe {
-        Event::new(shmem.as_ptr(), true).map_err(|err| {
-            InvalidStateError::new_err(format!(
-                "Failed to create event from epi to this process: {}",
-                err.to_string()
-            ))
-        })?
-    };
-    let shm_slice = unsafe { &mut shmem.as_slice_mut()[used_bytes..] };
 
-    Python::with_gil::<_, PyResult<()>>(|py| {
+    let result = Python::with_gil::<_, PyResult<()>>(|py| {
         // Initial setup
-        let env = build_env_fn.call0()?;
-
-        // Startup complete
-        sync_with_epi(&child_end, &parent_sockname)?;
+        set_current_thread_name(&format!("envproc-{}", proc_id));
+        install_output_capture(py, proc_id, &output_capture)?;
+        // The config is validated before the env is built or anything is written, so that a
+        // misconfigured process fails without side effects
+        if frame_skip_option == Some(0) {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given a frame skip of 0, but each env action needs at least one env step",
+                proc_id
+            )));
+        }
+        if frame_skip_option.is_some() && macro_reward_aggregation_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both a frame skip and a macro reward aggregation, but frame skip cannot be combined with macro-actions",
+                proc_id
+            )));
+        }
+        if action_repeat_option == Some(0) {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given an action repeat of 0, but each env action needs at least one env step",
+                proc_id
+            )));
+        }
+        if action_repeat_option.is_some()
+            && (frame_skip_option.is_some() || macro_reward_aggregation_option.is_some())
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given an action repeat, but action repeat cannot be combined with frame skip or macro-actions",
+                proc_id
+            )));
+        }
+        if frame_stack_option == Some(0) {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given a frame stack of 0, but at least one frame is needed",
+                proc_id
+            )));
+        }
+        if reset_settle_steps_option.is_some() && default_action_option.is_none() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given reset settle steps, but no default action to settle with",
+                proc_id
+            )));
+        }
+        if render_thread && !matches!(render_sync, RenderSync::OFF {}) {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both a render thread and a render sync, but frames rendered on the render thread cannot be synced",
+                proc_id
+            )));
+        }
+        if let Some(max_message_rate_hz) = max_message_rate_hz_option {
+            if !max_message_rate_hz.is_finite() || max_message_rate_hz <= 0.0 {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given max message rate {} Hz, but the max message rate must be positive and finite",
+                    proc_id, max_message_rate_hz
+                )));
+            }
+        }
+        // Messages received from the message queue are copied out of shared memory, so the padding of
+        // regions aligned by address would differ between the env process and the parent
+        if mqueue_max_message_bytes_option.is_some()
+            && (obs_chunk_size_option.is_some()
+                || region_alignment_option.is_some()
+                || obs_format == ObsFormat::FIXED_LAYOUT
+                || matches!(
+                    message_trace_mode_option,
+                    Some(MessageTraceMode::REPLAY { .. })
+                ))
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given a message queue max message size, which cannot be combined with an obs chunk size, a region alignment, the fixed layout obs format or the replay message trace mode",
+                proc_id
+            )));
+        }
+        if new_agent_flags && !recalculate_agent_id_every_step {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given new_agent_flags, but the agent set can only change between steps with recalculate_agent_id_every_step",
+                proc_id
+            )));
+        }
+        if action_interface == ActionInterface::STACKED_TENSOR
+            && macro_reward_aggregation_option.is_some()
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given the stacked tensor action interface and a macro reward aggregation, but macro-actions can only be passed to the env as a dict",
+                proc_id
+            )));
+        }
+        if obs_format == ObsFormat::ARROW_IPC
+            && (obs_file_dir_option.is_some() || dedup_set_state_obs || reuse_identical_obs)
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given the arrow IPC obs format, which cannot be combined with an obs file dir, dedup_set_state_obs or reuse_identical_obs",
+                proc_id
+            )));
+        }
+        if obs_format == ObsFormat::FIXED_LAYOUT
+            && (obs_file_dir_option.is_some() || dedup_set_state_obs || reuse_identical_obs)
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given the fixed layout obs format, which cannot be combined with an obs file dir, dedup_set_state_obs or reuse_identical_obs",
+                proc_id
+            )));
+        }
+        if (obs_format == ObsFormat::FIXED_LAYOUT) != fixed_layout_obs_shape_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given a fixed layout obs shape, but the fixed layout obs shape is needed exactly when the obs format is FIXED_LAYOUT",
+                proc_id
+            )));
+        }
+        if let Some((low, high)) = reward_bounds_option {
+            if low.is_nan() || high.is_nan() || low > high {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given reward bounds ({}, {}), but the bounds must be numbers and the lower bound must not be greater than the upper bound",
+                    proc_id, low, high
+                )));
+            }
+        }
+        if cuda_ipc_obs && (obs_format != ObsFormat::SERDE || dedup_set_state_obs) {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given cuda_ipc_obs, which cannot be combined with the arrow IPC or fixed layout obs formats or dedup_set_state_obs",
+                proc_id
+            )));
+        }
+        if let Some(obs_chunk_size) = obs_chunk_size_option {
+            if obs_format != ObsFormat::SERDE
+                || obs_file_dir_option.is_some()
+                || cuda_ipc_obs
+                || dedup_set_state_obs
+                || reuse_identical_obs
+            {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given an obs chunk size, which cannot be combined with the arrow IPC or fixed layout obs formats, an obs file dir, cuda_ipc_obs, dedup_set_state_obs or reuse_identical_obs",
+                    proc_id
+                )));
+            }
+            if obs_chunk_size == 0 {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given an obs chunk size of 0, but chunks must be non-empty",
+                    proc_id
+                )));
+            }
+        }
+        if agent_obs_serdes_option.is_some() && obs_format != ObsFormat::SERDE {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given agent obs serdes, which cannot be combined with the arrow IPC or fixed layout obs formats",
+                proc_id
+            )));
+        }
+        if let Some(region_alignment) = region_alignment_option {
+            if !region_alignment.is_power_of_two() {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given region alignment {}, but the region alignment must be a power of two",
+                    proc_id, region_alignment
+                )));
+            }
+        }
+        if matches!(
+            message_trace_mode_option,
+            Some(MessageTraceMode::REPLAY { .. })
+        ) && obs_chunk_size_option.is_some()
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both the replay message trace mode and an obs chunk size, but obs chunks can only be sent to a live parent",
+                proc_id
+            )));
+        }
+        if legal_action_mask_source_option.is_some() != legal_action_mask_serde_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given only one of a legal action mask source and a legal action mask serde, but both are needed to write legal action masks",
+                proc_id
+            )));
+        }
+        if rng_state_serde_option.is_some() && state_serde_option.is_none() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given an rng state serde, but no state serde was provided, and rng states are only written with states",
+                proc_id
+            )));
+        }
+        if state_hashes && state_serde_option.is_none() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given state_hashes, but no state serde was provided to use for serializing the state to hash",
+                proc_id
+            )));
+        }
+        if continue_from_final_state && state_serde_option.is_none() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given continue_from_final_state, but no state serde was provided to use for retaining the final state",
+                proc_id
+            )));
+        }
+        if dedup_set_state_obs && obs_file_dir_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both dedup_set_state_obs and an obs file dir, but obs written to the obs file cannot be deduplicated",
+                proc_id
+            )));
+        }
+        if reset_loop_threshold_option == Some(0) {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given a reset loop threshold of 0, but the threshold must be at least one episode",
+                proc_id
+            )));
+        }
+        if obs_force_f64 && obs_dtype_cast_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both obs_force_f64 and an obs dtype cast, but float observations can only be cast to one dtype",
+                proc_id
+            )));
+        }
+        if initial_shared_info_setter_bytes_option.is_some()
+            && shared_info_setter_serde_option.is_none()
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given an initial shared info setter, but no shared info setter serde",
+                proc_id
+            )));
+        }
+        if per_agent_bytes_option.is_some() && base_offset_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both a per-agent buffer budget and a base offset, but a partition of the partitioned buffer cannot be resized",
+                proc_id
+            )));
+        }
+        if per_agent_bytes_option.is_some() && mqueue_max_message_bytes_option.is_some() {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} was given both a per-agent buffer budget and a message queue max message size, but env action responses sent through the message queue cannot follow a resized buffer",
+                proc_id
+            )));
+        }
+        // Round trip the default action through the action serde so that the env receives exactly
+        // what it would receive if the default action was sent by the parent
+        let default_action_option = default_action_option
+            .map(|default_action| {
+                let mut default_action_bytes = Vec::new();
+                action_serde.append_vec(&mut default_action_bytes, None, &default_action)?;
+                action_serde
+                    .retrieve(py, &default_action_bytes[..], 0)
+                    .map(|(default_action, _)| default_action)
+            })
+            .transpose()?;
+        // The env is built with build_env_fn, unless an already constructed env was provided
+        let env = match (env_option, &build_env_fn) {
+            (Some(env), None) => env,
+            (None, Some(build_env_fn)) => build_env_fn.call0()?,
+            (Some(_), Some(_)) => {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given both an env and a build_env_fn, but only one can be used",
+                    proc_id
+                )));
+            }
+            (None, None) => {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given neither an env nor a build_env_fn",
+                    proc_id
+                )));
+            }
+        };
+        // The config is read once, since it describes how the env was built
+        let env_config_option = env_config(&env)?;
+        if let Some(layout_schema_path) = &layout_schema_path_option {
+            BufferLayout::new(&BufferLayoutConfig {
+                recalculate_agent_id_every_step,
+                has_shared_info_serde: shared_info_serde_option.is_some(),
+                has_state_serde: state_serde_option.is_some(),
+                stable_agent_indices,
+                episode_boundary_reasons,
+                compressed_shared_info: shared_info_compression_option.is_some(),
+                has_reward_component_serde: reward_component_serde_option.is_some(),
+                has_critic_obs_serde: critic_obs_serde_option.is_some(),
+                obs_in_file: obs_file_dir_option.is_some(),
+                done_reasons: done_reason_source_option.is_some(),
+                bootstrap_flags,
+                dedup_set_state_obs,
+                reuse_identical_obs,
+                arrow_obs: obs_format == ObsFormat::ARROW_IPC,
+                fixed_layout_obs: obs_format == ObsFormat::FIXED_LAYOUT,
+                cuda_ipc_obs,
+                global_obs: global_obs_fn_option.is_some(),
+                legal_action_masks: legal_action_mask_serde_option.is_some(),
+                state_hashes,
+                env_step_counts,
+                chunked_obs: obs_chunk_size_option.is_some(),
+                prev_actions,
+                packed_done_flags,
+                region_alignment: region_alignment_option,
+                include_counters,
+                new_agent_flags,
+                rng_states: rng_state_serde_option.is_some(),
+            })
+            .write_json(py, layout_schema_path)?;
+        }
 
+        // The initial shared info setter is serialized by the parent with the shared info setter
+        // serde and applied like any other setter, but before the first reset
+        if let (Some(initial_shared_info_setter_bytes), Some(shared_info_setter_serde)) = (
+            &initial_shared_info_setter_bytes_option,
+            &mut shared_info_setter_serde_option,
+        ) {
+            let (initial_shared_info_setter, _) =
+                shared_info_setter_serde.retrieve(py, &initial_shared_info_setter_bytes[..], 0)?;
+            apply_shared_info_setter(
+                &env,
+                initial_shared_info_setter.downcast::<PyDict>()?,
+                &shared_info_setter_keys_option,
+            )?;
+        }
         let reset_obs = env_reset(&env)?;
         let mut n_agents = reset_obs.len();
-        let mut agent_id_list = Vec::with_capacity(n_agents);
-        for agent_id in reset_obs.keys().iter() {
-            agent_id_list.push(agent_id);
+        check_n_agents(proc_id, expected_n_agents_option, n_agents)?;
+        // The buffer size can instead be derived from the agent count after the initial reset
+        let mut shm_buffer_size = match per_agent_bytes_option {
+            Some(per_agent_bytes) => overhead_bytes + per_agent_bytes * n_agents,
+            None => shm_buffer_size,
+        };
+        // With a base offset, the buffer is the partition starting there in the partitioned buffer
+        // created by the parent, and shm_buffer_size is unused
+        let (mut shmem, mut epi_evt, used_bytes) = match base_offset_option {
+            Some(base_offset) => create_event(PartitionedBuffer::open_partition(
+                flinks_folder,
+                base_offset,
+            )?)?,
+            None => create_shmem(proc_id, &get_flink(flinks_folder, proc_id), shm_buffer_size)?,
+        };
+        if sync_selfcheck {
+            run_sync_selfcheck(epi_evt.as_ref(), proc_id)?;
+        }
+        let mut shm_slice = unsafe { &mut shmem.as_slice_mut()[used_bytes..] };
+        if let Some(obs_chunk_size) = obs_chunk_size_option {
+            if OBS_CHUNK_HEADER_LEN + obs_chunk_size > shm_slice.len() {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given obs chunk size {}, but chunks must fit in the {} usable bytes of the shared memory buffer",
+                    proc_id,
+                    obs_chunk_size,
+                    shm_slice.len()
+                )));
+            }
+        }
+        let obs_dtype_cast_option = if obs_force_f64 {
+            Some(TorchDtype::FLOAT64)
+        } else {
+            obs_dtype_cast_option
+        };
+
+        // The base offset of the partition used, the versions of the serdes, the transport used for
+        // env action responses, whether the env may be reset while idle and whether the buffer may be
+        // resized are written for the parent to check during the startup handshake
+        let handshake_offset = append_base_offset_option(shm_slice, 0, base_offset_option);
+        let handshake_offset = serde_version_list.append(shm_slice, handshake_offset);
+        let handshake_offset = append_bool(
+            shm_slice,
+            handshake_offset,
+            mqueue_max_message_bytes_option.is_some(),
+        );
+        // Without a keepalive fn, the env is reset when idle, and every STEP response starts with
+        // whether it was answered with the episode started by that reset instead
+        let keepalive_resets =
+            keepalive_idle_threshold_option.is_some() && keepalive_fn_option.is_none();
+        let handshake_offset = append_bool(shm_slice, handshake_offset, keepalive_resets);
+        // With a per-agent budget, the buffer is replaced with a larger one when the agent count
+        // grows past it, and every env action response starts with whether it was written to a new
+        // buffer instead, followed by the generation of that buffer if so
+        append_bool(
+            shm_slice,
+            handshake_offset,
+            per_agent_bytes_option.is_some(),
+        );
+        let mut buffer_generation: usize = 0;
+
+        let (mut message_trace_writer_option, mut message_trace_reader_option) =
+            match &message_trace_mode_option {
+                Some(MessageTraceMode::RECORD { path }) => {
+                    (Some(MessageTraceWriter::create(path)?), None)
+                }
+                Some(MessageTraceMode::REPLAY { path }) => {
+                    (None, Some(MessageTraceReader::open(path)?))
+                }
+                None => (None, None),
+            };
+
+        let mut warned_message_trace_divergence = false;
+
+        // The message queue is created before syncing so that it exists once the parent starts
+        // collecting env action responses
+        let mut message_transport: Box<dyn MessageTransport> = match mqueue_max_message_bytes_option
+        {
+            Some(mqueue_max_message_bytes) => Box::new(MqueueTransport::create(
+                proc_id,
+                mqueue_max_message_bytes,
+                &child_end,
+                &parent_sockname,
+            )?),
+            None => Box::new(ShmemTransport::new(&child_end, &parent_sockname)),
+        };
+
+        // Startup complete. When replaying a message trace there is no parent to sync with.
+        if message_trace_reader_option.is_none() {
+            sync_with_epi(&child_end, &parent_sockname)?;
         }
 
+        let mut episode_start = Instant::now();
+        let mut agent_id_list = get_agent_id_list(
+            &reset_obs,
+            sort_agent_ids,
+            &agent_id_sort_key_fn_option,
+            &agent_order_permutation_option,
+        )?;
+
+        let mut render_clock = RenderClock::new(flinks_folder, render_sync);
+        let render_thread_option = if render && render_thread {
+            Some(RenderThread::spawn(&env, proc_id, render_delay_option)?)
+        } else {
+            None
+        };
+
         // Start main loop
         let mut offset;
         let mut has_received_env_action = false;
+        let mut received_stop = false;
+        let mut warned_agent_keys_mismatch = false;
+        // The step dicts are checked on the first step, or on every step with strict agent keys
+        let mut checked_step_dict_keys = false;
+        // The serialized obs of each agent in the last message written, by position in the agent id
+        // list. Only populated with dedup_set_state_obs.
+        let mut prev_obs_bytes_list: Vec<Vec<u8>> = Vec::new();
+        // The obs of each agent in the last message written, by position in the agent id list. Only
+        // populated with reuse_identical_obs.
+        let mut prev_obs_list: Vec<PyObject> = Vec::new();
+        // The serialized obs of all agents in the last message written, which are sent in chunks
+        // after the message. Only populated with an obs chunk size.
+        let mut chunked_obs_bytes: Vec<u8> = Vec::new();
+        let mut arrow_obs_writer_option =
+            (obs_format == ObsFormat::ARROW_IPC).then(ArrowObsWriter::default);
+        let fixed_layout_obs_writer_option =
+            fixed_layout_obs_shape_option.map(FixedLayoutObsWriter::new);
+        let mut n_env_actions: usize = 0;
+        let mut memory_watch_option = memory_watch_config_option.map(MemoryWatch::new);
+        // The (agent id, action, reward) of each agent for each of the last steps, oldest first
+        let mut action_history: VecDeque<Vec<(PyObject, PyObject, PyObject)>> = VecDeque::new();
+        let mut obs_file_writer_option = obs_file_dir_option
+            .as_ref()
+            .map(|obs_file_dir| ObsFileWriter::create(obs_file_dir, proc_id))
+            .transpose()?;
+        let mut cuda_ipc_obs_writer_option = cuda_ipc_obs.then(CudaIpcObsWriter::default);
+        let mut state_pool_option: Option<StatePool> = None;
+        // Env action responses are written here and then copied to the shared memory buffer, so that
+        // one which does not fit is detected before it is copied
+        let mut message_scratch = Vec::new();
+        // A copy of the last env action response, kept for DumpBuffer requests if a buffer dump dir
+        // was provided. The buffer itself is overwritten by the requests which follow the response.
+        let mut last_message_bytes_option = buffer_dump_dir_option.as_ref().map(|_| Vec::new());
+        let mut is_first_step = false;
+        // Whether any agent terminated / truncated in the last step message written
+        let mut last_step_terminated = false;
+        // The serialized final state of the last episode which ended with a terminated or truncated
+        // agent. Only populated with continue_from_final_state.
+        let mut final_state_bytes_option: Option<Vec<u8>> = None;
+        let mut last_step_truncated = false;
+        // Maps agent ids to the index they were assigned when first seen by this process
+        let agent_index_dict = PyDict::new(py);
+        // Steps taken by each agent since the start of the episode or since it was last terminated
+        // or truncated, indexed by agent index
+        let mut agent_step_count_list: Vec<usize> = Vec::new();
+        // The env steps performed and episodes started by this process so far, which are written
+        // with every env action response with include_counters
+        let mut total_env_steps: usize = 0;
+        let mut n_episodes: usize = 0;
+        // The agent indices of the agents in the last env action response written. Only populated
+        // with new_agent_flags.
+        let mut prev_agent_index_set: HashSet<usize> = HashSet::new();
+        // Env action responses are signalled at least this far apart, to avoid overwhelming a slow
+        // parent
+        let min_message_interval_option = max_message_rate_hz_option
+            .map(|max_message_rate_hz| Duration::from_secs_f64(1.0 / max_message_rate_hz));
+        let mut last_message_signalled_option: Option<Instant> = None;
+        // The last observations of each agent in the current episode, indexed by agent index
+        let mut agent_frame_history_list: Vec<VecDeque<PyObject>> = Vec::new();
+        // When the env has been idle for longer than the keepalive threshold while waiting for a
+        // message, the keepalive fn is called on it, or it is reset if there is no keepalive fn. The
+        // obs of such a reset are kept until the next message.
+        let mut last_env_activity = Instant::now();
+        let mut keepalive_reset_obs_option: Option<Bound<'_, PyDict>> = None;
+        // Steps since the last reset-style message, and the number of consecutive episodes which
+        // ended after at most one step. The episode started by the startup reset isn't counted, since
+        // the parent commonly resets it before stepping. A reset loop is reported once per run of
+        // short episodes.
+        let mut episode_n_steps: usize = 0;
+        let mut in_startup_episode = true;
+        let mut n_consecutive_short_episodes: usize = 0;
+        let mut reset_loop_reported = false;
         loop {
-            epi_evt
-                .wait(Timeout::Infinite)
-                .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
-            epi_evt
-                .set(EventState::Clear)
-                .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+            // When replaying a message trace, the next incoming message is taken from the trace
+            // instead of waiting for the parent to write one
+            if let Some(message_trace_reader) = &mut message_trace_reader_option {
+                let Some(message_bytes) = message_trace_reader.next_incoming()? else {
+                    println!("Env process {} {}", proc_id, message_trace_reader.summary());
+                    break;
+                };
+                shm_slice
+                    .get_mut(..message_bytes.len())
+                    .ok_or_else(|| {
+                        InvalidStateError::new_err(format!(
+                            "Env process {} read a message of {} bytes from the message trace, which does not fit in the shared memory buffer",
+                            proc_id,
+                            message_bytes.len()
+                        ))
+                    })?
+                    .copy_from_slice(message_bytes);
+            }
+            let ungil_epi_evt = UngilEvent(epi_evt.as_ref());
+            while message_trace_reader_option.is_none()
+                && !py.allow_threads(|| {
+                    ungil_epi_evt.wait(keepalive_idle_threshold_option.map(
+                        |keepalive_idle_threshold| {
+                            keepalive_idle_threshold.saturating_sub(last_env_activity.elapsed())
+                        },
+                    ))
+                })?
+            {
+                println!(
+                    "Env process {} has been idle for {:?}, performing keepalive",
+                    proc_id,
+                    last_env_activity.elapsed()
+                );
+                match &keepalive_fn_option {
+                    Some(keepalive_fn) => {
+                        keepalive_fn.call1((&env,))?;
+                    }
+                    None => {
+                        keepalive_reset_obs_option = Some(env_reset(&env)?);
+                    }
+                }
+                last_env_activity = Instant::now();
+            }
+            if message_trace_reader_option.is_none() {
+                epi_evt
+                    .set(EventState::Clear)
+                    .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+            }
             offset = 0;
-            let header;
-            (header, offset) = retrieve_header(shm_slice, offset)?;
+            // An unknown header (e.g. from a newer parent) is reported back rather than ending the process
+            let header = match retrieve_header(shm_slice, offset) {
+                Ok((header, new_offset)) => {
+                    offset = new_offset;
+                    header
+                }
+                Err(err) => {
+                    println!(
+                        "Env process {} received an unsupported header, responding with header not supported: {}",
+                        proc_id, err
+                    );
+                    send_header_not_supported(&child_end, &parent_sockname, shm_slice[offset])?;
+                    continue;
+                }
+            };
             match header {
-                Header::EnvAction => {
+                Header::EnvAction | Header::SetStateFromPool | Header::Resync => {
                     has_received_env_action = true;
-                    let env_action;
-                    (env_action, _) = retrieve_env_action(
-                        py,
-                        shm_slice,
-                        offset,
-                        agent_id_list.len(),
-                        &mut action_serde,
-                        &mut shared_info_setter_serde_option,
-                        &mut state_serde_option,
-                    )?;
+                    let (mut env_action, incoming_end);
+                    if header == Header::SetStateFromPool {
+                        // The desired state is read from the state pool rather than the message,
+                        // and is otherwise handled as a SET_STATE env action
+                        let (pool_index, send_state, send_shared_info);
+                        (pool_index, offset) = retrieve_usize_le(shm_slice, offset)?;
+                        (send_state, offset) = retrieve_bool(shm_slice, offset)?;
+                        (send_shared_info, incoming_end) = retrieve_bool(shm_slice, offset)?;
+                        if state_pool_option.is_none() {
+                            state_pool_option = Some(StatePool::open(flinks_folder)?);
+                        }
+                        let state_serde = state_serde_option.as_deref_mut().ok_or_else(|| {
+                            InvalidStateError::new_err(format!(
+                                "Env process {} received a SetStateFromPool request, but no state serde was provided to use for deserialization",
+                                proc_id
+                            ))
+                        })?;
+                        let (desired_state, _) = state_serde.retrieve(
+                            py,
+                            state_pool_option.as_ref().unwrap().read(pool_index)?,
+                            0,
+                        )?;
+                        env_action = EnvAction::SET_STATE {
+                            desired_state: desired_state.unbind(),
+                            shared_info_setter_option: None,
+                            send_state,
+                            prev_timestep_id_dict_option: None,
+                            send_shared_info,
+                            rng_state_option: None,
+                        };
+                    } else if header == Header::Resync {
+                        // State left over from the exchange the parent lost track of is discarded,
+                        // and the env is reset so that both sides start again from a fresh episode
+                        let send_shared_info;
+                        (send_shared_info, incoming_end) = retrieve_bool(shm_slice, offset)?;
+                        println!(
+                            "Env process {} received a Resync request, resetting the env",
+                            proc_id
+                        );
+                        action_history.clear();
+                        last_step_terminated = false;
+                        last_step_truncated = false;
+                        final_state_bytes_option = None;
+                        env_action = EnvAction::RESET {
+                            shared_info_setter_option: None,
+                            send_state: false,
+                            send_shared_info,
+                        };
+                    } else {
+                        (env_action, incoming_end) = retrieve_env_action(
+                            py,
+                            shm_slice,
+                            offset,
+                            agent_id_list.len(),
+                            &mut EnvActionSerdes {
+                                action_serde: &mut action_serde,
+                                shared_info_setter_serde_option: shared_info_setter_serde_option
+                                    .as_deref_mut(),
+                                state_serde_option: state_serde_option.as_deref_mut(),
+                                rng_state_serde_option: rng_state_serde_option.as_mut(),
+                            },
+                        )?;
+                    }
+                    if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                        message_trace_writer.record_incoming(&shm_slice[..incoming_end])?;
+                    }
+                    // After a keepalive reset, the parent's next STEP is answered with the episode
+                    // started by that reset instead, as if it had sent a RESET. Any other env action
+                    // replaces that episode anyway.
+                    let keepalive_reset_obs_option_taken = keepalive_reset_obs_option.take();
+                    let is_keepalive_reset = keepalive_reset_obs_option_taken.is_some()
+                        && matches!(env_action, EnvAction::STEP { .. });
+                    if is_keepalive_reset {
+                        if let EnvAction::STEP {
+                            shared_info_setter_option,
+                            send_state,
+                            send_shared_info,
+                            ..
+                        } = env_action
+                        {
+                            env_action = EnvAction::RESET {
+                                shared_info_setter_option,
+                                send_state,
+                                send_shared_info,
+                            };
+                        }
+                    }
                     // Read actions message
                     let (
                         obs_dict,
@@ -191,7 +1887,12 @@ pub fn env_process<'py>(
                         truncated_dict_option,
                         is_step,
                         should_send_state,
+                        n_env_steps,
+                        prev_action_dict_option,
                     );
+                    // The retained final state is only continued from by the reset which directly
+                    // follows the end of its episode
+                    let final_state_bytes_option_taken = final_state_bytes_option.take();
                     let shared_info_setter_option = match &env_action {
                         EnvAction::STEP {
                             shared_info_setter_option,
@@ -201,14 +1902,111 @@ pub fn env_process<'py>(
                         } => {
                             let mut actions_kv_list = Vec::with_capacity(agent_id_list.len());
                             let action_list = action_list.bind(py);
-                            for (agent_id, action) in agent_id_list.iter().zip(action_list.iter()) {
-                                actions_kv_list.push((agent_id, action));
+                            match &default_action_option {
+                                Some(default_action)
+                                    if default_action_on_first_step && is_first_step =>
+                                {
+                                    // The default action is a single primitive action, so it is
+                                    // wrapped as a macro-action of length one if necessary
+                                    let default_action = match &macro_reward_aggregation_option {
+                                        Some(_) => PyList::new(py, [default_action])?.into_any(),
+                                        None => default_action.clone(),
+                                    };
+                                    for agent_id in agent_id_list.iter() {
+                                        actions_kv_list.push((agent_id, default_action.clone()));
+                                    }
+                                }
+                                _ => {
+                                    for (agent_id, action) in
+                                        agent_id_list.iter().zip(action_list.iter())
+                                    {
+                                        actions_kv_list.push((agent_id, action));
+                                    }
+                                }
                             }
-                            let actions_dict =
-                                PyDict::from_sequence(&actions_kv_list.into_pyobject(py)?)?;
+                            let history_actions_kv_list = match action_history_len_option {
+                                Some(_) => actions_kv_list
+                                    .iter()
+                                    .map(|(agent_id, action)| {
+                                        ((*agent_id).clone().unbind(), action.clone().unbind())
+                                    })
+                                    .collect(),
+                                None => Vec::new(),
+                            };
+                            // The actions applied by this step are written back as the previous
+                            // actions of the agents
+                            prev_action_dict_option = if prev_actions {
+                                let prev_action_dict = PyDict::new(py);
+                                for (agent_id, action) in actions_kv_list.iter() {
+                                    prev_action_dict.set_item(*agent_id, action)?;
+                                }
+                                Some(prev_action_dict)
+                            } else {
+                                None
+                            };
+                            let step_start = Instant::now();
                             let (rew_dict, terminated_dict, truncated_dict);
-                            (obs_dict, rew_dict, terminated_dict, truncated_dict) =
-                                env_step(&env, actions_dict)?;
+                            if let Some(macro_reward_aggregation) = &macro_reward_aggregation_option
+                            {
+                                (
+                                    (obs_dict, rew_dict, terminated_dict, truncated_dict),
+                                    n_env_steps,
+                                ) = env_step_macro(
+                                    &env,
+                                    proc_id,
+                                    actions_kv_list,
+                                    macro_reward_aggregation,
+                                    &pre_step_fn_option,
+                                )?;
+                            } else {
+                                let actions = apply_pre_step_fn(
+                                    &pre_step_fn_option,
+                                    build_env_actions(py, actions_kv_list, &action_interface)?,
+                                    proc_id,
+                                )?;
+                                (
+                                    (obs_dict, rew_dict, terminated_dict, truncated_dict),
+                                    n_env_steps,
+                                ) = match (frame_skip_option, action_repeat_option) {
+                                    (Some(frame_skip), _) => env_step_repeated(
+                                        &env,
+                                        frame_skip,
+                                        |_| Ok(actions.clone()),
+                                        &frame_skip_reward_aggregation,
+                                        false,
+                                    )?,
+                                    (None, Some(action_repeat)) => env_step_repeated(
+                                        &env,
+                                        action_repeat,
+                                        |_| Ok(actions.clone()),
+                                        &MacroRewardAggregation::SUM,
+                                        true,
+                                    )?,
+                                    (None, None) => (env_step(&env, actions)?, 1),
+                                };
+                            }
+                            if let Some(slow_step_threshold) = slow_step_threshold_option {
+                                let step_duration = step_start.elapsed();
+                                if step_duration > slow_step_threshold {
+                                    println!(
+                                        "Env process {} took {:?} to step the env, which exceeds the slow step threshold of {:?}",
+                                        proc_id, step_duration, slow_step_threshold
+                                    );
+                                }
+                            }
+                            if let Some(action_history_len) = action_history_len_option {
+                                let mut step_history =
+                                    Vec::with_capacity(history_actions_kv_list.len());
+                                for (agent_id, action) in history_actions_kv_list.into_iter() {
+                                    let reward = rew_dict.get_item(&agent_id)?.ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the reward dict entry for agent id {}, but there was no such entry", proc_id, agent_id.bind(py).repr().unwrap())))?;
+                                    step_history.push((agent_id, action, reward.unbind()));
+                                }
+                                action_history.push_back(step_history);
+                                while action_history.len() > action_history_len {
+                                    action_history.pop_front();
+                                }
+                            }
+                            total_env_steps += n_env_steps;
                             rew_dict_option = Some(rew_dict);
                             terminated_dict_option = Some(terminated_dict);
                             truncated_dict_option = Some(truncated_dict);
@@ -219,133 +2017,807 @@ pub fn env_process<'py>(
                         EnvAction::RESET {
                             shared_info_setter_option,
                             send_state,
+                            ..
                         } => {
-                            obs_dict = env_reset(&env)?;
-                            agent_id_list.clear();
-                            for agent_id in obs_dict.keys().iter() {
-                                agent_id_list.push(agent_id);
-                            }
+                            obs_dict = match (
+                                keepalive_reset_obs_option_taken,
+                                &final_state_bytes_option_taken,
+                                reset_settle_steps_option,
+                                &default_action_option,
+                            ) {
+                                (Some(keepalive_reset_obs), _, _, _) => keepalive_reset_obs,
+                                (None, Some(final_state_bytes), _, _) => {
+                                    let (final_state, _) = state_serde_option
+                                        .as_deref_mut()
+                                        .unwrap()
+                                        .retrieve(py, &final_state_bytes[..], 0)?;
+                                    env_set_state(&env, &final_state)?
+                                }
+                                (None, None, Some(reset_settle_steps), Some(default_action)) => {
+                                    env_reset_settled(
+                                        &env,
+                                        reset_settle_steps,
+                                        default_action,
+                                        &action_interface,
+                                    )?
+                                }
+                                _ => env_reset(&env)?,
+                            };
+                            n_episodes += 1;
                             rew_dict_option = None;
                             terminated_dict_option = None;
                             truncated_dict_option = None;
                             is_step = false;
                             should_send_state = *send_state;
+                            n_env_steps = 0;
+                            prev_action_dict_option = None;
                             shared_info_setter_option
                         }
                         EnvAction::SET_STATE {
                             desired_state,
                             shared_info_setter_option,
                             send_state,
+                            rng_state_option,
                             ..
                         } => {
                             obs_dict = env_set_state(&env, desired_state.bind(py))?;
-                            agent_id_list.clear();
-                            for agent_id in obs_dict.keys().iter() {
-                                agent_id_list.push(agent_id);
+                            if let Some(rng_state) = rng_state_option {
+                                env.call_method1(intern!(py, "set_rng_state"), (rng_state,))?;
                             }
                             rew_dict_option = None;
                             terminated_dict_option = None;
                             truncated_dict_option = None;
                             is_step = false;
                             should_send_state = *send_state;
+                            n_env_steps = 0;
+                            prev_action_dict_option = None;
                             shared_info_setter_option
                         }
                     };
                     if let Some(shared_info_setter) = shared_info_setter_option {
-                        env_shared_info(&env)?.downcast::<PyDict>()?.update(
-                            shared_info_setter
-                                .downcast_bound::<PyDict>(py)?
-                                .as_mapping(),
+                        apply_shared_info_setter(
+                            &env,
+                            shared_info_setter.downcast_bound::<PyDict>(py)?,
+                            &shared_info_setter_keys_option,
                         )?;
                     }
                     let non_step = !is_step;
+                    is_first_step = non_step;
 
+                    if is_step {
+                        episode_n_steps += 1;
+                    } else {
+                        // Episodes of at most one step are counted towards a likely reset loop
+                        if in_startup_episode {
+                            in_startup_episode = false;
+                        } else if episode_n_steps <= 1 {
+                            n_consecutive_short_episodes += 1;
+                        } else {
+                            n_consecutive_short_episodes = 0;
+                            reset_loop_reported = false;
+                        }
+                        episode_n_steps = 0;
+                        if !reset_loop_reported
+                            && reset_loop_threshold_option.is_some_and(|reset_loop_threshold| {
+                                n_consecutive_short_episodes >= reset_loop_threshold
+                            })
+                        {
+                            reset_loop_reported = true;
+                            let msg = format!(
+                                "Env process {} has had {} consecutive episodes of at most one step, which likely indicates a reset loop (e.g. a termination condition which is always met)",
+                                proc_id, n_consecutive_short_episodes
+                            );
+                            if reset_loop_errors {
+                                return Err(InvalidStateError::new_err(msg));
+                            }
+                            println!("{}", msg);
+                        }
+                    }
                     if non_step {
                         n_agents = obs_dict.len();
+                        check_n_agents(proc_id, expected_n_agents_option, n_agents)?;
+                        episode_start = Instant::now();
+                        agent_step_count_list.fill(0);
+                        agent_frame_history_list
+                            .iter_mut()
+                            .for_each(|frame_history| frame_history.clear());
                     }
+                    // Once the episode has run past its deadline, all agents are truncated
+                    let episode_deadline_exceeded = is_step
+                        && max_episode_duration_option.is_some_and(|max_episode_duration| {
+                            episode_start.elapsed() > max_episode_duration
+                        });
 
                     if recalculate_agent_id_every_step || non_step {
-                        agent_id_list.clear();
-                        for agent_id in obs_dict.keys().iter() {
-                            agent_id_list.push(agent_id);
+                        agent_id_list = get_agent_id_list(
+                            &obs_dict,
+                            sort_agent_ids,
+                            &agent_id_sort_key_fn_option,
+                            &agent_order_permutation_option,
+                        )?;
+                    }
+
+                    if is_step
+                        && check_step_dict_keys
+                        && (strict_agent_keys || !checked_step_dict_keys)
+                    {
+                        for (dict_name, dict) in [
+                            ("obs", &obs_dict),
+                            ("reward", rew_dict_option.as_ref().unwrap()),
+                            ("terminated", terminated_dict_option.as_ref().unwrap()),
+                            ("truncated", truncated_dict_option.as_ref().unwrap()),
+                        ] {
+                            if !agent_keys_match(dict, &agent_id_list)? {
+                                return Err(InvalidStateError::new_err(format!(
+                                    "Env process {} received a {} dict from env.step with agent ids {}, but the agent ids of the current episode are {}",
+                                    proc_id,
+                                    dict_name,
+                                    dict.keys().repr()?,
+                                    PyList::new(py, &agent_id_list)?.repr()?
+                                )));
+                            }
                         }
+                        checked_step_dict_keys = true;
                     }
 
-                    // Write message
-                    offset = 0;
-                    if non_step {
-                        offset = append_usize(shm_slice, offset, n_agents);
+                    // The obs of agents missing from the agent id list would otherwise be dropped silently
+                    if is_step
+                        && !recalculate_agent_id_every_step
+                        && !agent_keys_match(&obs_dict, &agent_id_list)?
+                    {
+                        let msg = format!(
+                            "Env process {} received an obs dict from env.step with agent ids {}, but the agent ids of the current episode are {}",
+                            proc_id,
+                            obs_dict.keys().repr()?,
+                            PyList::new(py, &agent_id_list)?.repr()?
+                        );
+                        if strict_agent_keys {
+                            return Err(InvalidStateError::new_err(msg));
+                        }
+                        if !warned_agent_keys_mismatch {
+                            println!("{}. The obs of any agents not in the episode will be dropped. This warning will only be shown once.", msg);
+                            warned_agent_keys_mismatch = true;
+                        }
                     }
-                    for agent_id in agent_id_list.iter() {
-                        if recalculate_agent_id_every_step || non_step {
-                            offset = agent_id_serde.append(shm_slice, offset, agent_id)?;
+
+                    let send_obs = env_action.send_obs();
+                    let critic_obs_dict_option = critic_obs_serde_option
+                        .as_ref()
+                        .filter(|_| send_obs)
+                        .map(|_| env_build_critic_obs(&env, proc_id))
+                        .transpose()?;
+                    let legal_action_mask_dict_option = legal_action_mask_source_option
+                        .as_ref()
+                        .filter(|_| send_obs)
+                        .map(|legal_action_mask_source| {
+                            env_legal_action_masks(&env, legal_action_mask_source)
+                        })
+                        .transpose()?;
+                    let done_reason_dict_option = match &done_reason_source_option {
+                        Some(done_reason_source) if is_step => {
+                            Some(env_done_reasons(&env, done_reason_source)?)
                         }
-                        offset = obs_serde.append(
-                            shm_slice,
-                            offset,
-                            &obs_dict.get_item(agent_id)?.ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the obs dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?
-                        )?;
-                        if is_step {
-                            offset = reward_serde.append(
-                                shm_slice,
-                                offset,
-                                &rew_dict_option
-                                    .as_ref()
-                                    .unwrap()
-                                    .get_item(agent_id)?
-                                    .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the reward dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?,
+                        _ => None,
+                    };
+                    let reward_components_option = match &reward_component_serde_option {
+                        Some(_) if is_step => Some(env_reward_components(&env, proc_id)?),
+                        _ => None,
+                    };
+
+                    // Once the agents no longer fit in the per-agent budget, the response is written
+                    // to a larger buffer, which the parent is told to open through the old one
+                    if let Some(per_agent_bytes) = per_agent_bytes_option {
+                        let needed_buffer_size =
+                            overhead_bytes + per_agent_bytes * agent_id_list.len();
+                        if needed_buffer_size > shm_buffer_size {
+                            buffer_generation += 1;
+                            let (new_shmem, new_epi_evt, new_used_bytes) = create_shmem(
+                                proc_id,
+                                &get_resized_flink(flinks_folder, proc_id, buffer_generation),
+                                needed_buffer_size,
                             )?;
-                            offset = append_bool(
-                                shm_slice,
-                                offset,
-                                terminated_dict_option
-                                    .as_ref()
-                                    .unwrap()
-                                    .get_item(agent_id)?
-                                    .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the terminated dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?
-                                    .extract::<bool>()?,
-                            );
-                            offset = append_bool(
-                                shm_slice,
-                                offset,
-                                truncated_dict_option
-                                    .as_ref()
-                                    .unwrap()
-                                    .get_item(agent_id)?
-                                    .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the truncated dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?
-                                    .extract::<bool>()?,
+                            let offset = append_bool(shm_slice, 0, true);
+                            append_usize_le(shm_slice, offset, buffer_generation);
+                            println!(
+                                "Env process {} resized its shared memory buffer to {} bytes for {} agents",
+                                proc_id,
+                                needed_buffer_size,
+                                agent_id_list.len()
                             );
+                            shm_buffer_size = needed_buffer_size;
+                            epi_evt = new_epi_evt;
+                            shmem = new_shmem;
+                            shm_slice = unsafe { &mut shmem.as_slice_mut()[new_used_bytes..] };
                         }
                     }
-                    if let Some(shared_info_serde) = shared_info_serde_option.as_deref_mut() {
-                        offset =
-                            shared_info_serde.append(shm_slice, offset, &env_shared_info(&env)?)?;
+
+                    // Write message
+                    // Serdes align their data to the address the message is copied to
+                    let shm_start_addr = shm_slice.as_ptr() as usize;
+                    let message_end_option = write_message(
+                        &buffer_full_policy,
+                        proc_id,
+                        shm_slice,
+                        &mut message_scratch,
+                        |message_bytes| {
+                            chunked_obs_bytes.clear();
+                            if let Some(obs_file_writer) = &mut obs_file_writer_option {
+                                obs_file_writer.start_message();
+                            }
+                            if let Some(cuda_ipc_obs_writer) = &mut cuda_ipc_obs_writer_option {
+                                cuda_ipc_obs_writer.start_message();
+                            }
+                            if per_agent_bytes_option.is_some() {
+                                append_bool_vec(message_bytes, false);
+                            }
+                            if keepalive_resets && (is_step || is_keepalive_reset) {
+                                append_bool_vec(message_bytes, is_keepalive_reset);
+                            }
+                            if non_step && episode_boundary_reasons {
+                                let reason = match &env_action {
+                                    _ if is_keepalive_reset => {
+                                        EpisodeBoundaryReason::KEEPALIVE_RESET
+                                    }
+                                    EnvAction::SET_STATE { .. } => EpisodeBoundaryReason::SET_STATE,
+                                    _ if last_step_terminated => {
+                                        EpisodeBoundaryReason::TERMINATED_AUTORESET
+                                    }
+                                    _ if last_step_truncated => {
+                                        EpisodeBoundaryReason::TRUNCATED_AUTORESET
+                                    }
+                                    _ => EpisodeBoundaryReason::EXPLICIT_RESET,
+                                };
+                                append_episode_boundary_reason_vec(message_bytes, &reason);
+                            }
+                            if non_step {
+                                append_usize_le_vec(message_bytes, n_agents);
+                            }
+                            last_step_terminated = false;
+                            last_step_truncated = false;
+                            // The parent has no copy of obs which weren't sent, so none can be reused
+                            // by the next message
+                            if send_obs {
+                                prev_obs_bytes_list.truncate(agent_id_list.len());
+                                prev_obs_list.truncate(agent_id_list.len());
+                            } else {
+                                prev_obs_bytes_list.clear();
+                                prev_obs_list.clear();
+                            }
+                            // With the arrow IPC and fixed layout obs formats, the obs of all agents are
+                            // written after the per-agent fields
+                            let mut batched_obs_list_option = (obs_format != ObsFormat::SERDE
+                                && send_obs)
+                                .then(|| Vec::with_capacity(agent_id_list.len()));
+                            // With packed done flags, the terminated and truncated flags of all agents
+                            // are written as bitsets after the per-agent fields
+                            let mut done_flag_lists_option =
+                                (is_step && packed_done_flags).then(|| {
+                                    (
+                                        Vec::with_capacity(agent_id_list.len()),
+                                        Vec::with_capacity(agent_id_list.len()),
+                                    )
+                                });
+                            let mut agent_index_set = HashSet::new();
+                            for (agent_idx, agent_id) in agent_id_list.iter().enumerate() {
+                                if let Some(region_alignment) = region_alignment_option {
+                                    append_alignment_padding_vec(
+                                        message_bytes,
+                                        shm_start_addr,
+                                        region_alignment,
+                                    );
+                                }
+                                if recalculate_agent_id_every_step || non_step {
+                                    agent_id_serde.append_vec(
+                                        message_bytes,
+                                        Some(shm_start_addr),
+                                        agent_id,
+                                    )?;
+                                    let agent_index_option = stable_agent_indices
+                                        .then(|| get_agent_index(&agent_index_dict, agent_id))
+                                        .transpose()?;
+                                    // Every agent is new after a reset or set state, and otherwise
+                                    // only agents which weren't in the last message are
+                                    let is_new_agent_option = if new_agent_flags {
+                                        let agent_index =
+                                            get_agent_index(&agent_index_dict, agent_id)?;
+                                        agent_index_set.insert(agent_index);
+                                        Some(
+                                            non_step
+                                                || !prev_agent_index_set.contains(&agent_index),
+                                        )
+                                    } else {
+                                        None
+                                    };
+                                    append_agent_id_fields_vec(
+                                        message_bytes,
+                                        agent_index_option,
+                                        is_new_agent_option,
+                                    );
+                                }
+                                let mut obs = transform_obs(
+                                    &obs_transform_fn_option,
+                                    &obs_dtype_cast_option,
+                                    obs_dict.get_item(agent_id)?.ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the obs dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?,
+                                    proc_id,
+                                    agent_id,
+                                )?;
+                                if let Some(frame_stack) = frame_stack_option {
+                                    let agent_index = get_agent_index(&agent_index_dict, agent_id)?;
+                                    if agent_index >= agent_frame_history_list.len() {
+                                        agent_frame_history_list
+                                            .resize_with(agent_index + 1, VecDeque::new);
+                                    }
+                                    obs = stack_obs(
+                                        &mut agent_frame_history_list[agent_index],
+                                        obs,
+                                        frame_stack,
+                                    )?;
+                                }
+                                // The obs is still built when it isn't sent, so that the frame history
+                                // stays up to date
+                                if let Some(batched_obs_list) = &mut batched_obs_list_option {
+                                    batched_obs_list.push(obs.clone());
+                                } else if send_obs && obs_chunk_size_option.is_some() {
+                                    append_vec_with_fallback(
+                                        select_obs_serde(
+                                            &mut agent_obs_serdes_option,
+                                            &mut obs_serde,
+                                            agent_id,
+                                        )?
+                                        .as_mut(),
+                                        &mut chunked_obs_bytes,
+                                        None,
+                                        &obs,
+                                        &serde_fallback_fn_option,
+                                    )?;
+                                } else if send_obs {
+                                    // An obs which is the same object as the previous obs of the agent in the
+                                    // same position (with reuse_identical_obs), or which serializes to the same
+                                    // bytes for SET_STATE (with dedup_set_state_obs), is written as a flag and
+                                    // the parent reuses its copy
+                                    let is_set_state =
+                                        matches!(env_action, EnvAction::SET_STATE { .. });
+                                    let mut same_as_prev = reuse_identical_obs
+                                        && prev_obs_list
+                                            .get(agent_idx)
+                                            .is_some_and(|prev_obs| obs.is(prev_obs));
+                                    let mut obs_bytes_option = None;
+                                    if dedup_set_state_obs && !same_as_prev {
+                                        let mut obs_bytes = Vec::new();
+                                        append_vec_with_fallback(
+                                            select_obs_serde(
+                                                &mut agent_obs_serdes_option,
+                                                &mut obs_serde,
+                                                agent_id,
+                                            )?
+                                            .as_mut(),
+                                            &mut obs_bytes,
+                                            None,
+                                            &obs,
+                                            &serde_fallback_fn_option,
+                                        )?;
+                                        same_as_prev = is_set_state
+                                            && prev_obs_bytes_list.get(agent_idx).is_some_and(
+                                                |prev_obs_bytes| *prev_obs_bytes == obs_bytes,
+                                            );
+                                        obs_bytes_option = Some(obs_bytes);
+                                    }
+                                    if (dedup_set_state_obs && is_set_state) || reuse_identical_obs
+                                    {
+                                        append_bool_vec(message_bytes, same_as_prev);
+                                    }
+                                    if !same_as_prev {
+                                        match &obs_bytes_option {
+                                            Some(obs_bytes) => {
+                                                message_bytes.extend_from_slice(&obs_bytes[..])
+                                            }
+                                            None => append_obs_vec(
+                                                select_obs_serde(
+                                                    &mut agent_obs_serdes_option,
+                                                    &mut obs_serde,
+                                                    agent_id,
+                                                )?
+                                                .as_mut(),
+                                                &mut obs_file_writer_option,
+                                                &mut cuda_ipc_obs_writer_option,
+                                                message_bytes,
+                                                shm_start_addr,
+                                                &obs,
+                                                &serde_fallback_fn_option,
+                                            )?,
+                                        }
+                                    }
+                                    if let Some(obs_bytes) = obs_bytes_option {
+                                        set_or_push(&mut prev_obs_bytes_list, agent_idx, obs_bytes);
+                                    }
+                                    if reuse_identical_obs {
+                                        set_or_push(
+                                            &mut prev_obs_list,
+                                            agent_idx,
+                                            obs.clone().unbind(),
+                                        );
+                                    }
+                                }
+                                if let Some(critic_obs_serde) =
+                                    critic_obs_serde_option.as_deref_mut().filter(|_| send_obs)
+                                {
+                                    append_vec_with_fallback(
+                                        critic_obs_serde,
+                                        message_bytes,
+                                        Some(shm_start_addr),
+                                        &critic_obs_dict_option
+                                            .as_ref()
+                                            .unwrap()
+                                            .get_item(agent_id)?
+                                            .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the critic obs dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap())))?,
+                                        &serde_fallback_fn_option,
+                                    )?;
+                                }
+                                if let Some(legal_action_mask_serde) =
+                                    legal_action_mask_serde_option
+                                        .as_deref_mut()
+                                        .filter(|_| send_obs)
+                                {
+                                    legal_action_mask_serde.append_vec(
+                                        message_bytes,
+                                        Some(shm_start_addr),
+                                        &legal_action_mask_dict_option
+                                            .as_ref()
+                                            .unwrap()
+                                            .get_item(agent_id)?
+                                            .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the legal action mask dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap())))?,
+                                    )?;
+                                }
+                                if is_step {
+                                    let mut reward = rew_dict_option
+                                        .as_ref()
+                                        .unwrap()
+                                        .get_item(agent_id)?
+                                        .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the reward dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?;
+                                    if let Some(reward_bounds) = reward_bounds_option {
+                                        reward = check_reward_bounds(
+                                            reward,
+                                            reward_bounds,
+                                            &reward_bounds_policy,
+                                            proc_id,
+                                            agent_id,
+                                        )?;
+                                    }
+                                    reward_serde.append_vec(
+                                        message_bytes,
+                                        Some(shm_start_addr),
+                                        &reward,
+                                    )?;
+                                    if let Some(reward_component_serde) =
+                                        reward_component_serde_option.as_deref_mut()
+                                    {
+                                        reward_component_serde.append_vec(
+                                            message_bytes,
+                                            Some(shm_start_addr),
+                                            &reward_components_option
+                                                .as_ref()
+                                                .unwrap()
+                                                .get_item(agent_id)?
+                                                .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the reward components dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap())))?,
+                                        )?;
+                                    }
+                                    let terminated = terminated_dict_option
+                                        .as_ref()
+                                        .unwrap()
+                                        .get_item(agent_id)?
+                                        .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the terminated dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?
+                                        .extract::<bool>()?;
+                                    let truncated = truncated_dict_option
+                                        .as_ref()
+                                        .unwrap()
+                                        .get_item(agent_id)?
+                                        .ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the truncated dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap().to_string())))?
+                                        .extract::<bool>()?
+                                        || episode_deadline_exceeded;
+                                    // Agents with a step limit are truncated individually by their own clock
+                                    let truncated = match &agent_max_steps_dict_option {
+                                        Some(agent_max_steps_dict) => {
+                                            let agent_index =
+                                                get_agent_index(&agent_index_dict, agent_id)?;
+                                            if agent_index >= agent_step_count_list.len() {
+                                                agent_step_count_list.resize(agent_index + 1, 0);
+                                            }
+                                            agent_step_count_list[agent_index] += 1;
+                                            let agent_timed_out = agent_max_steps_dict
+                                                .get_item(agent_id)?
+                                                .map(|max_steps| max_steps.extract::<usize>())
+                                                .transpose()?
+                                                .is_some_and(|max_steps| {
+                                                    agent_step_count_list[agent_index] >= max_steps
+                                                });
+                                            if terminated || truncated || agent_timed_out {
+                                                agent_step_count_list[agent_index] = 0;
+                                            }
+                                            truncated || agent_timed_out
+                                        }
+                                        None => truncated,
+                                    };
+                                    last_step_terminated |= terminated;
+                                    last_step_truncated |= truncated;
+                                    if let Some((terminated_list, truncated_list)) =
+                                        &mut done_flag_lists_option
+                                    {
+                                        terminated_list.push(terminated);
+                                        truncated_list.push(truncated);
+                                    } else {
+                                        append_bool_vec(message_bytes, terminated);
+                                        append_bool_vec(message_bytes, truncated);
+                                    }
+                                    // The value of the next obs should be bootstrapped only if the episode was cut
+                                    // short rather than ended by the env
+                                    if bootstrap_flags {
+                                        append_bool_vec(message_bytes, truncated && !terminated);
+                                    }
+                                    if let Some(done_reason_dict) = &done_reason_dict_option {
+                                        let done_reason = done_reason_dict
+                                            .get_item(agent_id)?
+                                            .map(|done_reason| done_reason.extract::<u8>())
+                                            .transpose()?
+                                            .unwrap_or(0);
+                                        message_bytes.push(done_reason);
+                                    }
+                                    // Agents which weren't given an action by this step (e.g. agents
+                                    // which just joined) have no previous action
+                                    if let Some(prev_action_dict) = &prev_action_dict_option {
+                                        let prev_action_option =
+                                            prev_action_dict.get_item(agent_id)?;
+                                        append_bool_vec(
+                                            message_bytes,
+                                            prev_action_option.is_some(),
+                                        );
+                                        if let Some(prev_action) = prev_action_option {
+                                            action_serde.append_vec(
+                                                message_bytes,
+                                                Some(shm_start_addr),
+                                                &prev_action,
+                                            )?;
+                                        }
+                                    }
+                                }
+                            }
+                            if new_agent_flags {
+                                prev_agent_index_set = agent_index_set;
+                            }
+                            if let Some((terminated_list, truncated_list)) = done_flag_lists_option
+                            {
+                                append_bitset_vec(message_bytes, &terminated_list[..]);
+                                append_bitset_vec(message_bytes, &truncated_list[..]);
+                            }
+                            // With action repeat or macro actions, stepping may stop early when the
+                            // episode ends, so the number of env steps performed is written once per
+                            // step message
+                            if is_step && env_step_counts {
+                                append_usize_le_vec(message_bytes, n_env_steps);
+                            }
+                            if include_counters {
+                                append_usize_le_vec(message_bytes, total_env_steps);
+                                append_usize_le_vec(message_bytes, n_episodes);
+                            }
+                            if let Some(batched_obs_list) = batched_obs_list_option {
+                                if let Some(arrow_obs_writer) = &mut arrow_obs_writer_option {
+                                    let obs_bytes = arrow_obs_writer.write(py, batched_obs_list)?;
+                                    append_bytes_le_vec(message_bytes, &obs_bytes[..]);
+                                } else if let Some(fixed_layout_obs_writer) =
+                                    &fixed_layout_obs_writer_option
+                                {
+                                    fixed_layout_obs_writer.append_vec(
+                                        message_bytes,
+                                        shm_start_addr,
+                                        batched_obs_list,
+                                        proc_id,
+                                    )?;
+                                }
+                            }
+                            // The global obs is built from the obs dict of all agents and written once per
+                            // message, using the obs serde if no global obs serde was provided
+                            if let Some(global_obs_fn) =
+                                global_obs_fn_option.as_ref().filter(|_| send_obs)
+                            {
+                                let global_obs = global_obs_fn.call1((&obs_dict,))?;
+                                append_vec_with_fallback(
+                                    global_obs_serde_option
+                                        .as_deref_mut()
+                                        .unwrap_or(obs_serde.as_mut()),
+                                    message_bytes,
+                                    Some(shm_start_addr),
+                                    &global_obs,
+                                    &serde_fallback_fn_option,
+                                )?;
+                            }
+                            // The state is serialized once for both the hash and the state field
+                            let mut state_bytes_option = None;
+                            if state_hashes {
+                                let mut state_bytes = Vec::new();
+                                state_serde_option.as_deref_mut().unwrap().append_vec(
+                                    &mut state_bytes,
+                                    None,
+                                    &env_state(&env)?,
+                                )?;
+                                append_u64_le_vec(message_bytes, fnv1a_64(&state_bytes[..]));
+                                state_bytes_option = Some(state_bytes);
+                            }
+                            if let Some(shared_info_serde) = shared_info_serde_option
+                                .as_deref_mut()
+                                .filter(|_| env_action.send_shared_info())
+                            {
+                                let shared_info = env_shared_info(&env)?;
+                                if let Some(shared_info_compression) =
+                                    &shared_info_compression_option
+                                {
+                                    let mut shared_info_bytes = Vec::new();
+                                    append_vec_with_fallback(
+                                        shared_info_serde.as_mut(),
+                                        &mut shared_info_bytes,
+                                        None,
+                                        &shared_info,
+                                        &serde_fallback_fn_option,
+                                    )?;
+                                    append_bytes_le_vec(
+                                        message_bytes,
+                                        &shared_info_compression
+                                            .compress(py, &shared_info_bytes[..])?[..],
+                                    );
+                                } else {
+                                    append_vec_with_fallback(
+                                        shared_info_serde.as_mut(),
+                                        message_bytes,
+                                        Some(shm_start_addr),
+                                        &shared_info,
+                                        &serde_fallback_fn_option,
+                                    )?;
+                                }
+                            }
+
+                            if should_send_state {
+                                let state_serde = state_serde_option.as_deref_mut().ok_or_else(|| {
+                                    InvalidStateError::new_err(format!(
+                                        "Env process {} received an env action with send_state = true, but no state serde was provided to use for serialization", proc_id
+                                    ))
+                                })?;
+                                match &state_bytes_option {
+                                    Some(state_bytes) => {
+                                        message_bytes.extend_from_slice(&state_bytes[..])
+                                    }
+                                    None => state_serde.append_vec(
+                                        message_bytes,
+                                        Some(shm_start_addr),
+                                        &env_state(&env)?,
+                                    )?,
+                                }
+                                // The rng state is written with the state so that the snapshot can be
+                                // reproduced exactly by a SET_STATE env action
+                                if let Some(rng_state_serde) = rng_state_serde_option.as_deref_mut()
+                                {
+                                    let rng_state_option = env_rng_state(&env)?;
+                                    append_bool_vec(message_bytes, rng_state_option.is_some());
+                                    if let Some(rng_state) = rng_state_option {
+                                        rng_state_serde.append_vec(
+                                            message_bytes,
+                                            Some(shm_start_addr),
+                                            &rng_state,
+                                        )?;
+                                    }
+                                }
+                            }
+                            Ok(())
+                        },
+                    )?;
+
+                    if continue_from_final_state && (last_step_terminated || last_step_truncated) {
+                        let mut final_state_bytes = Vec::new();
+                        state_serde_option.as_deref_mut().unwrap().append_vec(
+                            &mut final_state_bytes,
+                            None,
+                            &env_state(&env)?,
+                        )?;
+                        final_state_bytes_option = Some(final_state_bytes);
                     }
 
-                    if should_send_state {
-                        state_serde_option.as_deref_mut().ok_or_else(|| {
-                            InvalidStateError::new_err(format!(
-                                "Env process {} received an env action with send_state = true, but no state serde was provided to use for serialization", proc_id
-                            ))
-                        })?.append(shm_slice, offset, &env_state(&env)?)?;
+                    match message_end_option {
+                        Some(message_end) => {
+                            let message_bytes = &shm_slice[..message_end];
+                            if let Some(last_message_bytes) = &mut last_message_bytes_option {
+                                last_message_bytes.clear();
+                                last_message_bytes.extend_from_slice(message_bytes);
+                            }
+                            if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                                message_trace_writer.record_outgoing(message_bytes)?;
+                            }
+                            if let Some(message_trace_reader) = &mut message_trace_reader_option {
+                                if message_trace_reader.compare_outgoing(message_bytes)?
+                                    && !warned_message_trace_divergence
+                                {
+                                    println!(
+                                        "Env process {} wrote a response which differs from the recorded one while replaying the message trace. This warning will only be shown once.",
+                                        proc_id
+                                    );
+                                    warned_message_trace_divergence = true;
+                                }
+                            }
+                            if let Some(min_message_interval) = min_message_interval_option {
+                                if let Some(last_message_signalled) = last_message_signalled_option
+                                {
+                                    let elapsed = last_message_signalled.elapsed();
+                                    if elapsed < min_message_interval {
+                                        sleep(min_message_interval - elapsed);
+                                    }
+                                }
+                                last_message_signalled_option = Some(Instant::now());
+                            }
+                            message_transport.send_message(message_bytes)?;
+                            if let Some(obs_chunk_size) = obs_chunk_size_option.filter(|_| send_obs)
+                            {
+                                send_obs_chunks(
+                                    py,
+                                    epi_evt.as_ref(),
+                                    shm_slice,
+                                    &chunked_obs_bytes[..],
+                                    obs_chunk_size,
+                                    &child_end,
+                                    &parent_sockname,
+                                )?;
+                            }
+                        }
+                        None => {
+                            if let Some(last_message_bytes) = &mut last_message_bytes_option {
+                                last_message_bytes.clear();
+                                last_message_bytes.extend_from_slice(shm_slice);
+                            }
+                            send_buffer_truncated(&child_end, &parent_sockname)?;
+                        }
                     }
 
-                    sendto_byte(&child_end, &parent_sockname)?;
+                    if let Some(memory_watch) = &mut memory_watch_option {
+                        memory_watch.step(proc_id);
+                    }
 
                     // Render
-                    if render {
-                        env_render(&env)?;
-                        if let Some(render_delay) = render_delay_option {
-                            sleep(Duration::from_micros(
-                                (render_delay.as_micros() as f64).round() as u64,
-                            ));
+                    last_env_activity = Instant::now();
+                    n_env_actions += 1;
+                    if render
+                        && render_every_option
+                            .is_none_or(|render_every| n_env_actions.is_multiple_of(render_every))
+                        && should_render(&render_predicate_fn_option, &env)?
+                    {
+                        let render_result = match &render_thread_option {
+                            // The render thread applies the render delay itself
+                            Some(render_thread) => render_thread.publish(&env),
+                            None => env_render(&env),
+                        };
+                        match render_result {
+                            Err(err) if !render_errors_fatal => {
+                                println!(
+                                    "Env process {} failed to render the env, continuing without rendering: {}",
+                                    proc_id, err
+                                );
+                                render = false;
+                            }
+                            result => result?,
+                        }
+                        // When rendering in a group, frame timing is coordinated by the render clock
+                        if render_thread_option.is_none()
+                            && !render_clock.end_frame(render_delay_option)?
+                        {
+                            if let Some(render_delay) = render_delay_option {
+                                sleep(Duration::from_micros(
+                                    (render_delay.as_micros() as f64).round() as u64,
+                                ));
+                            }
                         }
                     }
                 }
                 Header::EnvShapesRequest => {
                     if has_received_env_action {
-                        println!("This env process (proc id {:?}) received request for env shapes, but this seems abnormal. Terminating...", proc_id);
-                        break;
+                        if strict_protocol {
+                            println!("This env process (proc id {:?}) received request for env shapes, but this seems abnormal. Terminating...", proc_id);
+                            break;
+                        }
+                        println!("This env process (proc id {:?}) received request for env shapes, but this seems abnormal. Serving the env shapes again...", proc_id);
                     }
                     let obs_space = env_obs_spaces(&env)?.values().get_item(0)?;
                     let action_space = env_action_spaces(&env)?.values().get_item(0)?;
@@ -355,15 +2827,482 @@ pub fn env_process<'py>(
                     println!("--------------------");
 
                     offset = 0;
-                    offset = obs_space_serde.append(shm_slice, offset, &obs_space)?;
-                    action_space_serde.append(shm_slice, offset, &action_space)?;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    offset = offset_tracker
+                        .advance_to(obs_space_serde.append(shm_slice, offset, &obs_space)?)?;
+                    offset = offset_tracker.advance_to(action_space_serde.append(
+                        shm_slice,
+                        offset,
+                        &action_space,
+                    )?)?;
+                    offset_tracker.advance_to(append_action_space_descriptor(
+                        shm_slice,
+                        offset,
+                        &ActionSpaceDescriptor::from_action_space(&action_space),
+                    ))?;
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::SetSeed => {
+                    let seed;
+                    (seed, offset) = retrieve_usize_le(shm_slice, offset)?;
+                    if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                        message_trace_writer.record_incoming(&shm_slice[..offset])?;
+                    }
+                    env_seed(&env, proc_id, seed)?;
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::SampleAction => {
+                    let action_spaces = env_action_spaces(&env)?;
+                    offset = 0;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    offset = offset_tracker.advance_to(append_usize_le(
+                        shm_slice,
+                        offset,
+                        agent_id_list.len(),
+                    ))?;
+                    for agent_id in agent_id_list.iter() {
+                        let action_space = action_spaces.get_item(agent_id)?.ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the action spaces dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap())))?;
+                        offset = offset_tracker.advance_to(action_serde.append(
+                            shm_slice,
+                            offset,
+                            &action_space_sample(&action_space, proc_id)?,
+                        )?)?;
+                    }
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::Observe => {
+                    let obs_dict = env_build_obs(&env, proc_id)?;
+                    offset = 0;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    offset = offset_tracker.advance_to(append_usize_le(
+                        shm_slice,
+                        offset,
+                        agent_id_list.len(),
+                    ))?;
+                    if let Some(obs_file_writer) = &mut obs_file_writer_option {
+                        obs_file_writer.start_message();
+                    }
+                    if let Some(cuda_ipc_obs_writer) = &mut cuda_ipc_obs_writer_option {
+                        cuda_ipc_obs_writer.start_message();
+                    }
+                    for agent_id in agent_id_list.iter() {
+                        offset = offset_tracker.advance_to(append_obs(
+                            select_obs_serde(&mut agent_obs_serdes_option, &mut obs_serde, agent_id)?
+                                .as_mut(),
+                            &mut obs_file_writer_option,
+                            &mut cuda_ipc_obs_writer_option,
+                            shm_slice,
+                            offset,
+                            &transform_obs(
+                                &obs_transform_fn_option,
+                                &obs_dtype_cast_option,
+                                obs_dict.get_item(agent_id)?.ok_or_else(|| InvalidStateError::new_err(format!("Env process {} tried to access the obs dict entry for agent id {}, but there was no such entry", proc_id, agent_id.repr().unwrap())))?,
+                                proc_id,
+                                agent_id,
+                            )?,
+                            &serde_fallback_fn_option,
+                        )?)?;
+                    }
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::WarmStart => {
+                    // The env is left mid-episode, and the parent sends a RESET or SET_STATE env
+                    // action before stepping again, which recalculates the agent id list of the
+                    // episode
+                    let warm_start_steps;
+                    (warm_start_steps, offset) = retrieve_usize_le(shm_slice, offset)?;
+                    if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                        message_trace_writer.record_incoming(&shm_slice[..offset])?;
+                    }
+                    let default_action = default_action_option.as_ref().ok_or_else(|| {
+                        InvalidStateError::new_err(format!(
+                            "Env process {} received a WarmStart request, but no default action was provided to warm start with",
+                            proc_id
+                        ))
+                    })?;
+                    env_warm_start(
+                        &env,
+                        &agent_id_list[..],
+                        warm_start_steps,
+                        default_action,
+                        &action_interface,
+                    )?;
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::SetStateBatch => {
+                    // The env is left in the last state of the batch, and the parent sends a
+                    // RESET or SET_STATE env action before stepping again, which recalculates the
+                    // agent id list of the episode
+                    let state_serde = state_serde_option.as_deref_mut().ok_or_else(|| {
+                        InvalidStateError::new_err(format!(
+                            "Env process {} received a SetStateBatch request, but no state serde was provided to use for deserialization",
+                            proc_id
+                        ))
+                    })?;
+                    let n_states;
+                    (n_states, offset) = retrieve_usize_le(shm_slice, offset)?;
+                    let mut state_list = Vec::with_capacity(n_states);
+                    for _ in 0..n_states {
+                        let state;
+                        (state, offset) = state_serde.retrieve(py, shm_slice, offset)?;
+                        state_list.push(state);
+                    }
+                    if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                        message_trace_writer.record_incoming(&shm_slice[..offset])?;
+                    }
+                    offset = 0;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    if let Some(obs_file_writer) = &mut obs_file_writer_option {
+                        obs_file_writer.start_message();
+                    }
+                    if let Some(cuda_ipc_obs_writer) = &mut cuda_ipc_obs_writer_option {
+                        cuda_ipc_obs_writer.start_message();
+                    }
+                    for state in state_list.iter() {
+                        let obs_dict = env_set_state(&env, state)?;
+                        let batch_agent_id_list = get_agent_id_list(
+                            &obs_dict,
+                            sort_agent_ids,
+                            &agent_id_sort_key_fn_option,
+                            &agent_order_permutation_option,
+                        )?;
+                        offset = offset_tracker.advance_to(append_usize_le(
+                            shm_slice,
+                            offset,
+                            batch_agent_id_list.len(),
+                        ))?;
+                        for agent_id in batch_agent_id_list.iter() {
+                            offset = offset_tracker
+                                .advance_to(agent_id_serde.append(shm_slice, offset, agent_id)?)?;
+                            offset = offset_tracker.advance_to(append_obs(
+                                select_obs_serde(
+                                    &mut agent_obs_serdes_option,
+                                    &mut obs_serde,
+                                    agent_id,
+                                )?
+                                .as_mut(),
+                                &mut obs_file_writer_option,
+                                &mut cuda_ipc_obs_writer_option,
+                                shm_slice,
+                                offset,
+                                &transform_obs(
+                                    &obs_transform_fn_option,
+                                    &obs_dtype_cast_option,
+                                    obs_dict.get_item(agent_id)?.unwrap(),
+                                    proc_id,
+                                    agent_id,
+                                )?,
+                                &serde_fallback_fn_option,
+                            )?)?;
+                        }
+                    }
+                    last_env_activity = Instant::now();
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::DumpState => {
+                    let path_bytes;
+                    (path_bytes, offset) = retrieve_bytes_le(shm_slice, offset)?;
+                    let path = String::from_utf8(path_bytes.to_vec())?;
+                    if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                        message_trace_writer.record_incoming(&shm_slice[..offset])?;
+                    }
+                    if let Some(parent_dir) = Path::new(&path)
+                        .parent()
+                        .filter(|parent_dir| !parent_dir.as_os_str().is_empty())
+                    {
+                        if !parent_dir.is_dir() {
+                            return Err(InvalidStateError::new_err(format!(
+                                "Env process {} received a DumpState request for path {}, but its parent directory {} does not exist",
+                                proc_id,
+                                path,
+                                parent_dir.display()
+                            )));
+                        }
+                    }
+                    let state_serde = state_serde_option.as_deref_mut().ok_or_else(|| {
+                        InvalidStateError::new_err(format!(
+                            "Env process {} received a DumpState request, but no state serde was provided to use for serialization",
+                            proc_id
+                        ))
+                    })?;
+                    let mut state_bytes = Vec::new();
+                    state_serde.append_vec(&mut state_bytes, None, &env_state(&env)?)?;
+                    std::fs::write(path, state_bytes)?;
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::DumpBuffer => {
+                    let buffer_dump_dir = buffer_dump_dir_option.as_ref().ok_or_else(|| {
+                        InvalidStateError::new_err(format!(
+                            "Env process {} received a DumpBuffer request, but no buffer dump dir was provided",
+                            proc_id
+                        ))
+                    })?;
+                    let message_bytes = last_message_bytes_option.as_deref().unwrap();
+                    std::fs::create_dir_all(buffer_dump_dir)?;
+                    let path = format!("{}/{}.hex", buffer_dump_dir, proc_id);
+                    std::fs::write(&path, hex_dump(message_bytes))?;
+                    println!(
+                        "Env process {} dumped the {} bytes of its last env action response to {}",
+                        proc_id,
+                        message_bytes.len(),
+                        path
+                    );
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::Reconfigure => {
+                    // The parent only sends this between collecting a response and sending the
+                    // next env action, so no message written with the old serdes is in flight
+                    let (obs_serde_option, action_serde_option, reward_serde_option);
+                    (obs_serde_option, offset) = retrieve_serde_option(shm_slice, offset)?;
+                    (action_serde_option, offset) = retrieve_serde_option(shm_slice, offset)?;
+                    (reward_serde_option, offset) = retrieve_serde_option(shm_slice, offset)?;
+                    if let Some(message_trace_writer) = &mut message_trace_writer_option {
+                        message_trace_writer.record_incoming(&shm_slice[..offset])?;
+                    }
+                    // Agents with their own obs serde would keep writing with it, so the parent
+                    // would read their obs with the wrong serde. The global obs fallback and the
+                    // critic obs look up their serde per message and need no swapping here
+                    if obs_serde_option.is_some() && agent_obs_serdes_option.is_some() {
+                        return Err(InvalidStateError::new_err(format!(
+                            "Env process {} received a Reconfigure request for the obs serde, but it uses agent obs serdes, which cannot be reconfigured",
+                            proc_id
+                        )));
+                    }
+                    if let Some(new_obs_serde) = obs_serde_option {
+                        obs_serde = new_obs_serde;
+                    }
+                    if let Some(new_action_serde) = action_serde_option {
+                        action_serde = new_action_serde;
+                    }
+                    if let Some(new_reward_serde) = reward_serde_option {
+                        reward_serde = new_reward_serde;
+                    }
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::GetConfig => {
+                    // The config is serialized with the shared info serde, and is reported as
+                    // absent if there is no shared info serde to serialize it with
+                    offset = 0;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    match (&env_config_option, shared_info_serde_option.as_deref_mut()) {
+                        (Some(env_config), Some(shared_info_serde)) => {
+                            offset =
+                                offset_tracker.advance_to(append_bool(shm_slice, offset, true))?;
+                            offset_tracker.advance_to(append_with_fallback(
+                                shared_info_serde.as_mut(),
+                                shm_slice,
+                                offset,
+                                env_config,
+                                &serde_fallback_fn_option,
+                            )?)?;
+                        }
+                        _ => {
+                            offset_tracker.advance_to(append_bool(shm_slice, offset, false))?;
+                        }
+                    }
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::GetActionHistory => {
+                    offset = 0;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    offset = offset_tracker.advance_to(append_usize_le(
+                        shm_slice,
+                        offset,
+                        action_history.len(),
+                    ))?;
+                    for step_history in action_history.iter() {
+                        offset = offset_tracker.advance_to(append_usize_le(
+                            shm_slice,
+                            offset,
+                            step_history.len(),
+                        ))?;
+                        for (agent_id, action, reward) in step_history.iter() {
+                            offset = offset_tracker.advance_to(agent_id_serde.append(
+                                shm_slice,
+                                offset,
+                                agent_id.bind(py),
+                            )?)?;
+                            offset = offset_tracker.advance_to(action_serde.append(
+                                shm_slice,
+                                offset,
+                                action.bind(py),
+                            )?)?;
+                            offset = offset_tracker.advance_to(reward_serde.append(
+                                shm_slice,
+                                offset,
+                                reward.bind(py),
+                            )?)?;
+                        }
+                    }
+                    sendto_byte(&child_end, &parent_sockname)?;
+                }
+                Header::AgentCount => {
+                    let include_agent_ids;
+                    (include_agent_ids, _) = retrieve_bool(shm_slice, offset)?;
+                    offset = 0;
+                    let mut offset_tracker = OffsetTracker::new(0, shm_slice.len());
+                    offset = offset_tracker.advance_to(append_usize_le(
+                        shm_slice,
+                        offset,
+                        agent_id_list.len(),
+                    ))?;
+                    if include_agent_ids {
+                        for agent_id in agent_id_list.iter() {
+                            offset = offset_tracker
+                                .advance_to(agent_id_serde.append(shm_slice, offset, agent_id)?)?;
+                        }
+                    }
                     sendto_byte(&child_end, &parent_sockname)?;
                 }
                 Header::Stop => {
+                    received_stop = true;
                     break;
                 }
             }
         }
+
+        // Clean up before acknowledging the stop, so that the parent knows this process stopped
+        // cleanly and can join it
+        if env.hasattr(intern!(py, "close"))? {
+            env.call_method0(intern!(py, "close"))?;
+        }
+        drop(epi_evt);
+        drop(shmem);
+        if received_stop {
+            sendto_byte(&child_end, &parent_sockname)?;
+        }
         Ok(())
-    })
+    });
+    // Report the error to the parent, so that it can raise it instead of waiting for an
+    // acknowledgement which will never come
+    if let Err(err) = &result {
+        send_error_report(&child_end, &parent_sockname, err)?;
+    }
+    result
+}
+
+type RunEnvStepResult = (
+    Vec<PyObject>,
+    Vec<PyObject>,
+    Vec<PyObject>,
+    Vec<bool>,
+    Vec<bool>,
+);
+
+fn get_values_in_agent_order<'py>(
+    dict: &Bound<'py, PyDict>,
+    agent_id_list: &[Bound<'py, PyAny>],
+    dict_name: &str,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    agent_id_list
+        .iter()
+        .map(|agent_id| {
+            dict.get_item(agent_id)?.ok_or_else(|| {
+                InvalidStateError::new_err(format!(
+                    "Tried to access the {} dict entry for agent id {}, but there was no such entry",
+                    dict_name,
+                    agent_id.repr().unwrap()
+                ))
+            })
+        })
+        .collect()
+}
+
+fn unbind_all(obj_list: Vec<Bound<'_, PyAny>>) -> Vec<PyObject> {
+    obj_list.into_iter().map(|obj| obj.unbind()).collect()
+}
+
+// Builds the env, resets it, and steps it once per entry of actions_per_step_list, where each entry
+// is a list of actions parallel to the current agent id list. The shared info setter for each step,
+// if provided, is applied after that step as in env_process. No serialization or shared memory is
+// involved, so this can be used to test an env's interaction with the stepping logic in-process.
+// Returns the agent id list and obs list from the reset, and for each step the agent id list, obs
+// list, reward list, terminated list, and truncated list.
+#[pyfunction]
+#[pyo3(signature = (
+    build_env_fn,
+    actions_per_step_list,
+    shared_info_setter_per_step_list_option=None,
+    recalculate_agent_id_every_step=false,
+    sort_agent_ids=false,
+    agent_id_sort_key_fn_option=None,
+    shared_info_setter_keys_option=None,
+    ))]
+pub fn run_env_steps<'py>(
+    build_env_fn: Bound<'py, PyAny>,
+    actions_per_step_list: Vec<Vec<Bound<'py, PyAny>>>,
+    shared_info_setter_per_step_list_option: Option<Vec<Option<Bound<'py, PyDict>>>>,
+    recalculate_agent_id_every_step: bool,
+    sort_agent_ids: bool,
+    agent_id_sort_key_fn_option: Option<Bound<'py, PyAny>>,
+    shared_info_setter_keys_option: Option<Vec<String>>,
+) -> PyResult<(Vec<PyObject>, Vec<PyObject>, Vec<RunEnvStepResult>)> {
+    let py = build_env_fn.py();
+    let env = build_env_fn.call0()?;
+    let reset_obs_dict = env_reset(&env)?;
+    let mut agent_id_list = get_agent_id_list(
+        &reset_obs_dict,
+        sort_agent_ids,
+        &agent_id_sort_key_fn_option,
+        &None,
+    )?;
+    let reset_obs_list = unbind_all(get_values_in_agent_order(
+        &reset_obs_dict,
+        &agent_id_list,
+        "obs",
+    )?);
+    let reset_agent_id_list = unbind_all(agent_id_list.clone());
+    let mut step_result_list = Vec::with_capacity(actions_per_step_list.len());
+    for (step_idx, action_list) in actions_per_step_list.into_iter().enumerate() {
+        if action_list.len() != agent_id_list.len() {
+            return Err(InvalidStateError::new_err(format!(
+                "Step {} was given {} actions, but there are {} agents",
+                step_idx,
+                action_list.len(),
+                agent_id_list.len()
+            )));
+        }
+        let actions_dict = PyDict::from_sequence(
+            &agent_id_list
+                .iter()
+                .cloned()
+                .zip(action_list)
+                .collect::<Vec<_>>()
+                .into_pyobject(py)?,
+        )?;
+        let (obs_dict, rew_dict, terminated_dict, truncated_dict) =
+            env_step(&env, actions_dict.into_any())?;
+        if let Some(Some(shared_info_setter)) = shared_info_setter_per_step_list_option
+            .as_ref()
+            .and_then(|shared_info_setter_list| shared_info_setter_list.get(step_idx))
+        {
+            apply_shared_info_setter(&env, shared_info_setter, &shared_info_setter_keys_option)?;
+        }
+        if recalculate_agent_id_every_step {
+            agent_id_list = get_agent_id_list(
+                &obs_dict,
+                sort_agent_ids,
+                &agent_id_sort_key_fn_option,
+                &None,
+            )?;
+        }
+        step_result_list.push((
+            unbind_all(agent_id_list.clone()),
+            unbind_all(get_values_in_agent_order(&obs_dict, &agent_id_list, "obs")?),
+            unbind_all(get_values_in_agent_order(
+                &rew_dict,
+                &agent_id_list,
+                "reward",
+            )?),
+            get_values_in_agent_order(&terminated_dict, &agent_id_list, "terminated")?
+                .iter()
+                .map(|terminated| terminated.extract::<bool>())
+                .collect::<PyResult<_>>()?,
+            get_values_in_agent_order(&truncated_dict, &agent_id_list, "truncated")?
+                .iter()
+                .map(|truncated| truncated.extract::<bool>())
+                .collect::<PyResult<_>>()?,
+        ));
+    }
+    Ok((reset_agent_id_list, reset_obs_list, step_result_list))
 }
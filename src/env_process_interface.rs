@@ -4,44 +4,126 @@
 use std::cmp::max;
 use std::cmp::min;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::mem::size_of;
 use std::thread;
 use std::time::Duration;
 
 use itertools::izip;
 use itertools::Itertools;
-use pyany_serde::DynPyAnySerdeOption;
+use pyany_serde::PyAnySerdeType;
 use pyany_serde::{
-    communication::{retrieve_bool, retrieve_usize},
+    communication::{append_bool, append_bool_vec, insert_bytes, retrieve_bool},
     PyAnySerde,
 };
-use pyo3::types::PyString;
+use pyo3::types::{PyNone, PyString};
 use pyo3::{
-    exceptions::asyncio::InvalidStateError, intern, prelude::*, sync::GILOnceCell, types::PyDict,
+    exceptions::{asyncio::InvalidStateError, PyTimeoutError},
+    intern,
+    prelude::*,
+    sync::GILOnceCell,
+    types::PyDict,
 };
 use raw_sync::events::Event;
 use raw_sync::events::EventInit;
 use raw_sync::events::EventState;
-use shared_memory::Shmem;
 use shared_memory::ShmemConf;
 
-use crate::env_action::append_env_action;
+use crate::action_space_descriptor::{retrieve_action_space_descriptor, ActionSpaceDescriptor};
+use crate::agent_obs_serdes::{select_obs_serde, AgentObsSerdes};
+use crate::arrow_ipc::{read_arrow_obs_list, ObsFormat};
+use crate::communication::{
+    aligned_offset, append_bytes_le_vec, append_serde_type_option_vec, append_usize_le,
+    append_usize_le_vec, retrieve_agent_id_fields, retrieve_bitset, retrieve_bytes_le,
+    retrieve_u64_le, retrieve_u8, retrieve_usize_le,
+};
+use crate::compression::CompressionCodec;
+use crate::cuda_ipc::retrieve_cuda_ipc_obs;
 use crate::env_action::EnvAction;
-use crate::synchronization::{append_header, get_flink, recvfrom_byte, sendto_byte, Header};
+use crate::env_action::{append_env_action, EnvActionSerdes};
+use crate::env_action::{retrieve_episode_boundary_reason, EpisodeBoundaryReason};
+use crate::env_process_options::{
+    AgentOptions, BufferOptions, EpisodeOptions, ObsOptions, SerdeOptions,
+};
+use crate::fixed_layout::read_fixed_layout_obs_list;
+use crate::obs_file::ObsFileReader;
+use crate::partitioned_buffer::{
+    describe_base_offset_option, retrieve_base_offset_option, PartitionedBuffer, ShmemRegion,
+};
+use crate::serde_version::{
+    extract_serde_option, serde_type_version, SerdeVersionList, VersionedSerde,
+    VersionedSerdeOption,
+};
+use crate::state_pool::StatePool;
+use crate::synchronization::{
+    append_header, get_flink, get_resized_flink, recvfrom_ack, recvfrom_byte, sendto_byte, Header,
+};
 use crate::timestep::Timestep;
+use crate::transport::{get_mqueue_name, Mqueue};
+
+// Opens the shared memory buffer of an env process at the flink. Returns the buffer and the number
+// of bytes used by the event at its start.
+fn open_shmem(flink: &str) -> PyResult<(ShmemRegion, usize)> {
+    let shmem = ShmemConf::new().flink(flink).open().map_err(|err| {
+        InvalidStateError::new_err(format!("Unable to open shmem flink {}: {}", flink, err))
+    })?;
+    open_event(ShmemRegion::new(shmem))
+}
+
+// Returns the buffer along with the number of bytes used by the event at its start
+fn open_event(shmem: ShmemRegion) -> PyResult<(ShmemRegion, usize)> {
+    let (_, used_bytes) = unsafe {
+        Event::from_existing(shmem.as_ptr())
+            .map_err(|err| InvalidStateError::new_err(format!("Failed to get event: {}", err)))?
+    };
+    Ok((shmem, used_bytes))
+}
 
 fn sync_with_env_process<'py>(
     socket: &Bound<'py, PyAny>,
     address: &Bound<'py, PyAny>,
+    proc_id: &str,
 ) -> PyResult<()> {
-    recvfrom_byte(socket)?;
+    recvfrom_ack(socket, proc_id)?;
     sendto_byte(socket, address)
 }
 
+// Receives the obs chunks an env process sends after its message, signaling the event before each one
+// to let the env process know that the previous message has been read. Returns the concatenated
+// chunks.
+fn receive_obs_chunks(
+    parent_end: &Bound<'_, PyAny>,
+    shmem: &ShmemRegion,
+    proc_id: &str,
+) -> PyResult<Vec<u8>> {
+    let (ep_evt, used_bytes) = unsafe {
+        Event::from_existing(shmem.as_ptr())
+            .map_err(|err| InvalidStateError::new_err(format!("Failed to get event: {}", err)))?
+    };
+    let shm_slice = unsafe { &shmem.as_slice()[used_bytes..] };
+    let mut obs_bytes = Vec::new();
+    loop {
+        ep_evt
+            .set(EventState::Signaled)
+            .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+        recvfrom_ack(parent_end, proc_id)?;
+        let (chunk_idx, offset) = retrieve_usize_le(shm_slice, 0)?;
+        let (n_chunks, offset) = retrieve_usize_le(shm_slice, offset)?;
+        let (chunk, _) = retrieve_bytes_le(shm_slice, offset)?;
+        obs_bytes.extend_from_slice(chunk);
+        if chunk_idx + 1 >= n_chunks {
+            return Ok(obs_bytes);
+        }
+    }
+}
+
 type ObsDataKV<'py> = (
     Bound<'py, PyString>,
     (Vec<PyObject>, Vec<Bound<'py, PyAny>>),
 );
 
+type AgentIdObsLists<'py> = (Vec<Bound<'py, PyAny>>, Vec<Bound<'py, PyAny>>);
+
 type TimestepDataKV<'py> = (
     Bound<'py, PyString>,
     (
@@ -62,6 +144,8 @@ type StateInfoKV<'py> = (
     ),
 );
 
+type ActionHistoryEntry<'py> = (Bound<'py, PyAny>, Bound<'py, PyAny>, Bound<'py, PyAny>);
+
 static SELECTORS_EVENT_READ: GILOnceCell<u8> = GILOnceCell::new();
 
 #[pyclass(module = "rlgym_learn", unsendable)]
@@ -75,19 +159,93 @@ pub struct EnvProcessInterface {
     shared_info_serde_option: Option<Box<dyn PyAnySerde>>,
     shared_info_setter_serde_option: Option<Box<dyn PyAnySerde>>,
     state_serde_option: Option<Box<dyn PyAnySerde>>,
+    reward_component_serde_option: Option<Box<dyn PyAnySerde>>,
+    critic_obs_serde_option: Option<Box<dyn PyAnySerde>>,
+    episode_boundary_reasons: bool,
+    done_reasons: bool,
+    bootstrap_flags: bool,
+    dedup_set_state_obs: bool,
+    reuse_identical_obs: bool,
+    obs_format: ObsFormat,
+    fixed_layout_obs_shape_option: Option<Vec<usize>>,
+    // Whether the env processes write the hash of the serialized state with every message
+    state_hashes: bool,
+    // Whether the env processes write the number of env steps performed with every step message
+    env_step_counts: bool,
+    // Whether the env processes send the obs of each message in chunks after it
+    chunked_obs: bool,
+    // Whether the env processes write the previous action of each agent with every step message
+    prev_actions: bool,
+    // Whether the env processes write the terminated and truncated flags of step messages as bitsets
+    packed_done_flags: bool,
+    // The alignment the env processes pad the region of each agent to, if any
+    region_alignment_option: Option<usize>,
+    // The obs serdes used instead of the obs serde for specific agent ids, if any
+    agent_obs_serdes_option: Option<AgentObsSerdes>,
+    // Whether the env processes write their total env steps and episodes with every env action
+    // response
+    include_counters: bool,
+    // Whether the env processes write the stable index of each agent with its agent id
+    stable_agent_indices: bool,
+    // Whether the env processes write whether each agent is new with its agent id
+    new_agent_flags: bool,
+    // Whether the env processes send their env action responses through POSIX message queues
+    // instead of leaving them in shared memory
+    mqueue_transport: bool,
+    // Used for the rng states written with states and sent with SET_STATE env actions, if any
+    rng_state_serde_option: Option<Box<dyn PyAnySerde>>,
+    cuda_ipc_obs: bool,
+    // Whether the env processes write a global obs, which is retrieved with the global obs serde, or
+    // the obs serde if none was provided
+    global_obs: bool,
+    global_obs_serde_option: Option<Box<dyn PyAnySerde>>,
+    legal_action_mask_serde_option: Option<Box<dyn PyAnySerde>>,
+    // The versions of the serdes above, which are checked against those of each env process during
+    // the startup handshake
+    serde_version_list: SerdeVersionList,
     recalculate_agent_id_every_step: bool,
     flinks_folder: String,
-    proc_packages: Vec<(PyObject, Shmem, usize, String)>,
+    proc_packages: Vec<(PyObject, ShmemRegion, usize, String)>,
     min_process_steps_per_inference: usize,
+    shared_info_compression_option: Option<CompressionCodec>,
     selector: PyObject,
     proc_id_pid_idx_map: HashMap<String, usize>,
+    proc_id_episode_boundary_reason_map: HashMap<String, EpisodeBoundaryReason>,
+    proc_id_legal_action_mask_list_map: HashMap<String, Vec<PyObject>>,
+    proc_id_prev_action_list_map: HashMap<String, Vec<Option<PyObject>>>,
+    // The (total env steps, episodes) of the most recent env action response of each process
+    proc_id_counters_map: HashMap<String, (usize, usize)>,
+    proc_id_new_agent_flag_list_map: HashMap<String, Vec<bool>>,
+    // The stable agent indices of the most recent agent id list of each process
+    proc_id_agent_index_list_map: HashMap<String, Vec<usize>>,
+    // Opened on the first env action response received from each process with mqueue_transport
+    proc_id_mqueue_map: HashMap<String, Mqueue>,
+    // The processes which reset their env when idle, and so start every STEP response with whether it
+    // was answered with the episode started by such a reset instead
+    keepalive_reset_proc_ids: HashSet<String>,
+    // The processes which replace their shared memory buffer with a larger one when their agents no
+    // longer fit, and so start every env action response with whether they did
+    resizable_buffer_proc_ids: HashSet<String>,
+    // The rng state written with the most recent state of each process, if the env exposes one
+    proc_id_rng_state_map: HashMap<String, PyObject>,
     pid_idx_current_env_action: Vec<Option<EnvAction>>,
     pid_idx_current_agent_id_list_option: Vec<Option<Vec<PyObject>>>,
     pid_idx_prev_timestep_id_option_list_option: Vec<Option<Vec<Option<u128>>>>,
     pid_idx_current_obs_list: Vec<Vec<PyObject>>,
+    pid_idx_current_critic_obs_list: Vec<Vec<PyObject>>,
+    pid_idx_current_global_obs_option: Vec<Option<PyObject>>,
+    pid_idx_current_state_hash_option: Vec<Option<u64>>,
     pid_idx_current_action_list: Vec<Vec<PyObject>>,
     pid_idx_current_aald_option: Vec<Option<PyObject>>,
     just_initialized_pid_idx_list: Vec<usize>,
+    state_pool_option: Option<StatePool>,
+    state_pool_state_list: Vec<Option<PyObject>>,
+    // The buffer partitioned among the env processes if partition_bytes_option was provided, in
+    // which case the process at each index uses the partition at the same index
+    partitioned_buffer_option: Option<PartitionedBuffer>,
+    obs_file_dir_option: Option<String>,
+    proc_id_obs_file_reader_map: HashMap<String, ObsFileReader>,
+    action_space_descriptor_option: Option<ActionSpaceDescriptor>,
 }
 
 impl EnvProcessInterface {
@@ -95,7 +253,7 @@ impl EnvProcessInterface {
         &mut self,
         py: Python<'py>,
     ) -> PyResult<(Bound<'py, PyAny>, Bound<'py, PyAny>)> {
-        let (parent_end, shmem, _, _) = self.proc_packages.get_mut(0).unwrap();
+        let (parent_end, shmem, _, proc_id) = self.proc_packages.get_mut(0).unwrap();
         let (ep_evt, used_bytes) = unsafe {
             Event::from_existing(shmem.as_ptr()).map_err(|err| {
                 InvalidStateError::new_err(format!("Failed to get event: {}", err.to_string()))
@@ -106,12 +264,15 @@ impl EnvProcessInterface {
         ep_evt
             .set(EventState::Signaled)
             .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
-        recvfrom_byte(parent_end.bind(py))?;
+        recvfrom_ack(parent_end.bind(py), proc_id)?;
         let mut offset = 0;
         let obs_space;
         (obs_space, offset) = self.obs_space_serde.retrieve(py, shm_slice, offset)?;
         let action_space;
-        (action_space, _) = self.action_space_serde.retrieve(py, shm_slice, offset)?;
+        (action_space, offset) = self.action_space_serde.retrieve(py, shm_slice, offset)?;
+        let action_space_descriptor;
+        (action_space_descriptor, _) = retrieve_action_space_descriptor(shm_slice, offset)?;
+        self.action_space_descriptor_option = Some(action_space_descriptor);
         Ok((obs_space, action_space))
     }
 
@@ -126,19 +287,54 @@ impl EnvProcessInterface {
         ),
     ) -> PyResult<()> {
         let (_, parent_end, child_sockname, proc_id) = proc_package_def;
-        sync_with_env_process(&parent_end, &child_sockname)?;
-        let flink = get_flink(&self.flinks_folder[..], proc_id.as_str());
-        let shmem = ShmemConf::new()
-            .flink(flink.clone())
-            .open()
-            .map_err(|err| {
-                InvalidStateError::new_err(format!("Unable to open shmem flink {}: {}", flink, err))
-            })?;
-        let (_, used_bytes) = unsafe {
-            Event::from_existing(shmem.as_ptr()).map_err(|err| {
-                InvalidStateError::new_err(format!("Failed to get event: {}", err.to_string()))
-            })?
+        sync_with_env_process(&parent_end, &child_sockname, &proc_id)?;
+        // With a partitioned buffer, the process at each index uses the partition at the same index
+        let expected_base_offset_option = self.partition_base_offset(self.proc_packages.len())?;
+        let (shmem, used_bytes) = match expected_base_offset_option {
+            Some(base_offset) => open_event(PartitionedBuffer::open_partition(
+                &self.flinks_folder,
+                base_offset,
+            )?)?,
+            None => open_shmem(&get_flink(&self.flinks_folder[..], proc_id.as_str()))?,
         };
+        let shm_slice = unsafe { &shmem.as_slice()[used_bytes..] };
+        // The base offset comes first, so that a process which wrote its handshake to another
+        // partition is reported as such rather than as a serde version mismatch
+        let (base_offset_option, offset) = retrieve_base_offset_option(shm_slice, 0)?;
+        if base_offset_option != expected_base_offset_option {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} reported using {} in its handshake, but the parent expected it to use {}",
+                proc_id,
+                describe_base_offset_option(base_offset_option),
+                describe_base_offset_option(expected_base_offset_option)
+            )));
+        }
+        let offset = self.serde_version_list.check(shm_slice, offset, &proc_id)?;
+        let (uses_mqueue_transport, offset) = retrieve_bool(shm_slice, offset)?;
+        if uses_mqueue_transport != self.mqueue_transport {
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} {} the message queue transport, but the parent's buffer options {}",
+                proc_id,
+                if uses_mqueue_transport {
+                    "uses"
+                } else {
+                    "does not use"
+                },
+                if self.mqueue_transport {
+                    "do"
+                } else {
+                    "do not"
+                }
+            )));
+        }
+        let (keepalive_resets, offset) = retrieve_bool(shm_slice, offset)?;
+        if keepalive_resets {
+            self.keepalive_reset_proc_ids.insert(proc_id.clone());
+        }
+        let (resizable_buffer, _) = retrieve_bool(shm_slice, offset)?;
+        if resizable_buffer {
+            self.resizable_buffer_proc_ids.insert(proc_id.clone());
+        }
         self.selector.call_method1(
             py,
             intern!(py, "register"),
@@ -163,6 +359,67 @@ impl EnvProcessInterface {
         Ok(())
     }
 
+    // Sends a header (followed by the provided payload) to a process outside of the env action cycle
+    // and blocks until the process acknowledges it. This must only be used for a process which is not
+    // currently working on an env action, i.e. its latest response must have been collected already.
+    // Returns the pid idx of the process so that the response can be read from its shared memory.
+    fn send_header_and_wait<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: &str,
+        header: Header,
+        payload: &[u8],
+    ) -> PyResult<usize> {
+        let &pid_idx = self.proc_id_pid_idx_map.get(proc_id).ok_or_else(|| {
+            InvalidStateError::new_err(format!(
+                "Tried to send {} header to env process with proc id {}, but there is no such process",
+                header, proc_id
+            ))
+        })?;
+        let (parent_end, shmem, _, _) = self.proc_packages.get_mut(pid_idx).unwrap();
+        let (ep_evt, used_bytes) = unsafe {
+            Event::from_existing(shmem.as_ptr()).map_err(|err| {
+                InvalidStateError::new_err(format!("Failed to get event: {}", err))
+            })?
+        };
+        let shm_slice = unsafe { &mut shmem.as_slice_mut()[used_bytes..] };
+        let offset = append_header(shm_slice, 0, header);
+        insert_bytes(shm_slice, offset, payload);
+        ep_evt
+            .set(EventState::Signaled)
+            .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+        recvfrom_ack(parent_end.bind(py), proc_id)?;
+        Ok(pid_idx)
+    }
+
+    // Opens the buffers the process moved to since its last env action response, if any. Returns the
+    // offset of the response in the current buffer.
+    fn follow_buffer_resizes(&mut self, pid_idx: usize) -> PyResult<usize> {
+        let (_, shmem, used_bytes, proc_id) = &mut self.proc_packages[pid_idx];
+        loop {
+            let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+            let (resized, offset) = retrieve_bool(shm_slice, 0)?;
+            if !resized {
+                return Ok(offset);
+            }
+            let (buffer_generation, _) = retrieve_usize_le(shm_slice, offset)?;
+            (*shmem, *used_bytes) = open_shmem(&get_resized_flink(
+                &self.flinks_folder,
+                proc_id,
+                buffer_generation,
+            ))?;
+        }
+    }
+
+    fn receive_mqueue_message(&mut self, pid_idx: usize) -> PyResult<Vec<u8>> {
+        let proc_id = &self.proc_packages[pid_idx].3;
+        if !self.proc_id_mqueue_map.contains_key(proc_id) {
+            self.proc_id_mqueue_map
+                .insert(proc_id.clone(), Mqueue::open(&get_mqueue_name(proc_id))?);
+        }
+        self.proc_id_mqueue_map[proc_id].receive()
+    }
+
     // Returns number of timesteps collected, plus three kv pairs: the keys are all the proc id,
     // and the values are (agent id list, obs list),
     // (timestep list, optional state metrics, optional state),
@@ -172,6 +429,21 @@ impl EnvProcessInterface {
         py: Python<'py>,
         pid_idx: usize,
     ) -> PyResult<(usize, ObsDataKV<'py>, TimestepDataKV<'py>, StateInfoKV<'py>)> {
+        // With mqueue_transport, the response is received from the message queue and read the same
+        // way as from shared memory
+        let mqueue_message_option = if self.mqueue_transport {
+            Some(self.receive_mqueue_message(pid_idx)?)
+        } else {
+            None
+        };
+        let response_offset = if self
+            .resizable_buffer_proc_ids
+            .contains(&self.proc_packages[pid_idx].3)
+        {
+            self.follow_buffer_resizes(pid_idx)?
+        } else {
+            0
+        };
         let env_action = self.pid_idx_current_env_action[pid_idx]
             .as_ref()
             .ok_or_else(|| {
@@ -179,7 +451,8 @@ impl EnvProcessInterface {
                     "Tried to collect response from env which doesn't have an env action yet",
                 )
             })?;
-        let is_step_action;
+        let is_set_state_action = matches!(env_action, EnvAction::SET_STATE { .. });
+        let mut is_step_action;
         let send_state = match env_action {
             EnvAction::STEP { send_state, .. } => {
                 is_step_action = true;
@@ -194,10 +467,22 @@ impl EnvProcessInterface {
                 *send_state
             }
         };
-        let new_episode = !is_step_action;
         let (_, shmem, used_bytes, proc_id) = self.proc_packages.get(pid_idx).unwrap();
-        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
-        let mut offset = 0;
+        let shm_slice = match &mqueue_message_option {
+            Some(mqueue_message) => &mqueue_message[..],
+            None => unsafe { &shmem.as_slice()[*used_bytes..] },
+        };
+        let mut offset = response_offset;
+        // A STEP answered with the episode started by a keepalive reset is read as a RESET response
+        let mut is_keepalive_reset = false;
+        if is_step_action && self.keepalive_reset_proc_ids.contains(proc_id) {
+            (is_keepalive_reset, offset) = retrieve_bool(shm_slice, offset)?;
+            is_step_action = !is_keepalive_reset;
+        }
+        let new_episode = !is_step_action;
+        // Without obs, the obs of each agent is None and the critic obs, global obs and legal action
+        // masks are absent
+        let send_obs = is_keepalive_reset || env_action.send_obs();
         let current_agent_id_list_option = self
             .pid_idx_current_agent_id_list_option
             .get_mut(pid_idx)
@@ -210,12 +495,22 @@ impl EnvProcessInterface {
             mut agent_id_list,
             mut obs_list,
             mut reward_list_option,
+            mut reward_components_list_option,
             mut terminated_list_option,
             mut truncated_list_option,
+            mut should_bootstrap_list_option,
+            mut done_reason_list_option,
         );
 
+        if new_episode && self.episode_boundary_reasons {
+            let episode_boundary_reason;
+            (episode_boundary_reason, offset) =
+                retrieve_episode_boundary_reason(shm_slice, offset)?;
+            self.proc_id_episode_boundary_reason_map
+                .insert(proc_id.clone(), episode_boundary_reason);
+        }
         if new_episode {
-            (n_agents, offset) = retrieve_usize(shm_slice, offset)?;
+            (n_agents, offset) = retrieve_usize_le(shm_slice, offset)?;
             agent_id_list = Vec::with_capacity(n_agents);
         } else {
             let current_agent_id_list = current_agent_id_list_option.unwrap();
@@ -227,43 +522,243 @@ impl EnvProcessInterface {
             }
         }
         obs_list = Vec::with_capacity(n_agents);
+        // Empty if there is no critic obs serde
+        let mut critic_obs_list = Vec::new();
+        // Empty if there is no legal action mask serde
+        let mut legal_action_mask_list = Vec::new();
+        // Empty unless prev_actions is set
+        let mut prev_action_list = Vec::new();
+        // Empty unless stable_agent_indices is set and the agent ids are sent
+        let mut agent_index_list = Vec::new();
+        // Empty unless new_agent_flags is set
+        let mut new_agent_flag_list = Vec::new();
         if is_step_action {
             reward_list_option = Some(Vec::with_capacity(n_agents));
+            reward_components_list_option = self
+                .reward_component_serde_option
+                .as_ref()
+                .map(|_| Vec::with_capacity(n_agents));
             terminated_list_option = Some(Vec::with_capacity(n_agents));
             truncated_list_option = Some(Vec::with_capacity(n_agents));
+            should_bootstrap_list_option =
+                self.bootstrap_flags.then(|| Vec::with_capacity(n_agents));
+            done_reason_list_option = self.done_reasons.then(|| Vec::with_capacity(n_agents));
         } else {
             reward_list_option = None;
+            reward_components_list_option = None;
             terminated_list_option = None;
             truncated_list_option = None;
+            should_bootstrap_list_option = None;
+            done_reason_list_option = None;
         }
 
         // Populate lists
-        for _ in 0..n_agents {
+        for agent_idx in 0..n_agents {
+            if let Some(region_alignment) = self.region_alignment_option {
+                offset = aligned_offset(shm_slice, offset, region_alignment);
+            }
             if self.recalculate_agent_id_every_step || new_episode {
                 let agent_id;
                 (agent_id, offset) = self.agent_id_serde.retrieve(py, shm_slice, offset)?;
                 agent_id_list.push(agent_id.unbind());
+                let (agent_index_option, is_new_agent_option);
+                (agent_index_option, is_new_agent_option, offset) = retrieve_agent_id_fields(
+                    shm_slice,
+                    offset,
+                    self.stable_agent_indices,
+                    self.new_agent_flags,
+                )?;
+                agent_index_list.extend(agent_index_option);
+                new_agent_flag_list.extend(is_new_agent_option);
+            }
+            // Arrow IPC obs are read after the per-agent fields instead, and chunked obs after the
+            // message
+            if !send_obs {
+                obs_list.push(PyNone::get(py).to_owned().into_any());
+            } else if self.obs_format == ObsFormat::SERDE && !self.chunked_obs {
+                // An obs identical to the previous obs in the same position is only sent as a flag
+                let mut same_as_prev = false;
+                if (self.dedup_set_state_obs && is_set_state_action) || self.reuse_identical_obs {
+                    (same_as_prev, offset) = retrieve_bool(shm_slice, offset)?;
+                }
+                // A CUDA tensor obs is rebuilt from its IPC handle
+                let mut is_cuda_ipc = false;
+                if self.cuda_ipc_obs && !same_as_prev {
+                    (is_cuda_ipc, offset) = retrieve_bool(shm_slice, offset)?;
+                }
+                let obs;
+                (obs, offset) = match &self.obs_file_dir_option {
+                    _ if same_as_prev => (
+                        self.pid_idx_current_obs_list[pid_idx][agent_idx]
+                            .bind(py)
+                            .clone(),
+                        offset,
+                    ),
+                    _ if is_cuda_ipc => retrieve_cuda_ipc_obs(py, shm_slice, offset)?,
+                    Some(obs_file_dir) => self
+                        .proc_id_obs_file_reader_map
+                        .entry(proc_id.clone())
+                        .or_insert_with(|| ObsFileReader::new(obs_file_dir.clone()))
+                        .retrieve(
+                            py,
+                            proc_id,
+                            select_obs_serde(
+                                &mut self.agent_obs_serdes_option,
+                                &mut self.obs_serde,
+                                agent_id_list[agent_idx].bind(py),
+                            )?,
+                            shm_slice,
+                            offset,
+                        )?,
+                    None => select_obs_serde(
+                        &mut self.agent_obs_serdes_option,
+                        &mut self.obs_serde,
+                        agent_id_list[agent_idx].bind(py),
+                    )?
+                    .retrieve(py, shm_slice, offset)?,
+                };
+                obs_list.push(obs);
+            }
+            if let Some(critic_obs_serde) =
+                self.critic_obs_serde_option.as_mut().filter(|_| send_obs)
+            {
+                let critic_obs;
+                (critic_obs, offset) = critic_obs_serde.retrieve(py, shm_slice, offset)?;
+                critic_obs_list.push(critic_obs.unbind());
+            }
+            if let Some(legal_action_mask_serde) = self
+                .legal_action_mask_serde_option
+                .as_mut()
+                .filter(|_| send_obs)
+            {
+                let legal_action_mask;
+                (legal_action_mask, offset) =
+                    legal_action_mask_serde.retrieve(py, shm_slice, offset)?;
+                legal_action_mask_list.push(legal_action_mask.unbind());
             }
-            let obs;
-            (obs, offset) = self.obs_serde.retrieve(py, shm_slice, offset)?;
-            obs_list.push(obs);
             if is_step_action {
                 let reward;
                 (reward, offset) = self.reward_serde.retrieve(py, shm_slice, offset)?;
                 reward_list_option.as_mut().unwrap().push(reward);
-                let terminated;
-                (terminated, offset) = retrieve_bool(shm_slice, offset)?;
-                terminated_list_option.as_mut().unwrap().push(terminated);
-                let truncated;
-                (truncated, offset) = retrieve_bool(shm_slice, offset)?;
-                truncated_list_option.as_mut().unwrap().push(truncated);
+                if let Some(reward_component_serde) = &mut self.reward_component_serde_option {
+                    let reward_components;
+                    (reward_components, offset) =
+                        reward_component_serde.retrieve(py, shm_slice, offset)?;
+                    reward_components_list_option
+                        .as_mut()
+                        .unwrap()
+                        .push(reward_components.unbind());
+                }
+                if !self.packed_done_flags {
+                    let terminated;
+                    (terminated, offset) = retrieve_bool(shm_slice, offset)?;
+                    terminated_list_option.as_mut().unwrap().push(terminated);
+                    let truncated;
+                    (truncated, offset) = retrieve_bool(shm_slice, offset)?;
+                    truncated_list_option.as_mut().unwrap().push(truncated);
+                }
+                if let Some(should_bootstrap_list) = &mut should_bootstrap_list_option {
+                    let should_bootstrap;
+                    (should_bootstrap, offset) = retrieve_bool(shm_slice, offset)?;
+                    should_bootstrap_list.push(should_bootstrap);
+                }
+                if let Some(done_reason_list) = &mut done_reason_list_option {
+                    let done_reason;
+                    (done_reason, offset) = retrieve_u8(shm_slice, offset)?;
+                    done_reason_list.push(done_reason);
+                }
+                if self.prev_actions {
+                    let has_prev_action;
+                    (has_prev_action, offset) = retrieve_bool(shm_slice, offset)?;
+                    let prev_action_option = if has_prev_action {
+                        let prev_action;
+                        (prev_action, offset) =
+                            self.action_serde.retrieve(py, shm_slice, offset)?;
+                        Some(prev_action.unbind())
+                    } else {
+                        None
+                    };
+                    prev_action_list.push(prev_action_option);
+                }
             }
         }
+        if is_step_action && self.packed_done_flags {
+            let (terminated_list, truncated_list);
+            (terminated_list, offset) = retrieve_bitset(shm_slice, offset, n_agents)?;
+            (truncated_list, offset) = retrieve_bitset(shm_slice, offset, n_agents)?;
+            terminated_list_option = Some(terminated_list);
+            truncated_list_option = Some(truncated_list);
+        }
+        let n_env_steps_option;
+        if is_step_action && self.env_step_counts {
+            let n_env_steps;
+            (n_env_steps, offset) = retrieve_usize_le(shm_slice, offset)?;
+            n_env_steps_option = Some(n_env_steps);
+        } else {
+            n_env_steps_option = None;
+        }
+        if self.include_counters {
+            let (total_env_steps, n_episodes);
+            (total_env_steps, offset) = retrieve_usize_le(shm_slice, offset)?;
+            (n_episodes, offset) = retrieve_usize_le(shm_slice, offset)?;
+            self.proc_id_counters_map
+                .insert(proc_id.clone(), (total_env_steps, n_episodes));
+        }
+        if send_obs && self.obs_format == ObsFormat::ARROW_IPC {
+            let obs_bytes;
+            (obs_bytes, offset) = retrieve_bytes_le(shm_slice, offset)?;
+            obs_list = read_arrow_obs_list(py, obs_bytes)?;
+        }
+        if send_obs && self.obs_format == ObsFormat::FIXED_LAYOUT {
+            (obs_list, offset) = read_fixed_layout_obs_list(
+                py,
+                shm_slice,
+                offset,
+                n_agents,
+                self.fixed_layout_obs_shape_option.as_ref().ok_or_else(|| {
+                    InvalidStateError::new_err(
+                        "Env process interface was given the fixed layout obs format, but no fixed layout obs shape",
+                    )
+                })?,
+            )?;
+        }
+        let global_obs_option;
+        if send_obs && self.global_obs {
+            let global_obs;
+            (global_obs, offset) = self
+                .global_obs_serde_option
+                .as_mut()
+                .unwrap_or(&mut self.obs_serde)
+                .retrieve(py, shm_slice, offset)?;
+            global_obs_option = Some(global_obs.unbind());
+        } else {
+            global_obs_option = None;
+        }
+        let state_hash_option;
+        if self.state_hashes {
+            let state_hash;
+            (state_hash, offset) = retrieve_u64_le(shm_slice, offset)?;
+            state_hash_option = Some(state_hash);
+        } else {
+            state_hash_option = None;
+        }
 
+        let send_shared_info = env_action.send_shared_info();
         let shared_info_option;
-        if let Some(shared_info_serde) = &mut self.shared_info_serde_option {
+        if let Some(shared_info_serde) = self
+            .shared_info_serde_option
+            .as_mut()
+            .filter(|_| send_shared_info)
+        {
             let shared_info;
-            (shared_info, offset) = shared_info_serde.retrieve(py, shm_slice, offset)?;
+            if let Some(shared_info_compression) = &self.shared_info_compression_option {
+                let shared_info_bytes;
+                (shared_info_bytes, offset) = retrieve_bytes_le(shm_slice, offset)?;
+                let decompressed = shared_info_compression.decompress(py, shared_info_bytes)?;
+                (shared_info, _) = shared_info_serde.retrieve(py, &decompressed[..], 0)?;
+            } else {
+                (shared_info, offset) = shared_info_serde.retrieve(py, shm_slice, offset)?;
+            }
             shared_info_option = Some(shared_info);
         } else {
             shared_info_option = None;
@@ -277,12 +772,40 @@ impl EnvProcessInterface {
                 ))
             })?;
             let state;
-            (state, _) = state_serde.retrieve(py, shm_slice, offset)?;
+            (state, offset) = state_serde.retrieve(py, shm_slice, offset)?;
+            if let Some(rng_state_serde) = self.rng_state_serde_option.as_mut() {
+                let has_rng_state;
+                (has_rng_state, offset) = retrieve_bool(shm_slice, offset)?;
+                if has_rng_state {
+                    let rng_state;
+                    (rng_state, _) = rng_state_serde.retrieve(py, shm_slice, offset)?;
+                    self.proc_id_rng_state_map
+                        .insert(proc_id.clone(), rng_state.unbind());
+                } else {
+                    self.proc_id_rng_state_map.remove(proc_id);
+                }
+            }
             state_option = Some(state);
         } else {
             state_option = None;
         }
 
+        if send_obs && self.chunked_obs {
+            let (parent_end, shmem, _, _) = &self.proc_packages[pid_idx];
+            let obs_bytes = receive_obs_chunks(parent_end.bind(py), shmem, proc_id)?;
+            let mut obs_offset = 0;
+            for agent_id in agent_id_list.iter() {
+                let obs;
+                (obs, obs_offset) = select_obs_serde(
+                    &mut self.agent_obs_serdes_option,
+                    &mut self.obs_serde,
+                    agent_id.bind(py),
+                )?
+                .retrieve(py, &obs_bytes[..], obs_offset)?;
+                obs_list.push(obs);
+            }
+        }
+
         // Populate timestep_list
         let prev_timestep_id_option_list_option =
             &mut self.pid_idx_prev_timestep_id_option_list_option[pid_idx];
@@ -294,6 +817,13 @@ impl EnvProcessInterface {
         if is_step_action {
             let mut timestep_id_list = Vec::with_capacity(n_agents);
             timestep_list = Vec::with_capacity(n_agents);
+            let mut reward_components_iter_option =
+                reward_components_list_option.map(|list| list.into_iter());
+            let mut should_bootstrap_iter_option =
+                should_bootstrap_list_option.map(|list| list.into_iter());
+            let mut done_reason_iter_option = done_reason_list_option.map(|list| list.into_iter());
+            let mut critic_obs_iter = self.pid_idx_current_critic_obs_list[pid_idx].iter();
+            let mut next_critic_obs_iter = critic_obs_list.iter();
             for (
                 previous_timestep_id,
                 agent_id,
@@ -329,6 +859,30 @@ impl EnvProcessInterface {
                     reward: reward.unbind(),
                     terminated,
                     truncated,
+                    reward_components: reward_components_iter_option
+                        .as_mut()
+                        .and_then(|iter| iter.next()),
+                    critic_obs: critic_obs_iter
+                        .next()
+                        .map(|critic_obs| critic_obs.clone_ref(py)),
+                    next_critic_obs: next_critic_obs_iter
+                        .next()
+                        .map(|next_critic_obs| next_critic_obs.clone_ref(py)),
+                    global_obs: self.pid_idx_current_global_obs_option[pid_idx]
+                        .as_ref()
+                        .map(|global_obs| global_obs.clone_ref(py)),
+                    next_global_obs: global_obs_option
+                        .as_ref()
+                        .map(|next_global_obs| next_global_obs.clone_ref(py)),
+                    state_hash: self.pid_idx_current_state_hash_option[pid_idx],
+                    next_state_hash: state_hash_option,
+                    n_env_steps: n_env_steps_option,
+                    done_reason: done_reason_iter_option
+                        .as_mut()
+                        .and_then(|iter| iter.next()),
+                    should_bootstrap: should_bootstrap_iter_option
+                        .as_mut()
+                        .and_then(|iter| iter.next()),
                 });
             }
             timestep_id_list_option = Some(timestep_id_list);
@@ -388,6 +942,30 @@ impl EnvProcessInterface {
             prev_timestep_id_list.append(&mut vec![None; n_agents]);
         }
         self.pid_idx_current_agent_id_list_option[pid_idx] = Some(agent_id_list.clone());
+        self.pid_idx_current_critic_obs_list[pid_idx] = critic_obs_list;
+        if self.legal_action_mask_serde_option.is_some() && send_obs {
+            self.proc_id_legal_action_mask_list_map
+                .insert(proc_id.clone(), legal_action_mask_list);
+        } else {
+            self.proc_id_legal_action_mask_list_map.remove(proc_id);
+        }
+        if self.prev_actions && is_step_action {
+            self.proc_id_prev_action_list_map
+                .insert(proc_id.clone(), prev_action_list);
+        } else {
+            self.proc_id_prev_action_list_map.remove(proc_id);
+        }
+        if self.new_agent_flags {
+            self.proc_id_new_agent_flag_list_map
+                .insert(proc_id.clone(), new_agent_flag_list);
+        }
+        // The agent indices are only sent with the agent ids, and are otherwise unchanged
+        if self.stable_agent_indices && (self.recalculate_agent_id_every_step || new_episode) {
+            self.proc_id_agent_index_list_map
+                .insert(proc_id.clone(), agent_index_list);
+        }
+        self.pid_idx_current_global_obs_option[pid_idx] = global_obs_option;
+        self.pid_idx_current_state_hash_option[pid_idx] = state_hash_option;
         self.pid_idx_current_obs_list[pid_idx] = obs_list
             .clone()
             .into_iter()
@@ -435,49 +1013,203 @@ impl EnvProcessInterface {
         recalculate_agent_id_every_step,
         flinks_folder,
         min_process_steps_per_inference,
+        state_pool_slots=0,
+        state_pool_slot_bytes=0,
+        agent_options=None,
+        episode_options=None,
+        obs_options=None,
+        buffer_options=None,
+        serde_options=None,
         ))]
     pub fn new<'py>(
         py: Python<'py>,
-        agent_id_serde: Box<dyn PyAnySerde>,
-        action_serde: Box<dyn PyAnySerde>,
-        obs_serde: Box<dyn PyAnySerde>,
-        reward_serde: Box<dyn PyAnySerde>,
-        obs_space_serde: Box<dyn PyAnySerde>,
-        action_space_serde: Box<dyn PyAnySerde>,
-        shared_info_serde_option: DynPyAnySerdeOption,
-        shared_info_setter_serde_option: DynPyAnySerdeOption,
-        state_serde_option: DynPyAnySerdeOption,
+        agent_id_serde: VersionedSerde,
+        action_serde: VersionedSerde,
+        obs_serde: VersionedSerde,
+        reward_serde: VersionedSerde,
+        obs_space_serde: VersionedSerde,
+        action_space_serde: VersionedSerde,
+        shared_info_serde_option: VersionedSerdeOption,
+        shared_info_setter_serde_option: VersionedSerdeOption,
+        state_serde_option: VersionedSerdeOption,
         recalculate_agent_id_every_step: bool,
         flinks_folder: String,
         min_process_steps_per_inference: usize,
+        state_pool_slots: usize,
+        state_pool_slot_bytes: usize,
+        agent_options: Option<AgentOptions>,
+        episode_options: Option<EpisodeOptions>,
+        obs_options: Option<ObsOptions>,
+        buffer_options: Option<BufferOptions>,
+        serde_options: Option<SerdeOptions>,
     ) -> PyResult<Self> {
+        // The options are the same objects given to the env processes, so that the parent reads
+        // exactly the fields the processes write. Options which only affect the processes are
+        // ignored here.
+        let stable_agent_indices = agent_options.unwrap_or_default().stable_agent_indices;
+        let done_reasons = episode_options
+            .unwrap_or_default()
+            .done_reason_source_option
+            .is_some();
+        let ObsOptions {
+            obs_format,
+            cuda_ipc_obs,
+            fixed_layout_obs_shape_option,
+            obs_file_dir_option,
+            obs_chunk_size_option,
+            reuse_identical_obs,
+            dedup_set_state_obs,
+            global_obs_fn_option,
+            ..
+        } = obs_options.unwrap_or_default();
+        let chunked_obs = obs_chunk_size_option.is_some();
+        let global_obs = global_obs_fn_option.is_some();
+        let BufferOptions {
+            episode_boundary_reasons,
+            bootstrap_flags,
+            state_hashes,
+            env_step_counts,
+            prev_actions,
+            packed_done_flags,
+            include_counters,
+            new_agent_flags,
+            region_alignment_option,
+            mqueue_max_message_bytes_option,
+            partition_bytes_option,
+            n_partitions,
+            ..
+        } = buffer_options.unwrap_or_default();
+        let mqueue_transport = mqueue_max_message_bytes_option.is_some();
+        let SerdeOptions {
+            reward_component_serde_option,
+            critic_obs_serde_option,
+            global_obs_serde_option,
+            legal_action_mask_serde_option,
+            agent_obs_serdes_option,
+            rng_state_serde_option,
+            shared_info_compression_option,
+            ..
+        } = serde_options.unwrap_or_default();
+        let reward_component_serde_option =
+            extract_serde_option(py, reward_component_serde_option)?;
+        let critic_obs_serde_option = extract_serde_option(py, critic_obs_serde_option)?;
+        let global_obs_serde_option = extract_serde_option(py, global_obs_serde_option)?;
+        let legal_action_mask_serde_option =
+            extract_serde_option(py, legal_action_mask_serde_option)?;
+        let rng_state_serde_option = extract_serde_option(py, rng_state_serde_option)?
+            .map(|rng_state_serde| rng_state_serde.serde);
         let selector = PyModule::import(py, "selectors")?
             .getattr("DefaultSelector")?
             .call0()?
             .unbind();
+        let state_pool_option = if state_pool_slots > 0 {
+            Some(StatePool::create(
+                &flinks_folder,
+                state_pool_slots,
+                state_pool_slot_bytes,
+            )?)
+        } else {
+            None
+        };
+        // The partitioned buffer must exist before the env processes start, as they open their
+        // partition of it instead of creating a buffer of their own
+        let partitioned_buffer_option = partition_bytes_option
+            .map(|partition_bytes| {
+                PartitionedBuffer::create(&flinks_folder, n_partitions, partition_bytes)
+            })
+            .transpose()?;
+        let serde_version_list = SerdeVersionList::new(
+            [
+                &agent_id_serde,
+                &action_serde,
+                &obs_serde,
+                &reward_serde,
+                &obs_space_serde,
+                &action_space_serde,
+            ],
+            [
+                shared_info_serde_option.0.as_ref(),
+                shared_info_setter_serde_option.0.as_ref(),
+                state_serde_option.0.as_ref(),
+                reward_component_serde_option.as_ref(),
+                critic_obs_serde_option.as_ref(),
+                global_obs.then(|| global_obs_serde_option.as_ref().unwrap_or(&obs_serde)),
+                legal_action_mask_serde_option.as_ref(),
+            ],
+        );
         Ok(EnvProcessInterface {
-            agent_id_serde,
-            action_serde,
-            obs_serde,
-            reward_serde,
-            obs_space_serde,
-            action_space_serde,
-            shared_info_serde_option: shared_info_serde_option.into(),
-            shared_info_setter_serde_option: shared_info_setter_serde_option.into(),
-            state_serde_option: state_serde_option.into(),
+            agent_id_serde: agent_id_serde.serde,
+            action_serde: action_serde.serde,
+            obs_serde: obs_serde.serde,
+            reward_serde: reward_serde.serde,
+            obs_space_serde: obs_space_serde.serde,
+            action_space_serde: action_space_serde.serde,
+            shared_info_serde_option: shared_info_serde_option.0.map(|serde| serde.serde),
+            shared_info_setter_serde_option: shared_info_setter_serde_option
+                .0
+                .map(|serde| serde.serde),
+            state_serde_option: state_serde_option.0.map(|serde| serde.serde),
+            reward_component_serde_option: reward_component_serde_option.map(|serde| serde.serde),
+            critic_obs_serde_option: critic_obs_serde_option.map(|serde| serde.serde),
+            episode_boundary_reasons,
+            done_reasons,
+            bootstrap_flags,
+            dedup_set_state_obs,
+            reuse_identical_obs,
+            obs_format,
+            fixed_layout_obs_shape_option,
+            state_hashes,
+            env_step_counts,
+            chunked_obs,
+            prev_actions,
+            packed_done_flags,
+            region_alignment_option,
+            agent_obs_serdes_option: agent_obs_serdes_option
+                .map(|agent_obs_serdes| AgentObsSerdes::new(agent_obs_serdes.bind(py)))
+                .transpose()?,
+            include_counters,
+            stable_agent_indices,
+            new_agent_flags,
+            mqueue_transport,
+            rng_state_serde_option,
+            cuda_ipc_obs,
+            global_obs,
+            global_obs_serde_option: global_obs_serde_option.map(|serde| serde.serde),
+            legal_action_mask_serde_option: legal_action_mask_serde_option.map(|serde| serde.serde),
+            serde_version_list,
             recalculate_agent_id_every_step,
             flinks_folder,
             proc_packages: Vec::new(),
             min_process_steps_per_inference,
+            shared_info_compression_option,
             selector,
             proc_id_pid_idx_map: HashMap::new(),
+            proc_id_episode_boundary_reason_map: HashMap::new(),
+            proc_id_legal_action_mask_list_map: HashMap::new(),
+            proc_id_prev_action_list_map: HashMap::new(),
+            proc_id_counters_map: HashMap::new(),
+            proc_id_new_agent_flag_list_map: HashMap::new(),
+            proc_id_agent_index_list_map: HashMap::new(),
+            proc_id_mqueue_map: HashMap::new(),
+            keepalive_reset_proc_ids: HashSet::new(),
+            resizable_buffer_proc_ids: HashSet::new(),
+            proc_id_rng_state_map: HashMap::new(),
             pid_idx_current_env_action: Vec::new(),
             pid_idx_current_agent_id_list_option: Vec::new(),
             pid_idx_prev_timestep_id_option_list_option: Vec::new(),
             pid_idx_current_obs_list: Vec::new(),
+            pid_idx_current_critic_obs_list: Vec::new(),
+            pid_idx_current_global_obs_option: Vec::new(),
+            pid_idx_current_state_hash_option: Vec::new(),
             pid_idx_current_action_list: Vec::new(),
             pid_idx_current_aald_option: Vec::new(),
             just_initialized_pid_idx_list: Vec::new(),
+            state_pool_option,
+            state_pool_state_list: vec![None; state_pool_slots],
+            partitioned_buffer_option,
+            obs_file_dir_option,
+            proc_id_obs_file_reader_map: HashMap::new(),
+            action_space_descriptor_option: None,
         })
     }
 
@@ -502,6 +1234,9 @@ impl EnvProcessInterface {
         self.pid_idx_current_agent_id_list_option = vec![None; n_procs];
         self.pid_idx_prev_timestep_id_option_list_option = vec![None; n_procs];
         self.pid_idx_current_obs_list = vec![Vec::new(); n_procs];
+        self.pid_idx_current_critic_obs_list = vec![Vec::new(); n_procs];
+        self.pid_idx_current_global_obs_option = vec![None; n_procs];
+        self.pid_idx_current_state_hash_option = vec![None; n_procs];
         self.pid_idx_current_action_list = vec![Vec::new(); n_procs];
         self.pid_idx_current_aald_option = vec![None; n_procs];
 
@@ -515,6 +1250,7 @@ impl EnvProcessInterface {
                 EnvAction::RESET {
                     shared_info_setter_option: None,
                     send_state: false,
+                    send_shared_info: true,
                 },
             );
         }
@@ -542,6 +1278,9 @@ impl EnvProcessInterface {
         self.pid_idx_current_agent_id_list_option.push(None);
         self.pid_idx_prev_timestep_id_option_list_option.push(None);
         self.pid_idx_current_obs_list.push(Vec::new());
+        self.pid_idx_current_critic_obs_list.push(Vec::new());
+        self.pid_idx_current_global_obs_option.push(None);
+        self.pid_idx_current_state_hash_option.push(None);
         self.pid_idx_current_action_list.push(Vec::new());
         self.pid_idx_current_aald_option.push(None);
 
@@ -552,6 +1291,7 @@ impl EnvProcessInterface {
             EnvAction::RESET {
                 shared_info_setter_option: None,
                 send_state: false,
+                send_shared_info: true,
             },
         );
 
@@ -563,6 +1303,17 @@ impl EnvProcessInterface {
         let (parent_end, mut shmem, _, proc_id) = self.proc_packages.pop().unwrap();
         let pid_idx = self.proc_packages.len();
         self.proc_id_pid_idx_map.remove(&proc_id);
+        self.proc_id_episode_boundary_reason_map.remove(&proc_id);
+        self.proc_id_legal_action_mask_list_map.remove(&proc_id);
+        self.proc_id_prev_action_list_map.remove(&proc_id);
+        self.proc_id_counters_map.remove(&proc_id);
+        self.proc_id_new_agent_flag_list_map.remove(&proc_id);
+        self.proc_id_agent_index_list_map.remove(&proc_id);
+        self.proc_id_mqueue_map.remove(&proc_id);
+        self.keepalive_reset_proc_ids.remove(&proc_id);
+        self.resizable_buffer_proc_ids.remove(&proc_id);
+        self.proc_id_rng_state_map.remove(&proc_id);
+        self.proc_id_obs_file_reader_map.remove(&proc_id);
         let (ep_evt, used_bytes) = unsafe {
             Event::from_existing(shmem.as_ptr()).map_err(|err| {
                 InvalidStateError::new_err(format!("Failed to get event: {}", err.to_string()))
@@ -576,6 +1327,9 @@ impl EnvProcessInterface {
         self.pid_idx_current_agent_id_list_option.pop();
         self.pid_idx_prev_timestep_id_option_list_option.pop();
         self.pid_idx_current_obs_list.pop();
+        self.pid_idx_current_critic_obs_list.pop();
+        self.pid_idx_current_global_obs_option.pop();
+        self.pid_idx_current_state_hash_option.pop();
         self.pid_idx_current_env_action.pop();
         self.pid_idx_current_action_list.pop();
         self.pid_idx_current_aald_option.pop();
@@ -605,7 +1359,10 @@ impl EnvProcessInterface {
         self.min_process_steps_per_inference
     }
 
-    pub fn cleanup(&mut self) -> PyResult<()> {
+    // If a stop ack timeout is given, waits up to that long for each process to acknowledge that it
+    // has stopped cleanly
+    #[pyo3(signature = (stop_ack_timeout_option=None))]
+    pub fn cleanup(&mut self, stop_ack_timeout_option: Option<Duration>) -> PyResult<()> {
         while let Some(proc_package) = self.proc_packages.pop() {
             let (parent_end, mut shmem, _, _) = proc_package;
             let (ep_evt, used_bytes) = unsafe {
@@ -620,21 +1377,488 @@ impl EnvProcessInterface {
                 .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
             Python::with_gil(|py| {
                 self.selector
-                    .call_method1(py, intern!(py, "unregister"), (parent_end,))
+                    .call_method1(py, intern!(py, "unregister"), (&parent_end,))?;
+                if let Some(stop_ack_timeout) = stop_ack_timeout_option {
+                    let parent_end = parent_end.bind(py);
+                    parent_end.call_method1(
+                        intern!(py, "settimeout"),
+                        (stop_ack_timeout.as_secs_f64(),),
+                    )?;
+                    match recvfrom_byte(parent_end) {
+                        Err(err) if err.is_instance_of::<PyTimeoutError>(py) => {
+                            println!(
+                                "Env process did not acknowledge stop within {:?}",
+                                stop_ack_timeout
+                            );
+                        }
+                        result => {
+                            result?;
+                        }
+                    }
+                }
+                Ok::<(), PyErr>(())
             })?;
             // This sleep seems to be needed for the shared memory to get set/read correctly
             thread::sleep(Duration::from_millis(1));
         }
         self.proc_id_pid_idx_map.clear();
+        self.proc_id_episode_boundary_reason_map.clear();
+        self.proc_id_legal_action_mask_list_map.clear();
+        self.proc_id_prev_action_list_map.clear();
+        self.proc_id_counters_map.clear();
+        self.proc_id_new_agent_flag_list_map.clear();
+        self.proc_id_agent_index_list_map.clear();
+        self.proc_id_mqueue_map.clear();
+        self.keepalive_reset_proc_ids.clear();
+        self.resizable_buffer_proc_ids.clear();
+        self.proc_id_rng_state_map.clear();
         self.pid_idx_current_agent_id_list_option.clear();
         self.pid_idx_prev_timestep_id_option_list_option.clear();
         self.pid_idx_current_obs_list.clear();
+        self.pid_idx_current_critic_obs_list.clear();
+        self.pid_idx_current_global_obs_option.clear();
+        self.pid_idx_current_state_hash_option.clear();
         self.pid_idx_current_action_list.clear();
         self.pid_idx_current_aald_option.clear();
         self.just_initialized_pid_idx_list.clear();
+        // Removes the flink of the partitioned buffer now that no process uses it
+        self.partitioned_buffer_option = None;
         Ok(())
     }
 
+    // Returns the reason for the most recent reset-style message from the given process, if
+    // episode_boundary_reasons is enabled and such a message has been received
+    pub fn episode_boundary_reason(&self, proc_id: String) -> Option<EpisodeBoundaryReason> {
+        self.proc_id_episode_boundary_reason_map
+            .get(&proc_id)
+            .cloned()
+    }
+
+    // Returns the legal action masks for the most recent obs from the given process, in the order of
+    // its agent id list, if a legal action mask serde was provided and such obs have been received
+    pub fn legal_action_masks(&self, py: Python<'_>, proc_id: String) -> Option<Vec<PyObject>> {
+        self.proc_id_legal_action_mask_list_map
+            .get(&proc_id)
+            .map(|legal_action_mask_list| {
+                legal_action_mask_list
+                    .iter()
+                    .map(|legal_action_mask| legal_action_mask.clone_ref(py))
+                    .collect()
+            })
+    }
+
+    // Returns the action each agent of the given process was last stepped with, in the order of its
+    // agent id list, if prev_actions is set and the most recent message from the process was a step.
+    // Agents which weren't stepped with an action have None.
+    pub fn prev_actions(&self, py: Python<'_>, proc_id: String) -> Option<Vec<Option<PyObject>>> {
+        self.proc_id_prev_action_list_map
+            .get(&proc_id)
+            .map(|prev_action_list| {
+                prev_action_list
+                    .iter()
+                    .map(|prev_action_option| {
+                        prev_action_option
+                            .as_ref()
+                            .map(|prev_action| prev_action.clone_ref(py))
+                    })
+                    .collect()
+            })
+    }
+
+    // Returns whether each agent of the given process was new in its most recent env action
+    // response, in the order of its agent id list, if new_agent_flags is set and such a response has
+    // been received. Every agent is new after a reset or set state.
+    pub fn new_agent_flags(&self, proc_id: String) -> Option<Vec<bool>> {
+        self.proc_id_new_agent_flag_list_map.get(&proc_id).cloned()
+    }
+
+    // Returns the stable index of each agent of the given process, in the order of its agent id list,
+    // if stable_agent_indices is set and an env action response has been received. Indices are
+    // assigned in order of first appearance and are kept for the lifetime of the process.
+    pub fn agent_indices(&self, proc_id: String) -> Option<Vec<usize>> {
+        self.proc_id_agent_index_list_map.get(&proc_id).cloned()
+    }
+
+    // Returns the base offset the env process at the given index must be given as base_offset_option,
+    // if partition_bytes_option was provided. The process at each index uses the partition at the
+    // same index.
+    pub fn partition_base_offset(&self, pid_idx: usize) -> PyResult<Option<usize>> {
+        self.partitioned_buffer_option
+            .as_ref()
+            .map(|partitioned_buffer| partitioned_buffer.base_offset(pid_idx))
+            .transpose()
+    }
+
+    // Returns the rng state written with the most recent state of the given process, if an rng
+    // state serde was provided and the env exposes its rng state with get_rng_state
+    pub fn rng_state(&self, py: Python<'_>, proc_id: String) -> Option<PyObject> {
+        self.proc_id_rng_state_map
+            .get(&proc_id)
+            .map(|rng_state| rng_state.clone_ref(py))
+    }
+
+    // Returns the (total env steps, episodes) of the given process as of its most recent env action
+    // response, if include_counters is set and such a response has been received
+    pub fn counters(&self, proc_id: String) -> Option<(usize, usize)> {
+        self.proc_id_counters_map.get(&proc_id).copied()
+    }
+
+    // Sets the env of the given process to each of the given states in turn, and returns the agent id
+    // list and obs list resulting from each state, all in a single exchange with the process. The env
+    // is left in the last state, so the next env action sent to the process should be a RESET or
+    // SET_STATE.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn set_state_batch<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+        states: Vec<Bound<'py, PyAny>>,
+    ) -> PyResult<Vec<AgentIdObsLists<'py>>> {
+        let state_serde = self.state_serde_option.as_mut().ok_or_else(|| {
+            InvalidStateError::new_err(
+                "Tried to set a batch of states, but no state serde was provided to use for serialization",
+            )
+        })?;
+        let mut payload = Vec::new();
+        append_usize_le_vec(&mut payload, states.len());
+        for state in states.iter() {
+            state_serde.append_vec(&mut payload, None, state)?;
+        }
+        let pid_idx = self.send_header_and_wait(py, &proc_id, Header::SetStateBatch, &payload)?;
+        let (_, shmem, used_bytes, _) = self.proc_packages.get(pid_idx).unwrap();
+        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+        let mut offset = 0;
+        let mut result = Vec::with_capacity(states.len());
+        for _ in 0..states.len() {
+            let n_agents;
+            (n_agents, offset) = retrieve_usize_le(shm_slice, offset)?;
+            let mut agent_id_list = Vec::with_capacity(n_agents);
+            let mut obs_list = Vec::with_capacity(n_agents);
+            for _ in 0..n_agents {
+                let (agent_id, obs);
+                (agent_id, offset) = self.agent_id_serde.retrieve(py, shm_slice, offset)?;
+                let mut is_cuda_ipc = false;
+                if self.cuda_ipc_obs {
+                    (is_cuda_ipc, offset) = retrieve_bool(shm_slice, offset)?;
+                }
+                (obs, offset) = match &self.obs_file_dir_option {
+                    _ if is_cuda_ipc => retrieve_cuda_ipc_obs(py, shm_slice, offset)?,
+                    Some(obs_file_dir) => self
+                        .proc_id_obs_file_reader_map
+                        .entry(proc_id.clone())
+                        .or_insert_with(|| ObsFileReader::new(obs_file_dir.clone()))
+                        .retrieve(
+                            py,
+                            &proc_id,
+                            select_obs_serde(
+                                &mut self.agent_obs_serdes_option,
+                                &mut self.obs_serde,
+                                &agent_id,
+                            )?,
+                            shm_slice,
+                            offset,
+                        )?,
+                    None => select_obs_serde(
+                        &mut self.agent_obs_serdes_option,
+                        &mut self.obs_serde,
+                        &agent_id,
+                    )?
+                    .retrieve(py, shm_slice, offset)?,
+                };
+                agent_id_list.push(agent_id);
+                obs_list.push(obs);
+            }
+            result.push((agent_id_list, obs_list));
+        }
+        Ok(result)
+    }
+
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn set_seed<'py>(&mut self, py: Python<'py>, proc_id: String, seed: usize) -> PyResult<()> {
+        let mut payload = Vec::with_capacity(size_of::<usize>());
+        append_usize_le_vec(&mut payload, seed);
+        self.send_header_and_wait(py, &proc_id, Header::SetSeed, &payload)?;
+        Ok(())
+    }
+
+    // Steps the env of the given process warm_start_steps times with its default action, discarding
+    // the results. The env is left mid-episode, so a RESET or SET_STATE env action must be sent
+    // before stepping again.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn warm_start<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+        warm_start_steps: usize,
+    ) -> PyResult<()> {
+        let mut payload = Vec::with_capacity(size_of::<usize>());
+        append_usize_le_vec(&mut payload, warm_start_steps);
+        self.send_header_and_wait(py, &proc_id, Header::WarmStart, &payload)?;
+        Ok(())
+    }
+
+    // Writes the env's current state, serialized with the state serde, to the given path.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn dump_state<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+        path: String,
+    ) -> PyResult<()> {
+        let mut payload = Vec::with_capacity(size_of::<u64>() + path.len());
+        append_bytes_le_vec(&mut payload, path.as_bytes());
+        self.send_header_and_wait(py, &proc_id, Header::DumpState, &payload)?;
+        Ok(())
+    }
+
+    // Makes the process write a hex dump of its last env action response to its buffer dump dir.
+    // The response is no longer readable by collect_step_data afterwards.
+    pub fn dump_buffer<'py>(&mut self, py: Python<'py>, proc_id: String) -> PyResult<()> {
+        self.send_header_and_wait(py, &proc_id, Header::DumpBuffer, &[])?;
+        Ok(())
+    }
+
+    // Returns the descriptor of the action space received from the first process during
+    // init_processes, or None if the processes have not been initialized yet
+    pub fn action_space_descriptor(&self) -> Option<ActionSpaceDescriptor> {
+        self.action_space_descriptor_option.clone()
+    }
+
+    // Replaces the obs, action and reward serdes of this interface and of all the env processes.
+    // Serdes which are not provided are kept. Processes added afterwards must be started with the
+    // new serdes, which the startup handshake checks. The obs serde cannot be replaced while agent
+    // obs serdes are in use, since those are not carried by the reconfigure payload.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    #[pyo3(signature = (obs_serde_type_option=None, action_serde_type_option=None, reward_serde_type_option=None))]
+    pub fn reconfigure<'py>(
+        &mut self,
+        py: Python<'py>,
+        obs_serde_type_option: Option<PyAnySerdeType>,
+        action_serde_type_option: Option<PyAnySerdeType>,
+        reward_serde_type_option: Option<PyAnySerdeType>,
+    ) -> PyResult<()> {
+        if obs_serde_type_option.is_some() && self.agent_obs_serdes_option.is_some() {
+            return Err(InvalidStateError::new_err(
+                "Tried to reconfigure the obs serde, but agent obs serdes are in use and cannot be reconfigured",
+            ));
+        }
+        // Build the new serdes and their versions before sending anything so that an invalid serde
+        // type leaves every process unchanged
+        let obs_serde_option: Option<Box<dyn PyAnySerde>> = obs_serde_type_option
+            .as_ref()
+            .map(|serde_type| serde_type.try_into())
+            .transpose()?;
+        let action_serde_option: Option<Box<dyn PyAnySerde>> = action_serde_type_option
+            .as_ref()
+            .map(|serde_type| serde_type.try_into())
+            .transpose()?;
+        let reward_serde_option: Option<Box<dyn PyAnySerde>> = reward_serde_type_option
+            .as_ref()
+            .map(|serde_type| serde_type.try_into())
+            .transpose()?;
+        let version_option = |serde_type_option: &Option<PyAnySerdeType>| {
+            serde_type_option
+                .as_ref()
+                .map(|serde_type| serde_type_version(py, serde_type))
+                .transpose()
+        };
+        let obs_version_option = version_option(&obs_serde_type_option)?;
+        let action_version_option = version_option(&action_serde_type_option)?;
+        let reward_version_option = version_option(&reward_serde_type_option)?;
+        let mut payload = Vec::new();
+        append_serde_type_option_vec(&mut payload, obs_serde_type_option)?;
+        append_serde_type_option_vec(&mut payload, action_serde_type_option)?;
+        append_serde_type_option_vec(&mut payload, reward_serde_type_option)?;
+        let proc_id_list: Vec<String> = self.proc_id_pid_idx_map.keys().cloned().collect();
+        for proc_id in proc_id_list.iter() {
+            self.send_header_and_wait(py, proc_id, Header::Reconfigure, &payload)?;
+        }
+        if let Some(obs_serde) = obs_serde_option {
+            self.obs_serde = obs_serde;
+        }
+        if let Some(action_serde) = action_serde_option {
+            self.action_serde = action_serde;
+        }
+        if let Some(reward_serde) = reward_serde_option {
+            self.reward_serde = reward_serde;
+        }
+        if let Some(obs_version) = obs_version_option {
+            // Global obs without a serde of their own are serialized with the obs serde
+            if self.global_obs_serde_option.is_none() {
+                self.serde_version_list
+                    .set_version("global obs", obs_version.clone());
+            }
+            self.serde_version_list.set_version("obs", obs_version);
+        }
+        if let Some(action_version) = action_version_option {
+            self.serde_version_list
+                .set_version("action", action_version);
+        }
+        if let Some(reward_version) = reward_version_option {
+            self.serde_version_list
+                .set_version("reward", reward_version);
+        }
+        Ok(())
+    }
+
+    // Returns the (agent id, action, reward) of each agent for each of the last steps recorded by the
+    // process' action history, oldest first.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn action_history<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+    ) -> PyResult<Vec<Vec<ActionHistoryEntry<'py>>>> {
+        let pid_idx = self.send_header_and_wait(py, &proc_id, Header::GetActionHistory, &[])?;
+        let (_, shmem, used_bytes, _) = self.proc_packages.get(pid_idx).unwrap();
+        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+        let (n_steps, mut offset) = retrieve_usize_le(shm_slice, 0)?;
+        let mut action_history = Vec::with_capacity(n_steps);
+        for _ in 0..n_steps {
+            let n_agents;
+            (n_agents, offset) = retrieve_usize_le(shm_slice, offset)?;
+            let mut step_history = Vec::with_capacity(n_agents);
+            for _ in 0..n_agents {
+                let (agent_id, action, reward);
+                (agent_id, offset) = self.agent_id_serde.retrieve(py, shm_slice, offset)?;
+                (action, offset) = self.action_serde.retrieve(py, shm_slice, offset)?;
+                (reward, offset) = self.reward_serde.retrieve(py, shm_slice, offset)?;
+                step_history.push((agent_id, action, reward));
+            }
+            action_history.push(step_history);
+        }
+        Ok(action_history)
+    }
+
+    // Returns the env's config (or metadata) attribute as read by the process at startup, or None if
+    // the env has neither attribute.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn env_config<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+    ) -> PyResult<Option<Bound<'py, PyAny>>> {
+        if self.shared_info_serde_option.is_none() {
+            return Err(InvalidStateError::new_err(
+                "Tried to get an env config, but the env config is serialized with the shared info serde and no shared info serde was provided",
+            ));
+        }
+        let pid_idx = self.send_header_and_wait(py, &proc_id, Header::GetConfig, &[])?;
+        let (_, shmem, used_bytes, _) = self.proc_packages.get(pid_idx).unwrap();
+        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+        let (has_config, offset) = retrieve_bool(shm_slice, 0)?;
+        if !has_config {
+            return Ok(None);
+        }
+        let (config, _) = self
+            .shared_info_serde_option
+            .as_mut()
+            .unwrap()
+            .retrieve(py, shm_slice, offset)?;
+        Ok(Some(config))
+    }
+
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn sample_actions<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let pid_idx = self.send_header_and_wait(py, &proc_id, Header::SampleAction, &[])?;
+        let (_, shmem, used_bytes, _) = self.proc_packages.get(pid_idx).unwrap();
+        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+        let (n_actions, mut offset) = retrieve_usize_le(shm_slice, 0)?;
+        let mut action_list = Vec::with_capacity(n_actions);
+        for _ in 0..n_actions {
+            let action;
+            (action, offset) = self.action_serde.retrieve(py, shm_slice, offset)?;
+            action_list.push(action);
+        }
+        Ok(action_list)
+    }
+
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    pub fn observe<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+    ) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let pid_idx = self.send_header_and_wait(py, &proc_id, Header::Observe, &[])?;
+        let (_, shmem, used_bytes, _) = self.proc_packages.get(pid_idx).unwrap();
+        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+        let (n_agents, mut offset) = retrieve_usize_le(shm_slice, 0)?;
+        // The env process writes the obs of the agents of the current episode, in order
+        let agent_id_list = self.pid_idx_current_agent_id_list_option[pid_idx]
+            .clone()
+            .unwrap_or_default();
+        let mut obs_list = Vec::with_capacity(n_agents);
+        for agent_idx in 0..n_agents {
+            let agent_id = match agent_id_list.get(agent_idx) {
+                Some(agent_id) => agent_id.bind(py).clone(),
+                None => py.None().into_bound(py),
+            };
+            let mut is_cuda_ipc = false;
+            if self.cuda_ipc_obs {
+                (is_cuda_ipc, offset) = retrieve_bool(shm_slice, offset)?;
+            }
+            let obs;
+            (obs, offset) = match &self.obs_file_dir_option {
+                _ if is_cuda_ipc => retrieve_cuda_ipc_obs(py, shm_slice, offset)?,
+                Some(obs_file_dir) => self
+                    .proc_id_obs_file_reader_map
+                    .entry(proc_id.clone())
+                    .or_insert_with(|| ObsFileReader::new(obs_file_dir.clone()))
+                    .retrieve(
+                        py,
+                        &proc_id,
+                        select_obs_serde(
+                            &mut self.agent_obs_serdes_option,
+                            &mut self.obs_serde,
+                            &agent_id,
+                        )?,
+                        shm_slice,
+                        offset,
+                    )?,
+                None => select_obs_serde(
+                    &mut self.agent_obs_serdes_option,
+                    &mut self.obs_serde,
+                    &agent_id,
+                )?
+                .retrieve(py, shm_slice, offset)?,
+            };
+            obs_list.push(obs);
+        }
+        Ok(obs_list)
+    }
+
+    // Returns the number of agents currently in the env of the given process, and their agent ids
+    // if requested, without stepping it.
+    // It is expected that this method is called after collect_step_data and before send_env_actions
+    #[pyo3(signature = (proc_id, include_agent_ids=false))]
+    pub fn agent_count<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+        include_agent_ids: bool,
+    ) -> PyResult<(usize, Option<Vec<Bound<'py, PyAny>>>)> {
+        let mut payload = Vec::with_capacity(1);
+        append_bool_vec(&mut payload, include_agent_ids);
+        let pid_idx = self.send_header_and_wait(py, &proc_id, Header::AgentCount, &payload)?;
+        let (_, shmem, used_bytes, _) = self.proc_packages.get(pid_idx).unwrap();
+        let shm_slice = unsafe { &shmem.as_slice()[*used_bytes..] };
+        let (n_agents, mut offset) = retrieve_usize_le(shm_slice, 0)?;
+        if !include_agent_ids {
+            return Ok((n_agents, None));
+        }
+        let mut agent_id_list = Vec::with_capacity(n_agents);
+        for _ in 0..n_agents {
+            let agent_id;
+            (agent_id, offset) = self.agent_id_serde.retrieve(py, shm_slice, offset)?;
+            agent_id_list.push(agent_id);
+        }
+        Ok((n_agents, Some(agent_id_list)))
+    }
+
     pub fn collect_step_data<'py>(
         &mut self,
         py: Python<'py>,
@@ -663,8 +1887,12 @@ impl EnvProcessInterface {
                 }
                 let (parent_end, _, _, pid_idx) =
                     key.extract::<(PyObject, PyObject, PyObject, usize)>(py)?;
-                recvfrom_byte(parent_end.bind(py))?;
-                ready_pid_idxs.push(pid_idx);
+                recvfrom_ack(parent_end.bind(py), &self.proc_packages[pid_idx].3)?;
+                // The initial reset of a just initialized process is acknowledged here as well, but
+                // its response must only be collected once (chunked obs can only be received once)
+                if !ready_pid_idxs.contains(&pid_idx) {
+                    ready_pid_idxs.push(pid_idx);
+                }
                 n_process_steps_collected += 1;
             }
         }
@@ -684,6 +1912,157 @@ impl EnvProcessInterface {
         ))
     }
 
+    // Serializes the state with the state serde into the given slot of the state pool, from which
+    // env processes can then set their state using send_set_state_from_pool
+    pub fn set_pool_state<'py>(
+        &mut self,
+        pool_index: usize,
+        state: Bound<'py, PyAny>,
+    ) -> PyResult<()> {
+        let state_pool = self.state_pool_option.as_mut().ok_or_else(|| {
+            InvalidStateError::new_err(
+                "Tried to set a state pool state, but no state pool was created (state_pool_slots is 0)",
+            )
+        })?;
+        let state_serde = self.state_serde_option.as_mut().ok_or_else(|| {
+            InvalidStateError::new_err(
+                "Tried to set a state pool state, but no state serde was provided to use for serialization",
+            )
+        })?;
+        let mut state_bytes = Vec::new();
+        state_serde.append_vec(&mut state_bytes, None, &state)?;
+        state_pool.write(pool_index, &state_bytes[..])?;
+        self.state_pool_state_list[pool_index] = Some(state.unbind());
+        Ok(())
+    }
+
+    // Serializes a batch of states into consecutive slots of the state pool starting at start_index,
+    // so that a group of processes can each be assigned one of them with send_set_states_from_pool
+    #[pyo3(signature = (states, start_index=0))]
+    pub fn set_pool_states<'py>(
+        &mut self,
+        states: Vec<Bound<'py, PyAny>>,
+        start_index: usize,
+    ) -> PyResult<()> {
+        for (pool_index, state) in (start_index..).zip(states) {
+            self.set_pool_state(pool_index, state)?;
+        }
+        Ok(())
+    }
+
+    // Sends SET_STATE env actions to a group of processes, each using the state in the slot of the
+    // state pool it is assigned to
+    #[pyo3(signature = (proc_id_pool_index_dict, send_state=false, send_shared_info=true))]
+    pub fn send_set_states_from_pool<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id_pool_index_dict: HashMap<String, usize>,
+        send_state: bool,
+        send_shared_info: bool,
+    ) -> PyResult<()> {
+        for (proc_id, pool_index) in proc_id_pool_index_dict.into_iter() {
+            self.send_set_state_from_pool(
+                py,
+                proc_id,
+                pool_index,
+                send_state,
+                None,
+                send_shared_info,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Sends a SET_STATE env action to the given process, using the state in the given slot of the
+    // state pool as the desired state instead of sending it in the message. The response is
+    // collected by collect_step_data like that of any other env action.
+    #[pyo3(signature = (proc_id, pool_index, send_state=false, prev_timestep_id_dict_option=None, send_shared_info=true))]
+    pub fn send_set_state_from_pool<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: String,
+        pool_index: usize,
+        send_state: bool,
+        prev_timestep_id_dict_option: Option<PyObject>,
+        send_shared_info: bool,
+    ) -> PyResult<()> {
+        let desired_state = self
+            .state_pool_state_list
+            .get(pool_index)
+            .and_then(|state_option| state_option.as_ref())
+            .ok_or_else(|| {
+                InvalidStateError::new_err(format!(
+                    "Tried to send a SetStateFromPool request for state pool slot {}, but no state has been set in that slot",
+                    pool_index
+                ))
+            })?
+            .clone_ref(py);
+        let &pid_idx = self.proc_id_pid_idx_map.get(&proc_id).ok_or_else(|| {
+            InvalidStateError::new_err(format!(
+                "Tried to send SetStateFromPool header to env process with proc id {}, but there is no such process",
+                proc_id
+            ))
+        })?;
+        let (_, shmem, _, _) = self.proc_packages.get_mut(pid_idx).unwrap();
+        let (ep_evt, evt_used_bytes) = unsafe {
+            Event::from_existing(shmem.as_ptr()).map_err(|err| {
+                InvalidStateError::new_err(format!("Failed to get event: {}", err))
+            })?
+        };
+        let shm_slice = unsafe { &mut shmem.as_slice_mut()[evt_used_bytes..] };
+        let mut offset = append_header(shm_slice, 0, Header::SetStateFromPool);
+        offset = append_usize_le(shm_slice, offset, pool_index);
+        offset = append_bool(shm_slice, offset, send_state);
+        append_bool(shm_slice, offset, send_shared_info);
+        ep_evt
+            .set(EventState::Signaled)
+            .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+        self.pid_idx_current_aald_option[pid_idx] = None;
+        self.pid_idx_current_env_action[pid_idx] = Some(EnvAction::SET_STATE {
+            desired_state,
+            shared_info_setter_option: None,
+            send_state,
+            prev_timestep_id_dict_option,
+            send_shared_info,
+            rng_state_option: None,
+        });
+        Ok(())
+    }
+
+    // Sends a Resync request to the given process, which discards its loop state and resets the env,
+    // so that the parent can realign with a process whose protocol state it lost track of. The
+    // process must be waiting for a message when this is sent. Its reset response is collected by
+    // collect_step_data like that of any other env action.
+    #[pyo3(signature = (proc_id, send_shared_info=true))]
+    pub fn send_resync(&mut self, proc_id: String, send_shared_info: bool) -> PyResult<()> {
+        let &pid_idx = self.proc_id_pid_idx_map.get(&proc_id).ok_or_else(|| {
+            InvalidStateError::new_err(format!(
+                "Tried to send Resync header to env process with proc id {}, but there is no such process",
+                proc_id
+            ))
+        })?;
+        let (_, shmem, _, _) = self.proc_packages.get_mut(pid_idx).unwrap();
+        let (ep_evt, evt_used_bytes) = unsafe {
+            Event::from_existing(shmem.as_ptr()).map_err(|err| {
+                InvalidStateError::new_err(format!("Failed to get event: {}", err))
+            })?
+        };
+        let shm_slice = unsafe { &mut shmem.as_slice_mut()[evt_used_bytes..] };
+        let offset = append_header(shm_slice, 0, Header::Resync);
+        append_bool(shm_slice, offset, send_shared_info);
+        ep_evt
+            .set(EventState::Signaled)
+            .map_err(|err| InvalidStateError::new_err(err.to_string()))?;
+        self.pid_idx_current_aald_option[pid_idx] = None;
+        self.pid_idx_prev_timestep_id_option_list_option[pid_idx] = None;
+        self.pid_idx_current_env_action[pid_idx] = Some(EnvAction::RESET {
+            shared_info_setter_option: None,
+            send_state: false,
+            send_shared_info,
+        });
+        Ok(())
+    }
+
     pub fn send_env_actions<'py>(
         &mut self,
         py: Python<'py>,
@@ -730,9 +2109,12 @@ impl EnvProcessInterface {
                 shm_slice,
                 offset,
                 &env_action,
-                &mut self.action_serde,
-                &mut self.shared_info_setter_serde_option.as_mut(),
-                &mut self.state_serde_option.as_mut(),
+                &mut EnvActionSerdes {
+                    action_serde: &mut self.action_serde,
+                    shared_info_setter_serde_option: self.shared_info_setter_serde_option.as_mut(),
+                    state_serde_option: self.state_serde_option.as_mut(),
+                    rng_state_serde_option: self.rng_state_serde_option.as_mut(),
+                },
             )?;
 
             ep_evt
@@ -0,0 +1,305 @@
+use std::time::Duration;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::arrow_ipc::ObsFormat;
+use crate::compression::CompressionCodec;
+use crate::env_action::{
+    ActionInterface, DoneReasonSource, LegalActionMaskSource, MacroRewardAggregation,
+    RewardBoundsPolicy,
+};
+use crate::memory_watch::MemoryWatchConfig;
+use crate::message_trace::MessageTraceMode;
+use crate::misc::TorchDtype;
+use crate::offset_tracker::BufferFullPolicy;
+use crate::output_capture::OutputCapture;
+use crate::render_clock::RenderSync;
+
+// The optional env_process settings are grouped by concern into these options objects. Each
+// starts out with the defaults and is configured by setting attributes, and env_process uses the
+// defaults for any options object it is not given.
+
+// How agent ids are ordered and checked
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug, Default)]
+pub struct AgentOptions {
+    pub sort_agent_ids: bool,
+    pub agent_id_sort_key_fn_option: Option<Py<PyAny>>,
+    pub stable_agent_indices: bool,
+    pub agent_order_permutation_option: Option<Vec<usize>>,
+    pub expected_n_agents_option: Option<usize>,
+    pub strict_agent_keys: bool,
+    pub check_step_dict_keys: bool,
+}
+
+#[pymethods]
+impl AgentOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// How each env action is applied to the env
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct StepOptions {
+    pub action_interface: ActionInterface,
+    pub default_action_option: Option<Py<PyAny>>,
+    pub default_action_on_first_step: bool,
+    pub pre_step_fn_option: Option<Py<PyAny>>,
+    pub macro_reward_aggregation_option: Option<MacroRewardAggregation>,
+    pub frame_skip_option: Option<usize>,
+    pub frame_skip_reward_aggregation: MacroRewardAggregation,
+    pub action_repeat_option: Option<usize>,
+    pub reward_bounds_option: Option<(f64, f64)>,
+    pub reward_bounds_policy: RewardBoundsPolicy,
+    pub shared_info_setter_keys_option: Option<Vec<String>>,
+    pub initial_shared_info_setter_bytes_option: Option<Vec<u8>>,
+    pub max_message_rate_hz_option: Option<f64>,
+}
+
+impl Default for StepOptions {
+    fn default() -> Self {
+        StepOptions {
+            action_interface: ActionInterface::DICT,
+            default_action_option: None,
+            default_action_on_first_step: false,
+            pre_step_fn_option: None,
+            macro_reward_aggregation_option: None,
+            frame_skip_option: None,
+            frame_skip_reward_aggregation: MacroRewardAggregation::SUM,
+            action_repeat_option: None,
+            reward_bounds_option: None,
+            reward_bounds_policy: RewardBoundsPolicy::ERROR,
+            shared_info_setter_keys_option: None,
+            initial_shared_info_setter_bytes_option: None,
+            max_message_rate_hz_option: None,
+        }
+    }
+}
+
+#[pymethods]
+impl StepOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// When episodes end and how resets are performed
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug, Default)]
+pub struct EpisodeOptions {
+    pub max_episode_duration_option: Option<Duration>,
+    pub agent_max_steps_dict_option: Option<Py<PyDict>>,
+    pub done_reason_source_option: Option<DoneReasonSource>,
+    pub reset_settle_steps_option: Option<usize>,
+    pub reset_loop_threshold_option: Option<usize>,
+    pub reset_loop_errors: bool,
+    pub continue_from_final_state: bool,
+    pub keepalive_idle_threshold_option: Option<Duration>,
+    pub keepalive_fn_option: Option<Py<PyAny>>,
+}
+
+#[pymethods]
+impl EpisodeOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// How obs are computed and encoded
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct ObsOptions {
+    pub obs_transform_fn_option: Option<Py<PyAny>>,
+    pub obs_dtype_cast_option: Option<TorchDtype>,
+    pub obs_force_f64: bool,
+    pub frame_stack_option: Option<usize>,
+    pub obs_format: ObsFormat,
+    pub cuda_ipc_obs: bool,
+    pub fixed_layout_obs_shape_option: Option<Vec<usize>>,
+    pub obs_file_dir_option: Option<String>,
+    pub obs_chunk_size_option: Option<usize>,
+    pub reuse_identical_obs: bool,
+    pub dedup_set_state_obs: bool,
+    pub global_obs_fn_option: Option<Py<PyAny>>,
+    pub legal_action_mask_source_option: Option<LegalActionMaskSource>,
+}
+
+impl Default for ObsOptions {
+    fn default() -> Self {
+        ObsOptions {
+            obs_transform_fn_option: None,
+            obs_dtype_cast_option: None,
+            obs_force_f64: false,
+            frame_stack_option: None,
+            obs_format: ObsFormat::SERDE,
+            cuda_ipc_obs: false,
+            fixed_layout_obs_shape_option: None,
+            obs_file_dir_option: None,
+            obs_chunk_size_option: None,
+            reuse_identical_obs: false,
+            dedup_set_state_obs: false,
+            global_obs_fn_option: None,
+            legal_action_mask_source_option: None,
+        }
+    }
+}
+
+#[pymethods]
+impl ObsOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// Which extra fields are written to the buffer and how the buffer is managed
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct BufferOptions {
+    pub episode_boundary_reasons: bool,
+    pub bootstrap_flags: bool,
+    pub state_hashes: bool,
+    pub env_step_counts: bool,
+    pub prev_actions: bool,
+    pub packed_done_flags: bool,
+    pub include_counters: bool,
+    pub new_agent_flags: bool,
+    pub per_agent_bytes_option: Option<usize>,
+    pub overhead_bytes: usize,
+    pub region_alignment_option: Option<usize>,
+    pub buffer_full_policy: BufferFullPolicy,
+    pub layout_schema_path_option: Option<String>,
+    pub mqueue_max_message_bytes_option: Option<usize>,
+    pub partition_bytes_option: Option<usize>,
+    pub n_partitions: usize,
+    pub base_offset_option: Option<usize>,
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        BufferOptions {
+            episode_boundary_reasons: false,
+            bootstrap_flags: false,
+            state_hashes: false,
+            env_step_counts: false,
+            prev_actions: false,
+            packed_done_flags: false,
+            include_counters: false,
+            new_agent_flags: false,
+            per_agent_bytes_option: None,
+            overhead_bytes: 0,
+            region_alignment_option: None,
+            buffer_full_policy: BufferFullPolicy::ERROR,
+            layout_schema_path_option: None,
+            mqueue_max_message_bytes_option: None,
+            partition_bytes_option: None,
+            n_partitions: 0,
+            base_offset_option: None,
+        }
+    }
+}
+
+#[pymethods]
+impl BufferOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// When and how the env is rendered, in addition to the render and render_delay_option arguments
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub render_sync: RenderSync,
+    pub render_every_option: Option<usize>,
+    pub render_predicate_fn_option: Option<Py<PyAny>>,
+    pub render_errors_fatal: bool,
+    pub render_thread: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            render_sync: RenderSync::OFF {},
+            render_every_option: None,
+            render_predicate_fn_option: None,
+            render_errors_fatal: true,
+            render_thread: false,
+        }
+    }
+}
+
+#[pymethods]
+impl RenderOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// Diagnostics which do not change what is written to the buffer
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug)]
+pub struct DebugOptions {
+    pub output_capture: OutputCapture,
+    pub strict_protocol: bool,
+    pub sync_selfcheck: bool,
+    pub slow_step_threshold_option: Option<Duration>,
+    pub buffer_dump_dir_option: Option<String>,
+    pub message_trace_mode_option: Option<MessageTraceMode>,
+    pub memory_watch_config_option: Option<MemoryWatchConfig>,
+    pub action_history_len_option: Option<usize>,
+}
+
+impl Default for DebugOptions {
+    fn default() -> Self {
+        DebugOptions {
+            output_capture: OutputCapture::OFF {},
+            strict_protocol: false,
+            sync_selfcheck: false,
+            slow_step_threshold_option: None,
+            buffer_dump_dir_option: None,
+            message_trace_mode_option: None,
+            memory_watch_config_option: None,
+            action_history_len_option: None,
+        }
+    }
+}
+
+#[pymethods]
+impl DebugOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
+
+// The optional serdes beyond those env_process always takes. The serde types are extracted when
+// the process starts.
+#[pyclass(module = "rlgym_learn", get_all, set_all)]
+#[derive(Clone, Debug, Default)]
+pub struct SerdeOptions {
+    pub reward_component_serde_option: Option<Py<PyAny>>,
+    pub critic_obs_serde_option: Option<Py<PyAny>>,
+    pub global_obs_serde_option: Option<Py<PyAny>>,
+    pub legal_action_mask_serde_option: Option<Py<PyAny>>,
+    pub agent_obs_serdes_option: Option<Py<PyDict>>,
+    pub rng_state_serde_option: Option<Py<PyAny>>,
+    pub serde_fallback_fn_option: Option<Py<PyAny>>,
+    pub shared_info_compression_option: Option<CompressionCodec>,
+}
+
+#[pymethods]
+impl SerdeOptions {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,113 @@
+use std::mem::size_of;
+
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyMemoryView};
+
+use crate::communication::{aligned_offset, append_alignment_padding_vec};
+
+// With the FIXED_LAYOUT obs format, the obs of all agents in a message are written together after
+// the per-agent fields as a block of fixed size records, one per agent in agent id list order. Each
+// record is the obs flattened in C order as little-endian f32 values, with nothing between records.
+// The block starts at an address aligned to FIXED_LAYOUT_ALIGNMENT, so that it can be reinterpreted
+// in place by vectorized parsers.
+pub const FIXED_LAYOUT_ALIGNMENT: usize = 64;
+
+// Appends the values of a number or of (possibly nested) sequences of numbers, in C order
+fn extend_flat(values: &mut Vec<f32>, obj: &Bound<'_, PyAny>) -> PyResult<()> {
+    if let Ok(value) = obj.extract::<f32>() {
+        values.push(value);
+        return Ok(());
+    }
+    for item in obj.try_iter()? {
+        extend_flat(values, &item?)?;
+    }
+    Ok(())
+}
+
+pub struct FixedLayoutObsWriter {
+    shape: Vec<usize>,
+    n_values: usize,
+}
+
+impl FixedLayoutObsWriter {
+    pub fn new(shape: Vec<usize>) -> Self {
+        let n_values = shape.iter().product();
+        FixedLayoutObsWriter { shape, n_values }
+    }
+
+    // C contiguous f32 buffers (like float32 numpy arrays) are copied directly, anything else is
+    // flattened and converted value by value
+    fn obs_values(&self, obs: &Bound<'_, PyAny>) -> PyResult<Vec<f32>> {
+        let py = obs.py();
+        if let Ok(buffer) = PyBuffer::<f32>::get(obs) {
+            if buffer.is_c_contiguous() {
+                return buffer.to_vec(py);
+            }
+        }
+        let mut values = Vec::with_capacity(self.n_values);
+        if obs.hasattr(intern!(py, "flatten"))? {
+            extend_flat(
+                &mut values,
+                &obs.call_method0(intern!(py, "flatten"))?
+                    .call_method0(intern!(py, "tolist"))?,
+            )?;
+        } else {
+            extend_flat(&mut values, obs)?;
+        }
+        Ok(values)
+    }
+
+    // Appends the records to v, aligned as if v were copied to start_addr
+    pub fn append_vec(
+        &self,
+        v: &mut Vec<u8>,
+        start_addr: usize,
+        obs_list: Vec<Bound<'_, PyAny>>,
+        proc_id: &str,
+    ) -> PyResult<()> {
+        append_alignment_padding_vec(v, start_addr, FIXED_LAYOUT_ALIGNMENT);
+        for obs in obs_list.iter() {
+            let values = self.obs_values(obs)?;
+            if values.len() != self.n_values {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} was given the fixed layout obs shape {:?} with {} values, but received an obs with {} values",
+                    proc_id,
+                    self.shape,
+                    self.n_values,
+                    values.len()
+                )));
+            }
+            for value in values.iter() {
+                v.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads the records written by FixedLayoutObsWriter::append_vec. Each obs is returned as a memoryview
+// of its record with format "f" and the given shape, which numpy and torch can wrap without copying.
+pub fn read_fixed_layout_obs_list<'py>(
+    py: Python<'py>,
+    buf: &[u8],
+    offset: usize,
+    n_agents: usize,
+    shape: &[usize],
+) -> PyResult<(Vec<Bound<'py, PyAny>>, usize)> {
+    let record_len = shape.iter().product::<usize>() * size_of::<f32>();
+    let mut offset = aligned_offset(buf, offset, FIXED_LAYOUT_ALIGNMENT);
+    let mut obs_list = Vec::with_capacity(n_agents);
+    for _ in 0..n_agents {
+        let end = offset + record_len;
+        let record = PyBytes::new(py, &buf[offset..end]);
+        obs_list.push(
+            PyMemoryView::from(&record)?
+                .call_method1(intern!(py, "cast"), ("f", shape.to_vec()))?,
+        );
+        offset = end;
+    }
+    Ok((obs_list, offset))
+}
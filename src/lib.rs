@@ -1,20 +1,43 @@
 use pyo3::prelude::*;
 
+pub mod action_space_descriptor;
 pub mod agent_manager;
+pub mod agent_obs_serdes;
+pub mod arrow_ipc;
+pub mod buffer_layout;
+pub mod communication;
+pub mod compression;
+pub mod cuda_ipc;
 pub mod env_action;
 pub mod env_process;
 pub mod env_process_interface;
+pub mod env_process_options;
+pub mod fixed_layout;
+pub mod memory_watch;
+pub mod message_trace;
 pub mod misc;
+pub mod obs_file;
+pub mod offset_tracker;
+pub mod output_capture;
+pub mod partitioned_buffer;
+pub mod render_clock;
+pub mod render_thread;
 pub mod rocket_league;
+pub mod serde_version;
+pub mod state_pool;
 pub mod synchronization;
 pub mod timestep;
+pub mod transport;
 
 #[pymodule]
 #[pyo3(name = "rlgym_learn")]
 fn rlgym_learn(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(env_process::env_process, m)?)?;
+    m.add_function(wrap_pyfunction!(env_process::run_env_steps, m)?)?;
     m.add_function(wrap_pyfunction!(synchronization::recvfrom_byte, m)?)?;
     m.add_function(wrap_pyfunction!(synchronization::sendto_byte, m)?)?;
+    m.add_function(wrap_pyfunction!(misc::roundtrip_check, m)?)?;
+    m.add_function(wrap_pyfunction!(buffer_layout::buffer_layout, m)?)?;
     m.add_function(wrap_pyfunction!(
         rocket_league::math::rotation_to_quaternion_py,
         m
@@ -45,6 +68,31 @@ fn rlgym_learn(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<env_action::EnvActionResponse>()?;
     m.add_class::<env_action::EnvActionResponseType>()?;
     m.add_class::<env_action::EnvAction>()?;
+    m.add_class::<env_action::EpisodeBoundaryReason>()?;
+    m.add_class::<env_action::MacroRewardAggregation>()?;
+    m.add_class::<env_action::ActionInterface>()?;
+    m.add_class::<env_action::RewardBoundsPolicy>()?;
+    m.add_class::<offset_tracker::BufferFullPolicy>()?;
+    m.add_class::<env_action::DoneReasonSource>()?;
+    m.add_class::<env_action::LegalActionMaskSource>()?;
+    m.add_class::<message_trace::MessageTraceMode>()?;
+    m.add_class::<output_capture::OutputCapture>()?;
+    m.add_class::<compression::CompressionCodec>()?;
+    m.add_class::<render_clock::RenderSync>()?;
+    m.add_class::<misc::TorchDtype>()?;
+    m.add_class::<arrow_ipc::ObsFormat>()?;
+    m.add_class::<buffer_layout::BufferLayoutConfig>()?;
+    m.add_class::<memory_watch::MemoryWatchConfig>()?;
+    m.add_class::<env_process_options::AgentOptions>()?;
+    m.add_class::<env_process_options::StepOptions>()?;
+    m.add_class::<env_process_options::EpisodeOptions>()?;
+    m.add_class::<env_process_options::ObsOptions>()?;
+    m.add_class::<env_process_options::BufferOptions>()?;
+    m.add_class::<env_process_options::RenderOptions>()?;
+    m.add_class::<env_process_options::DebugOptions>()?;
+    m.add_class::<env_process_options::SerdeOptions>()?;
+    m.add_class::<action_space_descriptor::ActionSpaceKind>()?;
+    m.add_class::<action_space_descriptor::ActionSpaceDescriptor>()?;
     #[cfg(feature = "rl")]
     {
         m.add_class::<rocket_league::CarPythonSerde>()?;
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+
+use pyo3::prelude::*;
+
+#[pyclass(module = "rlgym_learn", get_all)]
+#[derive(Clone, Debug)]
+pub struct MemoryWatchConfig {
+    // Number of env actions between RSS samples
+    pub interval_steps: usize,
+    // Warn whenever a sample exceeds this many bytes
+    pub rss_threshold_bytes_option: Option<usize>,
+    // Warn when this many consecutive samples are strictly increasing. Values below 2 disable the
+    // check.
+    pub growth_window: usize,
+}
+
+#[pymethods]
+impl MemoryWatchConfig {
+    #[new]
+    #[pyo3(signature = (interval_steps=1000, rss_threshold_bytes_option=None, growth_window=10))]
+    pub fn new(
+        interval_steps: usize,
+        rss_threshold_bytes_option: Option<usize>,
+        growth_window: usize,
+    ) -> Self {
+        MemoryWatchConfig {
+            interval_steps,
+            rss_threshold_bytes_option,
+            growth_window,
+        }
+    }
+}
+
+// Reads the resident set size of this process in bytes, if the platform supports it
+#[cfg(target_os = "linux")]
+fn current_rss_bytes() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let rss_kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+    Some(rss_kb * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_rss_bytes() -> Option<usize> {
+    None
+}
+
+pub struct MemoryWatch {
+    config: MemoryWatchConfig,
+    n_steps: usize,
+    sample_list: VecDeque<usize>,
+}
+
+impl MemoryWatch {
+    pub fn new(config: MemoryWatchConfig) -> Self {
+        MemoryWatch {
+            config,
+            n_steps: 0,
+            sample_list: VecDeque::new(),
+        }
+    }
+
+    // Called after each env action. Samples the RSS every interval_steps calls and logs a warning
+    // if the threshold is exceeded or the RSS has grown over the whole window.
+    pub fn step(&mut self, proc_id: &str) {
+        self.n_steps += 1;
+        if self.config.interval_steps == 0
+            || !self.n_steps.is_multiple_of(self.config.interval_steps)
+        {
+            return;
+        }
+        let Some(rss_bytes) = current_rss_bytes() else {
+            return;
+        };
+        if let Some(rss_threshold_bytes) = self.config.rss_threshold_bytes_option {
+            if rss_bytes > rss_threshold_bytes {
+                println!(
+                    "Env process {} has an RSS of {} bytes, which exceeds the memory watch threshold of {} bytes",
+                    proc_id, rss_bytes, rss_threshold_bytes
+                );
+            }
+        }
+        if self.config.growth_window < 2 {
+            return;
+        }
+        self.sample_list.push_back(rss_bytes);
+        if self.sample_list.len() > self.config.growth_window {
+            self.sample_list.pop_front();
+        }
+        if self.sample_list.len() == self.config.growth_window
+            && self
+                .sample_list
+                .iter()
+                .zip(self.sample_list.iter().skip(1))
+                .all(|(prev, next)| next > prev)
+        {
+            println!(
+                "Env process {} has an RSS which grew over each of the last {} samples (from {} to {} bytes), which may indicate a memory leak",
+                proc_id,
+                self.config.growth_window,
+                self.sample_list.front().unwrap(),
+                rss_bytes
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+
+use crate::communication::{append_usize_le_vec, retrieve_bytes_le, retrieve_u8};
+
+// The messages exchanged by an env process can be recorded to a trace file and later replayed
+// without a live parent, to reproduce the exact same env interactions. The trace contains the
+// incoming messages which affect the env (env actions, set state from pool, resync, set seed, set
+// state batch, warm start and reconfigure requests), each followed by the response written for it if it was an
+// env action. Each record is a kind byte followed by the length prefixed message bytes.
+#[allow(non_camel_case_types)]
+#[pyclass(module = "rlgym_learn")]
+#[derive(Clone, Debug)]
+pub enum MessageTraceMode {
+    // Appends the messages to the trace file at path, which is truncated first
+    RECORD { path: String },
+    // Feeds the incoming messages of the trace file at path into the env process instead of waiting
+    // for a parent, comparing each response to the recorded one. The env process stops at the end
+    // of the trace.
+    REPLAY { path: String },
+}
+
+const INCOMING_RECORD: u8 = 0;
+const OUTGOING_RECORD: u8 = 1;
+
+pub struct MessageTraceWriter {
+    writer: BufWriter<File>,
+    record: Vec<u8>,
+}
+
+impl MessageTraceWriter {
+    pub fn create(path: &str) -> PyResult<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|err| {
+                InvalidStateError::new_err(format!(
+                    "Unable to create message trace file {}: {}",
+                    path, err
+                ))
+            })?;
+        Ok(MessageTraceWriter {
+            writer: BufWriter::new(file),
+            record: Vec::new(),
+        })
+    }
+
+    fn write_record(&mut self, kind: u8, message_bytes: &[u8]) -> PyResult<()> {
+        self.record.clear();
+        self.record.push(kind);
+        append_usize_le_vec(&mut self.record, message_bytes.len());
+        self.record.extend_from_slice(message_bytes);
+        self.writer.write_all(&self.record[..])?;
+        // Flushed after every record so that the trace is complete up to the point of a crash
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn record_incoming(&mut self, message_bytes: &[u8]) -> PyResult<()> {
+        self.write_record(INCOMING_RECORD, message_bytes)
+    }
+
+    pub fn record_outgoing(&mut self, message_bytes: &[u8]) -> PyResult<()> {
+        self.write_record(OUTGOING_RECORD, message_bytes)
+    }
+}
+
+pub struct MessageTraceReader {
+    path: String,
+    trace_bytes: Vec<u8>,
+    offset: usize,
+    n_responses_compared: usize,
+    n_responses_diverged: usize,
+}
+
+impl MessageTraceReader {
+    pub fn open(path: &str) -> PyResult<Self> {
+        let trace_bytes = std::fs::read(path).map_err(|err| {
+            InvalidStateError::new_err(format!(
+                "Unable to read message trace file {}: {}",
+                path, err
+            ))
+        })?;
+        Ok(MessageTraceReader {
+            path: path.to_owned(),
+            trace_bytes,
+            offset: 0,
+            n_responses_compared: 0,
+            n_responses_diverged: 0,
+        })
+    }
+
+    fn peek_kind(&self) -> PyResult<Option<u8>> {
+        if self.offset >= self.trace_bytes.len() {
+            return Ok(None);
+        }
+        Ok(Some(retrieve_u8(&self.trace_bytes[..], self.offset)?.0))
+    }
+
+    fn next_record(&mut self) -> PyResult<&[u8]> {
+        let (message_bytes, offset) = retrieve_bytes_le(&self.trace_bytes[..], self.offset + 1)?;
+        self.offset = offset;
+        Ok(message_bytes)
+    }
+
+    // Returns the next incoming message, skipping any responses which weren't compared, or None at
+    // the end of the trace
+    pub fn next_incoming(&mut self) -> PyResult<Option<&[u8]>> {
+        while let Some(kind) = self.peek_kind()? {
+            if kind == INCOMING_RECORD {
+                return self.next_record().map(Some);
+            }
+            self.next_record()?;
+        }
+        Ok(None)
+    }
+
+    // Compares the response written for the last incoming message to the recorded one, if there is
+    // one. Returns whether it diverged.
+    pub fn compare_outgoing(&mut self, message_bytes: &[u8]) -> PyResult<bool> {
+        if self.peek_kind()? != Some(OUTGOING_RECORD) {
+            return Ok(false);
+        }
+        let diverged = self.next_record()? != message_bytes;
+        self.n_responses_compared += 1;
+        if diverged {
+            self.n_responses_diverged += 1;
+        }
+        Ok(diverged)
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "replayed message trace {}: {} of {} responses diverged from the recorded ones",
+            self.path, self.n_responses_diverged, self.n_responses_compared
+        )
+    }
+}
@@ -1,7 +1,8 @@
+use pyany_serde::PyAnySerde;
 use pyo3::{
-    intern,
+    intern, pyclass, pyfunction,
     sync::GILOnceCell,
-    types::{PyAnyMethods, PyDict},
+    types::{PyAnyMethods, PyDict, PyStringMethods, PyTypeMethods},
     Bound, IntoPyObject, PyAny, PyErr, PyObject, PyResult, Python,
 };
 
@@ -26,6 +27,14 @@ pub fn torch_cat<'py>(py: Python<'py>, obj: &[Bound<'py, PyAny>]) -> PyResult<Bo
         .call1((obj,))?)
 }
 
+pub fn torch_stack<'py>(py: Python<'py>, obj: &[Bound<'py, PyAny>]) -> PyResult<Bound<'py, PyAny>> {
+    static INTERNED_STACK: GILOnceCell<PyObject> = GILOnceCell::new();
+    INTERNED_STACK
+        .get_or_try_init::<_, PyErr>(py, || Ok(py.import("torch")?.getattr("stack")?.unbind()))?
+        .bind(py)
+        .call1((obj,))
+}
+
 pub fn torch_empty<'py>(
     shape: &Bound<'py, PyAny>,
     dtype: &Bound<'py, PyAny>,
@@ -42,3 +51,151 @@ pub fn torch_empty<'py>(
             )?),
         )?)
 }
+
+// A dtype which float observation tensors can be cast to before serialization
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum TorchDtype {
+    BFLOAT16,
+    FLOAT16,
+    FLOAT64,
+}
+
+impl TorchDtype {
+    fn attr_name(&self) -> &'static str {
+        match self {
+            TorchDtype::BFLOAT16 => "bfloat16",
+            TorchDtype::FLOAT16 => "float16",
+            TorchDtype::FLOAT64 => "float64",
+        }
+    }
+}
+
+// Casts obj to dtype if it is a floating point tensor, and returns it unchanged otherwise
+pub fn torch_cast_float<'py>(
+    obj: Bound<'py, PyAny>,
+    dtype: &TorchDtype,
+) -> PyResult<Bound<'py, PyAny>> {
+    static INTERNED_TORCH: GILOnceCell<PyObject> = GILOnceCell::new();
+    let py = obj.py();
+    let torch = INTERNED_TORCH
+        .get_or_try_init::<_, PyErr>(py, || Ok(py.import("torch")?.into_any().unbind()))?
+        .bind(py);
+    if !torch
+        .call_method1(intern!(py, "is_tensor"), (&obj,))?
+        .extract::<bool>()?
+        || !obj
+            .call_method0(intern!(py, "is_floating_point"))?
+            .extract::<bool>()?
+    {
+        return Ok(obj);
+    }
+    obj.call_method1(intern!(py, "to"), (torch.getattr(dtype.attr_name())?,))
+}
+
+// Converts obj to a numpy array with jax.device_get if it is a JAX array, and returns it unchanged
+// otherwise. Candidates are picked out by the module of their type first, so that jax is only
+// imported once an object from it is encountered.
+pub fn jax_to_numpy<'py>(obj: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    static INTERNED_JAX: GILOnceCell<PyObject> = GILOnceCell::new();
+    let py = obj.py();
+    let module = obj.get_type().module()?;
+    let module = module.to_str()?;
+    if !["jax", "jaxlib"].iter().any(|package| {
+        module == *package
+            || module
+                .strip_prefix(package)
+                .is_some_and(|rest| rest.starts_with('.'))
+    }) {
+        return Ok(obj);
+    }
+    let jax = INTERNED_JAX
+        .get_or_try_init::<_, PyErr>(py, || Ok(py.import("jax")?.into_any().unbind()))?
+        .bind(py);
+    if !obj.is_instance(&jax.getattr(intern!(py, "Array"))?)? {
+        return Ok(obj);
+    }
+    jax.call_method1(intern!(py, "device_get"), (obj,))
+}
+
+// Appends obj into a scratch buffer with the serde and retrieves it again. Returns whether the
+// retrieved object compares equal to obj, and the number of bytes the serde used.
+#[pyfunction]
+pub fn roundtrip_check<'py>(
+    py: Python<'py>,
+    mut serde: Box<dyn PyAnySerde>,
+    obj: Bound<'py, PyAny>,
+) -> PyResult<(bool, usize)> {
+    let mut buf = Vec::new();
+    serde.append_vec(&mut buf, None, &obj)?;
+    let (retrieved, n_bytes) = serde.retrieve(py, &buf[..], 0)?;
+    Ok((retrieved.eq(&obj)?, n_bytes))
+}
+
+// Sets the OS name of the calling thread, so that it shows up in top, gdb and profilers. Linux limits
+// thread names to 15 bytes, so the name is truncated there. This is a no-op on platforms without
+// thread naming, and failures are ignored since the name is only a debugging aid.
+pub fn set_current_thread_name(name: &str) {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        let mut len = name.len();
+        if cfg!(target_os = "linux") {
+            len = len.min(15);
+            while !name.is_char_boundary(len) {
+                len -= 1;
+            }
+        }
+        let Ok(c_name) = std::ffi::CString::new(&name[..len]) else {
+            return;
+        };
+        unsafe {
+            #[cfg(target_os = "linux")]
+            libc::pthread_setname_np(libc::pthread_self(), c_name.as_ptr());
+            #[cfg(target_os = "macos")]
+            libc::pthread_setname_np(c_name.as_ptr());
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = name;
+}
+
+#[cfg(test)]
+mod tests {
+    use pyany_serde::pyany_serde_impl::{DictSerde, IntSerde, StringSerde};
+    use pyo3::types::{PyDictMethods, PyListMethods};
+
+    use super::*;
+
+    // Ordered maps need no serde of their own: the dict serde writes the entry count followed by
+    // each key and value in insertion order, and inserts them in the same order when retrieving
+    #[test]
+    fn dict_serde_keeps_insertion_order() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut serde = DictSerde {
+                keys_serde: Box::new(StringSerde {}),
+                values_serde: Box::new(IntSerde {}),
+            };
+            let dict = PyDict::new(py);
+            for (idx, key) in ["c", "a", "b"].into_iter().enumerate() {
+                dict.set_item(key, idx).unwrap();
+            }
+            let mut buf = vec![0; 256];
+            let end = serde.append(&mut buf, 0, dict.as_any()).unwrap();
+            let mut v = Vec::new();
+            serde.append_vec(&mut v, None, dict.as_any()).unwrap();
+            assert_eq!(&buf[..end], &v[..]);
+            let (retrieved, offset) = serde.retrieve(py, &buf, 0).unwrap();
+            assert_eq!(offset, end);
+            let keys: Vec<String> = retrieved
+                .downcast::<PyDict>()
+                .unwrap()
+                .keys()
+                .iter()
+                .map(|key| key.extract().unwrap())
+                .collect();
+            assert_eq!(keys, ["c", "a", "b"]);
+        });
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+
+use pyany_serde::PyAnySerde;
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+
+use crate::communication::{append_usize_le, append_usize_le_vec, retrieve_usize_le};
+
+// Observations too large for the shared memory buffer can be written to a per-process file on disk
+// instead, in which case only their offset and length in the file are written to the buffer. Each
+// message overwrites the file from the start, since the parent reads all the observations of a
+// message before sending the env action that leads to the next one.
+
+fn get_obs_file_path(obs_file_dir: &str, proc_id: &str) -> String {
+    format!("{}/{}.obs", obs_file_dir, proc_id)
+}
+
+pub struct ObsFileWriter {
+    path: String,
+    file: File,
+    file_offset: usize,
+}
+
+impl ObsFileWriter {
+    pub fn create(obs_file_dir: &str, proc_id: &str) -> PyResult<Self> {
+        let path = get_obs_file_path(obs_file_dir, proc_id);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|err| {
+                InvalidStateError::new_err(format!("Unable to create obs file {}: {}", path, err))
+            })?;
+        Ok(ObsFileWriter {
+            path,
+            file,
+            file_offset: 0,
+        })
+    }
+
+    pub fn start_message(&mut self) {
+        self.file_offset = 0;
+    }
+
+    // Writes the serialized obs to the file and returns its offset in the file
+    fn write_obs_bytes(&mut self, obs_bytes: &[u8]) -> PyResult<usize> {
+        let file_offset = self.file_offset;
+        self.file.write_all_at(obs_bytes, file_offset as u64)?;
+        self.file_offset += obs_bytes.len();
+        Ok(file_offset)
+    }
+
+    // Writes the serialized obs to the file and its offset and length in the file to buf
+    pub fn append(&mut self, buf: &mut [u8], offset: usize, obs_bytes: &[u8]) -> PyResult<usize> {
+        let file_offset = self.write_obs_bytes(obs_bytes)?;
+        let offset = append_usize_le(buf, offset, file_offset);
+        Ok(append_usize_le(buf, offset, obs_bytes.len()))
+    }
+
+    pub fn append_vec(&mut self, v: &mut Vec<u8>, obs_bytes: &[u8]) -> PyResult<()> {
+        let file_offset = self.write_obs_bytes(obs_bytes)?;
+        append_usize_le_vec(v, file_offset);
+        append_usize_le_vec(v, obs_bytes.len());
+        Ok(())
+    }
+}
+
+impl Drop for ObsFileWriter {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct ObsFileReader {
+    obs_file_dir: String,
+    file_option: Option<File>,
+    obs_bytes: Vec<u8>,
+}
+
+impl ObsFileReader {
+    pub fn new(obs_file_dir: String) -> Self {
+        ObsFileReader {
+            obs_file_dir,
+            file_option: None,
+            obs_bytes: Vec::new(),
+        }
+    }
+
+    // Reads the offset and length written to buf by ObsFileWriter::append and retrieves the obs
+    // from the file of the given process. The file is opened on first use and kept open, since the
+    // reader is specific to one process.
+    pub fn retrieve<'py>(
+        &mut self,
+        py: Python<'py>,
+        proc_id: &str,
+        obs_serde: &mut Box<dyn PyAnySerde>,
+        buf: &[u8],
+        offset: usize,
+    ) -> PyResult<(Bound<'py, PyAny>, usize)> {
+        let (file_offset, offset) = retrieve_usize_le(buf, offset)?;
+        let (len, offset) = retrieve_usize_le(buf, offset)?;
+        if self.file_option.is_none() {
+            let path = get_obs_file_path(&self.obs_file_dir, proc_id);
+            self.file_option = Some(File::open(&path).map_err(|err| {
+                InvalidStateError::new_err(format!("Unable to open obs file {}: {}", path, err))
+            })?);
+        }
+        self.obs_bytes.resize(len, 0);
+        self.file_option
+            .as_ref()
+            .unwrap()
+            .read_exact_at(&mut self.obs_bytes[..], file_offset as u64)?;
+        let (obs, _) = obs_serde.retrieve(py, &self.obs_bytes[..], 0)?;
+        Ok((obs, offset))
+    }
+}
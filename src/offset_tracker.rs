@@ -0,0 +1,177 @@
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::exceptions::PyBufferError;
+use pyo3::prelude::*;
+
+// Tracks the running write offset into a buffer, validating every offset reported by a writer
+// (serde append calls, primitive appends) so that a bogus offset results in a clear error instead of
+// silently wrapping or being interpreted as valid.
+#[derive(Debug)]
+pub struct OffsetTracker {
+    offset: usize,
+    buf_len: usize,
+}
+
+impl OffsetTracker {
+    pub fn new(offset: usize, buf_len: usize) -> Self {
+        OffsetTracker { offset, buf_len }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // Validates the new offset reported by a writer and moves the tracked offset to it
+    pub fn advance_to(&mut self, new_offset: usize) -> PyResult<usize> {
+        if new_offset < self.offset {
+            return Err(InvalidStateError::new_err(format!(
+                "Writer reported offset {} which is before the current offset {}",
+                new_offset, self.offset
+            )));
+        }
+        if new_offset > self.buf_len {
+            return Err(PyBufferError::new_err(format!(
+                "Writer reported offset {} which is past the end of the buffer (length {})",
+                new_offset, self.buf_len
+            )));
+        }
+        self.offset = new_offset;
+        Ok(new_offset)
+    }
+
+    // Moves the tracked offset forward by n bytes
+    pub fn advance_by(&mut self, n: usize) -> PyResult<usize> {
+        let new_offset = self.offset.checked_add(n).ok_or_else(|| {
+            InvalidStateError::new_err(format!(
+                "Advancing offset {} by {} bytes overflows",
+                self.offset, n
+            ))
+        })?;
+        self.advance_to(new_offset)
+    }
+}
+
+// What env_process does when a message does not fit in the shared memory buffer. With ERROR the
+// process fails with an error. With TRUNCATE, whatever fit is left in the buffer and the parent is
+// told that the message was truncated, which it raises as an error. The process keeps running, so
+// the parent can recover by sending a Resync.
+#[allow(non_camel_case_types)]
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum BufferFullPolicy {
+    ERROR,
+    TRUNCATE,
+}
+
+// Runs the writes of a message into message_bytes, which is cleared first, and copies the message to
+// the start of buf if it fits. A message which does not fit is handled according to the policy.
+// Returns the end of the message in buf, or None if the message was truncated.
+pub fn write_message<F>(
+    buffer_full_policy: &BufferFullPolicy,
+    proc_id: &str,
+    buf: &mut [u8],
+    message_bytes: &mut Vec<u8>,
+    write_fn: F,
+) -> PyResult<Option<usize>>
+where
+    F: FnOnce(&mut Vec<u8>) -> PyResult<()>,
+{
+    message_bytes.clear();
+    write_fn(message_bytes)?;
+    let message_len = message_bytes.len();
+    if message_len <= buf.len() {
+        buf[..message_len].copy_from_slice(&message_bytes[..]);
+        return Ok(Some(message_len));
+    }
+    if *buffer_full_policy == BufferFullPolicy::TRUNCATE {
+        let buf_len = buf.len();
+        buf.copy_from_slice(&message_bytes[..buf_len]);
+        println!(
+            "Env process {} truncated a message of {} bytes which did not fit in the shared memory buffer ({} bytes)",
+            proc_id, message_len, buf_len
+        );
+        return Ok(None);
+    }
+    Err(PyBufferError::new_err(format!(
+        "Env process {} could not fit a message of {} bytes in the shared memory buffer ({} bytes). Increase shm_buffer_size.",
+        proc_id,
+        message_len,
+        buf.len()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_within_buffer() {
+        let mut tracker = OffsetTracker::new(4, 16);
+        assert_eq!(tracker.advance_to(4).unwrap(), 4);
+        assert_eq!(tracker.advance_to(10).unwrap(), 10);
+        assert_eq!(tracker.advance_by(6).unwrap(), 16);
+        assert_eq!(tracker.advance_by(0).unwrap(), 16);
+        assert_eq!(tracker.offset(), 16);
+    }
+
+    #[test]
+    fn advance_out_of_range_is_error() {
+        let mut tracker = OffsetTracker::new(8, 16);
+        assert!(tracker.advance_to(7).is_err());
+        assert!(tracker.advance_to(17).is_err());
+        assert!(tracker.advance_by(9).is_err());
+        assert!(tracker.advance_by(usize::MAX).is_err());
+        // A rejected offset leaves the tracked offset unchanged
+        assert_eq!(tracker.offset(), 8);
+    }
+
+    #[test]
+    fn write_message_copies_message_which_fits() {
+        let mut buf = vec![0xff; 8];
+        let mut message_bytes = vec![9; 16];
+        let end = write_message(
+            &BufferFullPolicy::ERROR,
+            "p0",
+            &mut buf,
+            &mut message_bytes,
+            |v| {
+                v.extend_from_slice(&[1, 2, 3]);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(end, Some(3));
+        assert_eq!(buf, [1, 2, 3, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(message_bytes, [1, 2, 3]);
+    }
+
+    #[test]
+    fn write_message_handles_message_which_does_not_fit() {
+        pyo3::prepare_freethreaded_python();
+        let write_fn = |v: &mut Vec<u8>| {
+            v.extend(0..10);
+            Ok(())
+        };
+        let mut buf = vec![0xff; 4];
+        let err = write_message(
+            &BufferFullPolicy::ERROR,
+            "p0",
+            &mut buf,
+            &mut Vec::new(),
+            write_fn,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("a message of 10 bytes"));
+        // Nothing is copied to the buffer unless the policy is to truncate
+        assert_eq!(buf, [0xff; 4]);
+        let end = write_message(
+            &BufferFullPolicy::TRUNCATE,
+            "p0",
+            &mut buf,
+            &mut Vec::new(),
+            write_fn,
+        )
+        .unwrap();
+        assert_eq!(end, None);
+        assert_eq!(buf, [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,73 @@
+use pyo3::{intern, prelude::*};
+
+#[allow(non_camel_case_types)]
+#[pyclass(module = "rlgym_learn")]
+#[derive(Clone, Debug)]
+pub enum OutputCapture {
+    OFF {},
+    // Prefix each line written to the env's Python stdout / stderr with the proc id
+    PREFIX {},
+    // Redirect the env's Python stdout / stderr to a file per process in the given folder
+    FILE { folder: String },
+}
+
+#[pyclass(module = "rlgym_learn")]
+pub struct PrefixedWriter {
+    prefix: String,
+    stream: PyObject,
+    at_line_start: bool,
+}
+
+#[pymethods]
+impl PrefixedWriter {
+    fn write<'py>(&mut self, py: Python<'py>, s: &str) -> PyResult<usize> {
+        let mut prefixed = String::with_capacity(s.len() + self.prefix.len());
+        for line in s.split_inclusive('\n') {
+            if self.at_line_start {
+                prefixed.push_str(&self.prefix);
+            }
+            prefixed.push_str(line);
+            self.at_line_start = line.ends_with('\n');
+        }
+        self.stream
+            .call_method1(py, intern!(py, "write"), (prefixed,))?;
+        Ok(s.len())
+    }
+
+    fn flush<'py>(&self, py: Python<'py>) -> PyResult<()> {
+        self.stream.call_method0(py, intern!(py, "flush"))?;
+        Ok(())
+    }
+}
+
+pub fn install_output_capture<'py>(
+    py: Python<'py>,
+    proc_id: &str,
+    output_capture: &OutputCapture,
+) -> PyResult<()> {
+    let sys = py.import(intern!(py, "sys"))?;
+    match output_capture {
+        OutputCapture::OFF {} => (),
+        OutputCapture::PREFIX {} => {
+            let prefix = format!("[{}] ", proc_id);
+            for stream_name in [intern!(py, "stdout"), intern!(py, "stderr")] {
+                let writer = PrefixedWriter {
+                    prefix: prefix.clone(),
+                    stream: sys.getattr(stream_name)?.unbind(),
+                    at_line_start: true,
+                };
+                sys.setattr(stream_name, writer)?;
+            }
+        }
+        OutputCapture::FILE { folder } => {
+            std::fs::create_dir_all(folder)?;
+            let file = py.import(intern!(py, "builtins"))?.call_method1(
+                intern!(py, "open"),
+                (format!("{}/{}.log", folder, proc_id), "a", 1),
+            )?;
+            sys.setattr(intern!(py, "stdout"), &file)?;
+            sys.setattr(intern!(py, "stderr"), &file)?;
+        }
+    }
+    Ok(())
+}
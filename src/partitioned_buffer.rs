@@ -0,0 +1,225 @@
+use pyany_serde::communication::{append_bool, retrieve_bool};
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+use shared_memory::{Shmem, ShmemConf};
+
+use crate::communication::{append_usize_le, retrieve_usize_le};
+
+// Partitions start at a multiple of this from the start of the mapping, so that the event at the
+// start of each partition is aligned
+pub const PARTITION_ALIGNMENT: usize = 64;
+
+// The number of partitions and the partition size are written at the start of the mapping, padded
+// to the partition alignment
+const PARTITIONED_BUFFER_HEADER_BYTES: usize = PARTITION_ALIGNMENT;
+
+fn get_partitioned_buffer_flink(flinks_folder: &str) -> String {
+    format!("{}/partitioned_buffer", flinks_folder)
+}
+
+// A part of a shared memory mapping used as the buffer of one env process. This is either the whole
+// mapping created by the env process, or its partition of the partitioned buffer.
+pub struct ShmemRegion {
+    shmem: Shmem,
+    start: usize,
+    len: usize,
+}
+
+impl ShmemRegion {
+    pub fn new(shmem: Shmem) -> Self {
+        let len = shmem.len();
+        ShmemRegion {
+            shmem,
+            start: 0,
+            len,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        unsafe { self.shmem.as_ptr().add(self.start) }
+    }
+
+    /// # Safety
+    /// The same as for Shmem::as_slice, as the region is still shared with other processes
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        &self.shmem.as_slice()[self.start..self.start + self.len]
+    }
+
+    /// # Safety
+    /// The same as for Shmem::as_slice_mut, as the region is still shared with other processes
+    pub unsafe fn as_slice_mut(&mut self) -> &mut [u8] {
+        &mut self.shmem.as_slice_mut()[self.start..self.start + self.len]
+    }
+}
+
+// A single shared memory mapping created by the parent and split into a fixed number of equally
+// sized partitions, each used as the buffer of one env process instead of a mapping of its own. The
+// mapping starts with the number of partitions and the partition size, and the env process using a
+// partition is given the offset of its start in the mapping as its base offset.
+pub struct PartitionedBuffer {
+    _shmem: Shmem,
+    n_partitions: usize,
+    partition_bytes: usize,
+}
+
+impl PartitionedBuffer {
+    pub fn create(
+        flinks_folder: &str,
+        n_partitions: usize,
+        partition_bytes: usize,
+    ) -> PyResult<Self> {
+        if n_partitions == 0
+            || partition_bytes == 0
+            || !partition_bytes.is_multiple_of(PARTITION_ALIGNMENT)
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Tried to create a partitioned buffer of {} partitions of {} bytes, but there must be at least one partition and the partition size must be a non-zero multiple of {} bytes",
+                n_partitions, partition_bytes, PARTITION_ALIGNMENT
+            )));
+        }
+        let flink = get_partitioned_buffer_flink(flinks_folder);
+        let mut shmem = ShmemConf::new()
+            .size(PARTITIONED_BUFFER_HEADER_BYTES + n_partitions * partition_bytes)
+            .flink(&flink)
+            .create()
+            .map_err(|err| {
+                InvalidStateError::new_err(format!(
+                    "Unable to create partitioned buffer shmem flink {}: {}",
+                    flink, err
+                ))
+            })?;
+        let shm_slice = unsafe { shmem.as_slice_mut() };
+        let offset = append_usize_le(shm_slice, 0, n_partitions);
+        append_usize_le(shm_slice, offset, partition_bytes);
+        Ok(PartitionedBuffer {
+            _shmem: shmem,
+            n_partitions,
+            partition_bytes,
+        })
+    }
+
+    pub fn base_offset(&self, partition_idx: usize) -> PyResult<usize> {
+        if partition_idx >= self.n_partitions {
+            return Err(InvalidStateError::new_err(format!(
+                "Tried to use partition {} of the partitioned buffer, but the partitioned buffer only has {} partitions",
+                partition_idx, self.n_partitions
+            )));
+        }
+        Ok(PARTITIONED_BUFFER_HEADER_BYTES + partition_idx * self.partition_bytes)
+    }
+
+    // Opens the partition starting at the base offset as its own region of the mapping. The base
+    // offset must be the start of one of the partitions described by the header of the mapping.
+    pub fn open_partition(flinks_folder: &str, base_offset: usize) -> PyResult<ShmemRegion> {
+        let flink = get_partitioned_buffer_flink(flinks_folder);
+        let shmem = ShmemConf::new().flink(&flink).open().map_err(|err| {
+            InvalidStateError::new_err(format!(
+                "Unable to open partitioned buffer shmem flink {}: {}",
+                flink, err
+            ))
+        })?;
+        let shm_slice = unsafe { shmem.as_slice() };
+        let (n_partitions, offset) = retrieve_usize_le(shm_slice, 0)?;
+        let (partition_bytes, _) = retrieve_usize_le(shm_slice, offset)?;
+        let partition_idx_option = base_offset
+            .checked_sub(PARTITIONED_BUFFER_HEADER_BYTES)
+            .filter(|partitions_offset| {
+                partition_bytes > 0 && partitions_offset.is_multiple_of(partition_bytes)
+            })
+            .map(|partitions_offset| partitions_offset / partition_bytes)
+            .filter(|&partition_idx| partition_idx < n_partitions);
+        if partition_idx_option.is_none()
+            || PARTITIONED_BUFFER_HEADER_BYTES + n_partitions * partition_bytes > shm_slice.len()
+        {
+            return Err(InvalidStateError::new_err(format!(
+                "Partitioned buffer shmem flink {} has {} partitions of {} bytes starting at offset {}, but was given base offset {}, which is not the start of one of them",
+                flink, n_partitions, partition_bytes, PARTITIONED_BUFFER_HEADER_BYTES, base_offset
+            )));
+        }
+        Ok(ShmemRegion {
+            shmem,
+            start: base_offset,
+            len: partition_bytes,
+        })
+    }
+}
+
+// The base offset of the partition used by an env process is written at the start of its handshake,
+// if it uses one
+pub fn append_base_offset_option(
+    buf: &mut [u8],
+    offset: usize,
+    base_offset_option: Option<usize>,
+) -> usize {
+    let offset = append_bool(buf, offset, base_offset_option.is_some());
+    match base_offset_option {
+        Some(base_offset) => append_usize_le(buf, offset, base_offset),
+        None => offset,
+    }
+}
+
+pub fn retrieve_base_offset_option(buf: &[u8], offset: usize) -> PyResult<(Option<usize>, usize)> {
+    let (is_some, offset) = retrieve_bool(buf, offset)?;
+    if is_some {
+        let (base_offset, offset) = retrieve_usize_le(buf, offset)?;
+        Ok((Some(base_offset), offset))
+    } else {
+        Ok((None, offset))
+    }
+}
+
+pub fn describe_base_offset_option(base_offset_option: Option<usize>) -> String {
+    match base_offset_option {
+        Some(base_offset) => format!("the partition at base offset {}", base_offset),
+        None => "a buffer of its own".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_flinks_folder(name: &str) -> String {
+        let flinks_folder = std::env::temp_dir().join(format!(
+            "rlgym_learn_partitioned_buffer_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&flinks_folder).unwrap();
+        flinks_folder.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn partitions_do_not_overlap() {
+        let flinks_folder = temp_flinks_folder("overlap");
+        let partitioned_buffer = PartitionedBuffer::create(&flinks_folder, 2, 128).unwrap();
+        let mut first = PartitionedBuffer::open_partition(
+            &flinks_folder,
+            partitioned_buffer.base_offset(0).unwrap(),
+        )
+        .unwrap();
+        let mut second = PartitionedBuffer::open_partition(
+            &flinks_folder,
+            partitioned_buffer.base_offset(1).unwrap(),
+        )
+        .unwrap();
+        unsafe {
+            assert_eq!(first.as_slice().len(), 128);
+            first.as_slice_mut().fill(1);
+            second.as_slice_mut().fill(2);
+            assert!(first.as_slice().iter().all(|&byte| byte == 1));
+            assert!(second.as_slice().iter().all(|&byte| byte == 2));
+        }
+        assert!(partitioned_buffer.base_offset(2).is_err());
+    }
+
+    #[test]
+    fn base_offset_inside_partition_is_error() {
+        let flinks_folder = temp_flinks_folder("inside");
+        let partitioned_buffer = PartitionedBuffer::create(&flinks_folder, 2, 128).unwrap();
+        let base_offset = partitioned_buffer.base_offset(0).unwrap() + 8;
+        assert!(PartitionedBuffer::open_partition(&flinks_folder, base_offset).is_err());
+        assert!(PartitionedBuffer::open_partition(&flinks_folder, 0).is_err());
+        assert!(PartitionedBuffer::create(&temp_flinks_folder("unaligned"), 2, 100).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+use shared_memory::{Shmem, ShmemConf, ShmemError};
+
+#[allow(non_camel_case_types)]
+#[pyclass(module = "rlgym_learn")]
+#[derive(Clone, Debug)]
+pub enum RenderSync {
+    OFF {},
+    // Publish the time at which the next frame should end to the shared render clock
+    LEADER {},
+    // Sleep after each frame until the time published by the leader
+    FOLLOWER {},
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+// A single timestamp in its own shared memory region, shared by all the rendering processes using
+// the same flinks folder
+pub struct RenderClock {
+    render_sync: RenderSync,
+    flink: String,
+    shmem_option: Option<Shmem>,
+}
+
+impl RenderClock {
+    pub fn new(flinks_folder: &str, render_sync: RenderSync) -> Self {
+        RenderClock {
+            render_sync,
+            flink: format!("{}/render_clock", flinks_folder),
+            shmem_option: None,
+        }
+    }
+
+    // Followers may start before the leader has created the clock, so the region is opened lazily
+    fn clock(&mut self) -> PyResult<Option<&AtomicU64>> {
+        if self.shmem_option.is_none() {
+            self.shmem_option = match self.render_sync {
+                RenderSync::OFF {} => None,
+                RenderSync::LEADER {} => {
+                    let shmem = match ShmemConf::new()
+                        .size(size_of::<u64>())
+                        .flink(&self.flink)
+                        .create()
+                    {
+                        Err(ShmemError::LinkExists) => ShmemConf::new().flink(&self.flink).open(),
+                        result => result,
+                    }
+                    .map_err(|err| {
+                        InvalidStateError::new_err(format!(
+                            "Unable to create render clock shmem flink {}: {}",
+                            self.flink, err
+                        ))
+                    })?;
+                    Some(shmem)
+                }
+                RenderSync::FOLLOWER {} => ShmemConf::new().flink(&self.flink).open().ok(),
+            };
+        }
+        Ok(self
+            .shmem_option
+            .as_ref()
+            .map(|shmem| unsafe { &*(shmem.as_ptr() as *const AtomicU64) }))
+    }
+
+    // Called after each rendered frame. Returns true if the frame timing was handled by the render
+    // clock, in which case the process' own render delay should not be applied.
+    pub fn end_frame(&mut self, render_delay_option: Option<Duration>) -> PyResult<bool> {
+        let render_sync = self.render_sync.clone();
+        let Some(clock) = self.clock()? else {
+            return Ok(false);
+        };
+        match render_sync {
+            RenderSync::OFF {} => Ok(false),
+            RenderSync::LEADER {} => {
+                let frame_end = now_nanos()
+                    + render_delay_option.map_or(0, |render_delay| render_delay.as_nanos() as u64);
+                clock.store(frame_end, Ordering::Release);
+                let now = now_nanos();
+                if frame_end > now {
+                    sleep(Duration::from_nanos(frame_end - now));
+                }
+                Ok(true)
+            }
+            RenderSync::FOLLOWER {} => {
+                let frame_end = clock.load(Ordering::Acquire);
+                let now = now_nanos();
+                if frame_end > now {
+                    sleep(Duration::from_nanos(frame_end - now));
+                }
+                Ok(true)
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::intern;
+use pyo3::prelude::*;
+
+// Renders the env on its own thread so that the step loop is not blocked by rendering or the render
+// delay. The step loop publishes the env's state after each frame it would have rendered, replacing
+// any state the render thread has not picked up yet, so the render thread always renders the latest
+// state and skips frames if it falls behind.
+//
+// Envs with a renderer attribute (like rlgym's RLGym) are rendered by passing the published state and
+// the env's shared info to the renderer. Other envs are rendered by calling env.render(), which
+// renders whatever state the env is in when the render thread acquires the GIL.
+pub struct RenderThread {
+    shared: Arc<RenderShared>,
+    has_renderer: bool,
+}
+
+struct RenderShared {
+    slot: Mutex<RenderSlot>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct RenderSlot {
+    state_option: Option<PyObject>,
+    error_option: Option<PyErr>,
+    stopped: bool,
+}
+
+impl RenderThread {
+    pub fn spawn(
+        env: &Bound<'_, PyAny>,
+        proc_id: &str,
+        render_delay_option: Option<Duration>,
+    ) -> PyResult<Self> {
+        let has_renderer = env.hasattr(intern!(env.py(), "renderer"))?;
+        let shared = Arc::new(RenderShared {
+            slot: Mutex::new(RenderSlot::default()),
+            condvar: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let env = env.clone().unbind();
+        let proc_id = proc_id.to_owned();
+        spawn(move || {
+            render_loop(
+                &thread_shared,
+                &env,
+                has_renderer,
+                render_delay_option,
+                &proc_id,
+            )
+        });
+        Ok(RenderThread {
+            shared,
+            has_renderer,
+        })
+    }
+
+    // Hands the env's current state to the render thread. Returns the error the render thread
+    // stopped with, if any, in which case nothing more is rendered.
+    pub fn publish(&self, env: &Bound<'_, PyAny>) -> PyResult<()> {
+        let state = if self.has_renderer {
+            env.getattr(intern!(env.py(), "state"))?.unbind()
+        } else {
+            env.py().None()
+        };
+        let mut slot = self.shared.slot.lock().unwrap();
+        if let Some(err) = slot.error_option.take() {
+            return Err(err);
+        }
+        if !slot.stopped {
+            slot.state_option = Some(state);
+            self.shared.condvar.notify_one();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RenderThread {
+    // The thread is not joined, since it may be waiting for the GIL held by the dropping thread
+    fn drop(&mut self) {
+        self.shared.slot.lock().unwrap().stopped = true;
+        self.shared.condvar.notify_one();
+    }
+}
+
+fn render_loop(
+    shared: &RenderShared,
+    env: &PyObject,
+    has_renderer: bool,
+    render_delay_option: Option<Duration>,
+    proc_id: &str,
+) {
+    loop {
+        let state = {
+            let mut slot = shared
+                .condvar
+                .wait_while(shared.slot.lock().unwrap(), |slot| {
+                    slot.state_option.is_none() && !slot.stopped
+                })
+                .unwrap();
+            if slot.stopped {
+                return;
+            }
+            slot.state_option.take().unwrap()
+        };
+        let result = Python::with_gil(|py| {
+            let env = env.bind(py);
+            if has_renderer {
+                env.getattr(intern!(py, "renderer"))?.call_method1(
+                    intern!(py, "render"),
+                    (state, env.getattr(intern!(py, "shared_info"))?),
+                )?;
+            } else {
+                env.call_method0(intern!(py, "render"))?;
+            }
+            Ok::<_, PyErr>(())
+        });
+        if let Err(err) = result {
+            let render_err = Python::with_gil(|py| {
+                let render_err = InvalidStateError::new_err(format!(
+                    "Env process {} failed to render the env on the render thread",
+                    proc_id
+                ));
+                render_err.set_cause(py, Some(err));
+                render_err
+            });
+            let mut slot = shared.slot.lock().unwrap();
+            slot.error_option = Some(render_err);
+            slot.stopped = true;
+            return;
+        }
+        if let Some(render_delay) = render_delay_option {
+            sleep(render_delay);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use pyany_serde::communication::{append_bool, retrieve_bool};
+use pyany_serde::{PickleablePyAnySerdeType, PyAnySerde, PyAnySerdeType};
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+
+use crate::communication::{append_bytes_le, retrieve_bytes_le};
+
+// The wire format of every serde provided by pyany-serde is determined by the pyany-serde version
+// this crate was built against. This must be kept in sync with the dependency in Cargo.toml.
+const PYANY_SERDE_VERSION: &str = "pyany-serde 0.5.0";
+
+// The version of a serde is the pyany-serde version followed by the serde type, including the types
+// of any nested serdes. Python serdes are identified by their class, plus their version attribute
+// if they have one, since their wire format is up to the class.
+pub fn serde_type_version<'py>(py: Python<'py>, serde_type: &PyAnySerdeType) -> PyResult<String> {
+    Ok(format!(
+        "{} {}",
+        PYANY_SERDE_VERSION,
+        describe_serde_type(py, serde_type)?
+    ))
+}
+
+fn describe_serde_type<'py>(py: Python<'py>, serde_type: &PyAnySerdeType) -> PyResult<String> {
+    let describe_py = |serde_type: &Py<PyAnySerdeType>| {
+        describe_serde_type(py, &serde_type.extract::<PyAnySerdeType>(py)?)
+    };
+    let describe_list = |serde_type_list: &mut dyn Iterator<Item = &PyAnySerdeType>| {
+        serde_type_list
+            .map(|serde_type| describe_serde_type(py, serde_type))
+            .collect::<PyResult<Vec<_>>>()
+            .map(|description_list| description_list.join(", "))
+    };
+    Ok(match serde_type {
+        PyAnySerdeType::BOOL {} => "bool".to_owned(),
+        PyAnySerdeType::BYTES {} => "bytes".to_owned(),
+        PyAnySerdeType::COMPLEX {} => "complex".to_owned(),
+        PyAnySerdeType::DATACLASS {
+            clazz,
+            field_serde_type_dict,
+            ..
+        } => format!(
+            "dataclass {}({})",
+            qualified_name(clazz.bind(py))?,
+            field_serde_type_dict
+                .iter()
+                .map(|(field, field_serde_type)| Ok(format!(
+                    "{}: {}",
+                    field,
+                    describe_serde_type(py, field_serde_type)?
+                )))
+                .collect::<PyResult<Vec<_>>>()?
+                .join(", ")
+        ),
+        PyAnySerdeType::DICT {
+            keys_serde_type,
+            values_serde_type,
+        } => format!(
+            "dict[{}, {}]",
+            describe_py(keys_serde_type)?,
+            describe_py(values_serde_type)?
+        ),
+        PyAnySerdeType::DYNAMIC {} => "dynamic".to_owned(),
+        PyAnySerdeType::FLOAT {} => "float".to_owned(),
+        PyAnySerdeType::INT {} => "int".to_owned(),
+        PyAnySerdeType::LIST { items_serde_type } => {
+            format!("list[{}]", describe_py(items_serde_type)?)
+        }
+        PyAnySerdeType::NUMPY { dtype, .. } => format!("numpy[{}]", dtype),
+        PyAnySerdeType::OPTION { value_serde_type } => {
+            format!("option[{}]", describe_py(value_serde_type)?)
+        }
+        PyAnySerdeType::PICKLE {} => "pickle".to_owned(),
+        PyAnySerdeType::PYTHONSERDE { python_serde } => {
+            let python_serde = python_serde.bind(py);
+            let class_name = qualified_name(&python_serde.get_type().into_any())?;
+            match python_serde.getattr_opt("version")? {
+                Some(version) => format!("python serde {} version {}", class_name, version.str()?),
+                None => format!("python serde {}", class_name),
+            }
+        }
+        PyAnySerdeType::SET { items_serde_type } => {
+            format!("set[{}]", describe_py(items_serde_type)?)
+        }
+        PyAnySerdeType::STRING {} => "string".to_owned(),
+        PyAnySerdeType::TUPLE { item_serde_types } => {
+            format!("tuple[{}]", describe_list(&mut item_serde_types.iter())?)
+        }
+        PyAnySerdeType::TYPEDDICT {
+            key_serde_type_dict,
+        } => format!(
+            "typeddict[{}]",
+            key_serde_type_dict
+                .iter()
+                .map(|(key, item_serde_type)| Ok(format!(
+                    "{}: {}",
+                    key,
+                    describe_serde_type(py, item_serde_type)?
+                )))
+                .collect::<PyResult<Vec<_>>>()?
+                .join(", ")
+        ),
+        PyAnySerdeType::UNION {
+            option_serde_types, ..
+        } => format!("union[{}]", describe_list(&mut option_serde_types.iter())?),
+    })
+}
+
+fn qualified_name<'py>(clazz: &Bound<'py, PyAny>) -> PyResult<String> {
+    Ok(format!(
+        "{}.{}",
+        clazz.getattr("__module__")?.str()?,
+        clazz.getattr("__qualname__")?.str()?
+    ))
+}
+
+// A serde extracted together with its version, for the arguments of env processes and the parent.
+// The serde is accepted in the same forms as Box<dyn PyAnySerde>.
+pub struct VersionedSerde {
+    pub serde: Box<dyn PyAnySerde>,
+    pub version: String,
+}
+
+impl VersionedSerde {
+    fn from_serde_type<'py>(py: Python<'py>, serde_type: &PyAnySerdeType) -> PyResult<Self> {
+        Ok(VersionedSerde {
+            serde: serde_type.try_into()?,
+            version: serde_type_version(py, serde_type)?,
+        })
+    }
+}
+
+impl<'py> FromPyObject<'py> for VersionedSerde {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        ob.extract::<VersionedSerdeOption>()?
+            .0
+            .ok_or_else(|| InvalidStateError::new_err("Expected a serde type, but got None"))
+    }
+}
+
+// An optional serde extracted together with its version, accepted in the same forms as
+// DynPyAnySerdeOption
+// Extracts a serde type held by an options object, which is only known to be a serde type once the
+// options are used
+pub fn extract_serde_option<'py>(
+    py: Python<'py>,
+    serde_option: Option<Py<PyAny>>,
+) -> PyResult<Option<VersionedSerde>> {
+    serde_option
+        .map(|serde| serde.bind(py).extract::<VersionedSerde>())
+        .transpose()
+}
+
+pub struct VersionedSerdeOption(pub Option<VersionedSerde>);
+
+impl<'py> FromPyObject<'py> for VersionedSerdeOption {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let serde_type_option = ob.extract::<Option<PyAnySerdeType>>().or_else(|_| {
+            ob.extract::<PickleablePyAnySerdeType>()
+                .map(|v| v.0.flatten())
+        })?;
+        Ok(VersionedSerdeOption(
+            serde_type_option
+                .map(|serde_type| VersionedSerde::from_serde_type(ob.py(), &serde_type))
+                .transpose()?,
+        ))
+    }
+}
+
+// The versions of the serdes configured for an env process and its parent, in the order they are
+// written
+const SERDE_NAMES: [&str; 13] = [
+    "agent id",
+    "action",
+    "obs",
+    "reward",
+    "obs space",
+    "action space",
+    "shared info",
+    "shared info setter",
+    "state",
+    "reward component",
+    "critic obs",
+    "global obs",
+    "legal action mask",
+];
+
+#[derive(Clone)]
+pub struct SerdeVersionList(pub [(&'static str, Option<String>); 13]);
+
+impl SerdeVersionList {
+    // The required serdes are given in the order agent id, action, obs, reward, obs space and action
+    // space. The optional serdes are given in the order shared info, shared info setter, state,
+    // reward component, critic obs, global obs and legal action mask.
+    pub fn new(
+        required_serdes: [&VersionedSerde; 6],
+        optional_serdes: [Option<&VersionedSerde>; 7],
+    ) -> Self {
+        let mut serde_version_list = SerdeVersionList(SERDE_NAMES.map(|name| (name, None)));
+        let required_versions = required_serdes.map(|serde| Some(serde.version.clone()));
+        let optional_versions =
+            optional_serdes.map(|serde_option| serde_option.map(|serde| serde.version.clone()));
+        for ((_, version_option), version) in serde_version_list
+            .0
+            .iter_mut()
+            .zip(required_versions.into_iter().chain(optional_versions))
+        {
+            *version_option = version;
+        }
+        serde_version_list
+    }
+
+    // Replaces the version of a configured serde, e.g. after the serde is reconfigured at runtime.
+    // Serdes which are not configured stay unconfigured.
+    pub fn set_version(&mut self, name: &str, version: String) {
+        for (serde_name, version_option) in self.0.iter_mut() {
+            if *serde_name == name && version_option.is_some() {
+                *version_option = Some(version);
+                return;
+            }
+        }
+    }
+
+    // Written by the env process during the startup handshake. Each serde is written as whether it is
+    // configured, followed by its version if so.
+    pub fn append(&self, buf: &mut [u8], mut offset: usize) -> usize {
+        for (_, version_option) in self.0.iter() {
+            offset = append_bool(buf, offset, version_option.is_some());
+            if let Some(version) = version_option {
+                offset = append_bytes_le(buf, offset, version.as_bytes());
+            }
+        }
+        offset
+    }
+
+    // Checks the versions written by an env process against the serdes of the parent, so that a
+    // mismatch is raised at startup rather than silently corrupting data. Returns the offset after
+    // the versions.
+    pub fn check(&self, buf: &[u8], mut offset: usize, proc_id: &str) -> PyResult<usize> {
+        for (name, parent_version_option) in self.0.iter() {
+            let is_some;
+            (is_some, offset) = retrieve_bool(buf, offset)?;
+            let version_option = if is_some {
+                let version_bytes;
+                (version_bytes, offset) = retrieve_bytes_le(buf, offset)?;
+                Some(String::from_utf8_lossy(version_bytes).into_owned())
+            } else {
+                None
+            };
+            if version_option != *parent_version_option {
+                return Err(InvalidStateError::new_err(format!(
+                    "Env process {} reported {} for its {} serde, but the parent has {}",
+                    proc_id,
+                    describe_version(version_option.as_deref()),
+                    name,
+                    describe_version(parent_version_option.as_deref())
+                )));
+            }
+        }
+        Ok(offset)
+    }
+}
+
+fn describe_version(version_option: Option<&str>) -> String {
+    match version_option {
+        Some(version) => format!("version {}", version),
+        None => "no serde configured".to_owned(),
+    }
+}
@@ -0,0 +1,108 @@
+use std::mem::size_of;
+
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+use shared_memory::{Shmem, ShmemConf};
+
+use crate::communication::{
+    append_bytes_le, append_usize_le, retrieve_bytes_le, retrieve_usize_le,
+};
+
+// A fixed number of fixed size slots of serialized states in their own shared memory region,
+// populated by the parent and read by all the env processes using the same flinks folder. The
+// region starts with the number of slots and the slot size, followed by each slot as a length
+// prefixed byte string.
+pub struct StatePool {
+    shmem: Shmem,
+    n_slots: usize,
+    slot_bytes: usize,
+}
+
+const POOL_HEADER_BYTES: usize = 2 * size_of::<u64>();
+
+fn get_pool_flink(flinks_folder: &str) -> String {
+    format!("{}/state_pool", flinks_folder)
+}
+
+impl StatePool {
+    pub fn create(flinks_folder: &str, n_slots: usize, slot_bytes: usize) -> PyResult<Self> {
+        let flink = get_pool_flink(flinks_folder);
+        let mut shmem = ShmemConf::new()
+            .size(POOL_HEADER_BYTES + n_slots * (size_of::<u64>() + slot_bytes))
+            .flink(&flink)
+            .create()
+            .map_err(|err| {
+                InvalidStateError::new_err(format!(
+                    "Unable to create state pool shmem flink {}: {}",
+                    flink, err
+                ))
+            })?;
+        let shm_slice = unsafe { shmem.as_slice_mut() };
+        let offset = append_usize_le(shm_slice, 0, n_slots);
+        append_usize_le(shm_slice, offset, slot_bytes);
+        Ok(StatePool {
+            shmem,
+            n_slots,
+            slot_bytes,
+        })
+    }
+
+    pub fn open(flinks_folder: &str) -> PyResult<Self> {
+        let flink = get_pool_flink(flinks_folder);
+        let shmem = ShmemConf::new().flink(&flink).open().map_err(|err| {
+            InvalidStateError::new_err(format!(
+                "Unable to open state pool shmem flink {}: {}",
+                flink, err
+            ))
+        })?;
+        let shm_slice = unsafe { shmem.as_slice() };
+        let (n_slots, offset) = retrieve_usize_le(shm_slice, 0)?;
+        let (slot_bytes, _) = retrieve_usize_le(shm_slice, offset)?;
+        let region_bytes_option = n_slots
+            .checked_mul(size_of::<u64>() + slot_bytes)
+            .and_then(|slots_bytes| slots_bytes.checked_add(POOL_HEADER_BYTES));
+        if region_bytes_option.is_none_or(|region_bytes| region_bytes > shm_slice.len()) {
+            return Err(InvalidStateError::new_err(format!(
+                "State pool shmem flink {} describes {} slots of {} bytes, which do not fit in its region of {} bytes",
+                flink,
+                n_slots,
+                slot_bytes,
+                shm_slice.len()
+            )));
+        }
+        Ok(StatePool {
+            shmem,
+            n_slots,
+            slot_bytes,
+        })
+    }
+
+    fn slot_offset(&self, index: usize) -> PyResult<usize> {
+        if index >= self.n_slots {
+            return Err(InvalidStateError::new_err(format!(
+                "Tried to access state pool slot {}, but the state pool only has {} slots",
+                index, self.n_slots
+            )));
+        }
+        Ok(POOL_HEADER_BYTES + index * (size_of::<u64>() + self.slot_bytes))
+    }
+
+    pub fn write(&mut self, index: usize, state_bytes: &[u8]) -> PyResult<()> {
+        if state_bytes.len() > self.slot_bytes {
+            return Err(InvalidStateError::new_err(format!(
+                "Tried to write a serialized state of {} bytes to the state pool, but state pool slots only hold {} bytes",
+                state_bytes.len(),
+                self.slot_bytes
+            )));
+        }
+        let offset = self.slot_offset(index)?;
+        append_bytes_le(unsafe { self.shmem.as_slice_mut() }, offset, state_bytes);
+        Ok(())
+    }
+
+    pub fn read(&self, index: usize) -> PyResult<&[u8]> {
+        let offset = self.slot_offset(index)?;
+        let (state_bytes, _) = retrieve_bytes_le(unsafe { self.shmem.as_slice() }, offset)?;
+        Ok(state_bytes)
+    }
+}
@@ -1,4 +1,5 @@
 use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::exceptions::PyBufferError;
 use pyo3::sync::GILOnceCell;
 use pyo3::types::PyBytes;
 use pyo3::{intern, prelude::*, IntoPyObjectExt};
@@ -9,6 +10,19 @@ pub enum Header {
     EnvShapesRequest,
     EnvAction,
     Stop,
+    SetSeed,
+    SampleAction,
+    Observe,
+    DumpState,
+    AgentCount,
+    SetStateFromPool,
+    DumpBuffer,
+    GetActionHistory,
+    Reconfigure,
+    Resync,
+    GetConfig,
+    SetStateBatch,
+    WarmStart,
 }
 
 impl Display for Header {
@@ -17,6 +31,19 @@ impl Display for Header {
             Self::EnvShapesRequest => write!(f, "EnvShapesRequest"),
             Self::EnvAction => write!(f, "EnvAction"),
             Self::Stop => write!(f, "Stop"),
+            Self::SetSeed => write!(f, "SetSeed"),
+            Self::SampleAction => write!(f, "SampleAction"),
+            Self::Observe => write!(f, "Observe"),
+            Self::DumpState => write!(f, "DumpState"),
+            Self::AgentCount => write!(f, "AgentCount"),
+            Self::SetStateFromPool => write!(f, "SetStateFromPool"),
+            Self::DumpBuffer => write!(f, "DumpBuffer"),
+            Self::GetActionHistory => write!(f, "GetActionHistory"),
+            Self::Reconfigure => write!(f, "Reconfigure"),
+            Self::Resync => write!(f, "Resync"),
+            Self::GetConfig => write!(f, "GetConfig"),
+            Self::SetStateBatch => write!(f, "SetStateBatch"),
+            Self::WarmStart => write!(f, "WarmStart"),
         }
     }
 }
@@ -46,15 +73,142 @@ pub fn sendto_byte<'py>(socket: &Bound<'py, PyAny>, address: &Bound<'py, PyAny>)
     Ok(())
 }
 
+// Sent by an env process instead of its usual acknowledgement when it fails with an error, followed
+// by a second datagram containing the formatted traceback of the error
+pub const ERROR_REPORT_BYTE: u8 = 1;
+
+// Sent by an env process instead of its usual acknowledgement when the message it wrote did not fit
+// in the shared memory buffer and was truncated, with the truncate buffer full policy
+pub const BUFFER_TRUNCATED_BYTE: u8 = 2;
+
+pub fn send_buffer_truncated<'py>(
+    socket: &Bound<'py, PyAny>,
+    address: &Bound<'py, PyAny>,
+) -> PyResult<()> {
+    let py = socket.py();
+    socket.call_method1(
+        intern!(py, "sendto"),
+        (PyBytes::new(py, &[BUFFER_TRUNCATED_BYTE]), address),
+    )?;
+    Ok(())
+}
+
+// Sent by an env process instead of its usual acknowledgement when it received a header value it
+// does not recognize, followed by a second datagram containing that header value
+pub const HEADER_NOT_SUPPORTED_BYTE: u8 = 3;
+
+pub fn send_header_not_supported<'py>(
+    socket: &Bound<'py, PyAny>,
+    address: &Bound<'py, PyAny>,
+    header_value: u8,
+) -> PyResult<()> {
+    let py = socket.py();
+    socket.call_method1(
+        intern!(py, "sendto"),
+        (PyBytes::new(py, &[HEADER_NOT_SUPPORTED_BYTE]), address),
+    )?;
+    socket.call_method1(
+        intern!(py, "sendto"),
+        (PyBytes::new(py, &[header_value]), address),
+    )?;
+    Ok(())
+}
+
+// Tracebacks are truncated to their last this many bytes so that they fit in a single datagram
+const MAX_ERROR_REPORT_LEN: usize = 60000;
+
+pub fn send_error_report<'py>(
+    socket: &Bound<'py, PyAny>,
+    address: &Bound<'py, PyAny>,
+    err: &PyErr,
+) -> PyResult<()> {
+    let py = socket.py();
+    let traceback = py
+        .import("traceback")?
+        .call_method1(
+            intern!(py, "format_exception"),
+            (err.get_type(py), err.value(py), err.traceback(py)),
+        )?
+        .extract::<Vec<String>>()?
+        .concat();
+    let mut start = traceback.len().saturating_sub(MAX_ERROR_REPORT_LEN);
+    while !traceback.is_char_boundary(start) {
+        start += 1;
+    }
+    socket.call_method1(
+        intern!(py, "sendto"),
+        (PyBytes::new(py, &[ERROR_REPORT_BYTE]), address),
+    )?;
+    socket.call_method1(
+        intern!(py, "sendto"),
+        (PyBytes::new(py, &traceback.as_bytes()[start..]), address),
+    )?;
+    Ok(())
+}
+
+// Receives the acknowledgement of an env process. If the env process reported an error instead, the
+// error is raised with the traceback from the env process. If it reported that its message was
+// truncated or that it did not recognize the header, an error is raised as well, but the env process
+// keeps running.
+pub fn recvfrom_ack(socket: &Bound<'_, PyAny>, proc_id: &str) -> PyResult<()> {
+    let py = socket.py();
+    let ack = recvfrom_byte(socket)?.get_item(0)?;
+    match ack.downcast::<PyBytes>()?.as_bytes() {
+        [ERROR_REPORT_BYTE] => (),
+        [BUFFER_TRUNCATED_BYTE] => {
+            return Err(PyBufferError::new_err(format!(
+                "Env process {} could not fit its message in the shared memory buffer and truncated it. Increase shm_buffer_size, and send a resync to the process to continue using it.",
+                proc_id
+            )));
+        }
+        [HEADER_NOT_SUPPORTED_BYTE] => {
+            let header_value = recvfrom_byte(socket)?.get_item(0)?;
+            return Err(InvalidStateError::new_err(format!(
+                "Env process {} does not support header value {}, which is not a header known to its build. The env process is still running.",
+                proc_id,
+                header_value.downcast::<PyBytes>()?.as_bytes()[0]
+            )));
+        }
+        _ => return Ok(()),
+    }
+    let traceback_bytes = socket
+        .call_method1(intern!(py, "recvfrom"), (MAX_ERROR_REPORT_LEN,))?
+        .get_item(0)?;
+    Err(InvalidStateError::new_err(format!(
+        "Env process {} failed with the following error:\n{}",
+        proc_id,
+        String::from_utf8_lossy(traceback_bytes.downcast::<PyBytes>()?.as_bytes())
+    )))
+}
+
 pub fn get_flink(flinks_folder: &str, proc_id: &str) -> String {
     format!("{}/{}", flinks_folder, proc_id)
 }
 
+// The flink of the buffer an env process moves to when it resizes its shared memory buffer for the
+// given time
+pub fn get_resized_flink(flinks_folder: &str, proc_id: &str, buffer_generation: usize) -> String {
+    format!("{}/{}.{}", flinks_folder, proc_id, buffer_generation)
+}
+
 pub fn append_header(buf: &mut [u8], offset: usize, header: Header) -> usize {
     buf[offset] = match header {
         Header::EnvShapesRequest => 0,
         Header::EnvAction => 1,
         Header::Stop => 2,
+        Header::SetSeed => 3,
+        Header::SampleAction => 4,
+        Header::Observe => 5,
+        Header::DumpState => 6,
+        Header::AgentCount => 7,
+        Header::SetStateFromPool => 8,
+        Header::DumpBuffer => 9,
+        Header::GetActionHistory => 10,
+        Header::Reconfigure => 11,
+        Header::Resync => 12,
+        Header::GetConfig => 13,
+        Header::SetStateBatch => 14,
+        Header::WarmStart => 15,
     };
     offset + 1
 }
@@ -64,10 +218,61 @@ pub fn retrieve_header(slice: &[u8], offset: usize) -> PyResult<(Header, usize)>
         0 => Ok(Header::EnvShapesRequest),
         1 => Ok(Header::EnvAction),
         2 => Ok(Header::Stop),
+        3 => Ok(Header::SetSeed),
+        4 => Ok(Header::SampleAction),
+        5 => Ok(Header::Observe),
+        6 => Ok(Header::DumpState),
+        7 => Ok(Header::AgentCount),
+        8 => Ok(Header::SetStateFromPool),
+        9 => Ok(Header::DumpBuffer),
+        10 => Ok(Header::GetActionHistory),
+        11 => Ok(Header::Reconfigure),
+        12 => Ok(Header::Resync),
+        13 => Ok(Header::GetConfig),
+        14 => Ok(Header::SetStateBatch),
+        15 => Ok(Header::WarmStart),
         v => Err(InvalidStateError::new_err(format!(
-            "tried to retrieve header from shared_memory but got value {}",
+            "tried to retrieve header from shared_memory but got value {}, which is not a header supported by this build",
             v
         ))),
     }?;
     Ok((header, offset + 1))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_not_supported_ack_names_header() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let socket_module = py.import("socket").unwrap();
+            let af_inet = socket_module.getattr("AF_INET").unwrap();
+            let sock_dgram = socket_module.getattr("SOCK_DGRAM").unwrap();
+            let new_socket = || {
+                let socket = socket_module
+                    .call_method1("socket", (&af_inet, &sock_dgram))
+                    .unwrap();
+                socket.call_method1("bind", (("127.0.0.1", 0),)).unwrap();
+                socket.call_method1("settimeout", (5.0,)).unwrap();
+                socket
+            };
+            let child_end = new_socket();
+            let parent_end = new_socket();
+            let parent_sockname = parent_end.call_method0("getsockname").unwrap();
+            send_header_not_supported(&child_end, &parent_sockname, 200).unwrap();
+            let err = recvfrom_ack(&parent_end, "p0").unwrap_err();
+            assert!(err.is_instance_of::<InvalidStateError>(py));
+            assert!(err
+                .to_string()
+                .contains("Env process p0 does not support header value 200"));
+            // The header value is consumed with the acknowledgement, so the next one is read as usual
+            sendto_byte(&child_end, &parent_sockname).unwrap();
+            recvfrom_ack(&parent_end, "p0").unwrap();
+            for socket in [child_end, parent_end] {
+                socket.call_method0("close").unwrap();
+            }
+        });
+    }
+}
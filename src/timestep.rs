@@ -12,11 +12,43 @@ pub struct Timestep {
     pub reward: PyObject,
     pub terminated: bool,
     pub truncated: bool,
+    pub reward_components: Option<PyObject>,
+    pub critic_obs: Option<PyObject>,
+    pub next_critic_obs: Option<PyObject>,
+    pub global_obs: Option<PyObject>,
+    pub next_global_obs: Option<PyObject>,
+    pub state_hash: Option<u64>,
+    pub next_state_hash: Option<u64>,
+    pub n_env_steps: Option<usize>,
+    pub done_reason: Option<u8>,
+    pub should_bootstrap: Option<bool>,
 }
 
 #[pymethods]
 impl Timestep {
     #[new]
+    #[pyo3(signature = (
+        env_id,
+        timestep_id,
+        previous_timestep_id,
+        agent_id,
+        obs,
+        next_obs,
+        action,
+        reward,
+        terminated,
+        truncated,
+        reward_components=None,
+        critic_obs=None,
+        next_critic_obs=None,
+        done_reason=None,
+        should_bootstrap=None,
+        global_obs=None,
+        next_global_obs=None,
+        state_hash=None,
+        next_state_hash=None,
+        n_env_steps=None,
+        ))]
     pub fn new(
         env_id: String,
         timestep_id: u128,
@@ -28,6 +60,16 @@ impl Timestep {
         reward: PyObject,
         terminated: bool,
         truncated: bool,
+        reward_components: Option<PyObject>,
+        critic_obs: Option<PyObject>,
+        next_critic_obs: Option<PyObject>,
+        done_reason: Option<u8>,
+        should_bootstrap: Option<bool>,
+        global_obs: Option<PyObject>,
+        next_global_obs: Option<PyObject>,
+        state_hash: Option<u64>,
+        next_state_hash: Option<u64>,
+        n_env_steps: Option<usize>,
     ) -> Self {
         Timestep {
             env_id,
@@ -40,6 +82,16 @@ impl Timestep {
             reward,
             terminated,
             truncated,
+            reward_components,
+            critic_obs,
+            next_critic_obs,
+            done_reason,
+            should_bootstrap,
+            global_obs,
+            next_global_obs,
+            state_hash,
+            next_state_hash,
+            n_env_steps,
         }
     }
 }
@@ -0,0 +1,226 @@
+use pyo3::exceptions::asyncio::InvalidStateError;
+use pyo3::prelude::*;
+
+use crate::synchronization::sendto_byte;
+
+// Env action responses are built in the shared memory buffer and then sent to the parent by a
+// transport. The parent is signalled through its socket with either transport, so that it can keep
+// selecting over all of its env processes.
+pub trait MessageTransport {
+    fn send_message(&mut self, message_bytes: &[u8]) -> PyResult<()>;
+}
+
+// Leaves the message in the shared memory buffer for the parent to read
+pub struct ShmemTransport<'a, 'py> {
+    child_end: &'a Bound<'py, PyAny>,
+    parent_sockname: &'a Bound<'py, PyAny>,
+}
+
+impl<'a, 'py> ShmemTransport<'a, 'py> {
+    pub fn new(child_end: &'a Bound<'py, PyAny>, parent_sockname: &'a Bound<'py, PyAny>) -> Self {
+        ShmemTransport {
+            child_end,
+            parent_sockname,
+        }
+    }
+}
+
+impl MessageTransport for ShmemTransport<'_, '_> {
+    fn send_message(&mut self, _message_bytes: &[u8]) -> PyResult<()> {
+        sendto_byte(self.child_end, self.parent_sockname)
+    }
+}
+
+// Enqueues the message on the POSIX message queue of the env process before signalling the parent
+pub struct MqueueTransport<'a, 'py> {
+    mqueue: Mqueue,
+    child_end: &'a Bound<'py, PyAny>,
+    parent_sockname: &'a Bound<'py, PyAny>,
+}
+
+impl<'a, 'py> MqueueTransport<'a, 'py> {
+    pub fn create(
+        proc_id: &str,
+        max_message_bytes: usize,
+        child_end: &'a Bound<'py, PyAny>,
+        parent_sockname: &'a Bound<'py, PyAny>,
+    ) -> PyResult<Self> {
+        Ok(MqueueTransport {
+            mqueue: Mqueue::create(&get_mqueue_name(proc_id), max_message_bytes)?,
+            child_end,
+            parent_sockname,
+        })
+    }
+}
+
+impl MessageTransport for MqueueTransport<'_, '_> {
+    fn send_message(&mut self, message_bytes: &[u8]) -> PyResult<()> {
+        self.mqueue.send(message_bytes)?;
+        sendto_byte(self.child_end, self.parent_sockname)
+    }
+}
+
+pub fn get_mqueue_name(proc_id: &str) -> String {
+    format!("/rlgym-learn-{}", proc_id)
+}
+
+// A POSIX message queue holding at most one message, which is only supported on Linux. The queue
+// is created by the env process and unlinked when the parent drops its end.
+pub struct Mqueue {
+    name: String,
+    #[cfg(target_os = "linux")]
+    mqd: libc::mqd_t,
+    #[cfg(target_os = "linux")]
+    unlink_on_drop: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Mqueue {
+    fn c_name(name: &str) -> PyResult<std::ffi::CString> {
+        std::ffi::CString::new(name).map_err(|err| {
+            InvalidStateError::new_err(format!("Invalid message queue name {}: {}", name, err))
+        })
+    }
+
+    // Any queue left behind under the same name by a previous run is replaced
+    pub fn create(name: &str, max_message_bytes: usize) -> PyResult<Self> {
+        let c_name = Self::c_name(name)?;
+        let mut attr: libc::mq_attr = unsafe { std::mem::zeroed() };
+        attr.mq_maxmsg = 1;
+        attr.mq_msgsize = max_message_bytes as _;
+        let mqd = unsafe {
+            libc::mq_unlink(c_name.as_ptr());
+            libc::mq_open(
+                c_name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_WRONLY,
+                0o600 as libc::mode_t,
+                &mut attr as *mut libc::mq_attr,
+            )
+        };
+        if mqd == -1 {
+            return Err(InvalidStateError::new_err(format!(
+                "Unable to create message queue {} for messages of up to {} bytes (the limit for unprivileged processes is /proc/sys/fs/mqueue/msgsize_max): {}",
+                name,
+                max_message_bytes,
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(Mqueue {
+            name: name.to_owned(),
+            mqd,
+            unlink_on_drop: false,
+        })
+    }
+
+    pub fn open(name: &str) -> PyResult<Self> {
+        let c_name = Self::c_name(name)?;
+        let mqd = unsafe { libc::mq_open(c_name.as_ptr(), libc::O_RDONLY) };
+        if mqd == -1 {
+            return Err(InvalidStateError::new_err(format!(
+                "Unable to open message queue {}: {}",
+                name,
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(Mqueue {
+            name: name.to_owned(),
+            mqd,
+            unlink_on_drop: true,
+        })
+    }
+
+    pub fn send(&self, message_bytes: &[u8]) -> PyResult<()> {
+        let result = unsafe {
+            libc::mq_send(
+                self.mqd,
+                message_bytes.as_ptr() as *const libc::c_char,
+                message_bytes.len(),
+                0,
+            )
+        };
+        if result == -1 {
+            return Err(InvalidStateError::new_err(format!(
+                "Unable to send a message of {} bytes on message queue {}: {}",
+                message_bytes.len(),
+                self.name,
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn receive(&self) -> PyResult<Vec<u8>> {
+        let mut attr: libc::mq_attr = unsafe { std::mem::zeroed() };
+        if unsafe { libc::mq_getattr(self.mqd, &mut attr) } == -1 {
+            return Err(InvalidStateError::new_err(format!(
+                "Unable to get the attributes of message queue {}: {}",
+                self.name,
+                std::io::Error::last_os_error()
+            )));
+        }
+        let mut message_bytes = vec![0; attr.mq_msgsize as usize];
+        let len = unsafe {
+            libc::mq_receive(
+                self.mqd,
+                message_bytes.as_mut_ptr() as *mut libc::c_char,
+                message_bytes.len(),
+                std::ptr::null_mut(),
+            )
+        };
+        if len == -1 {
+            return Err(InvalidStateError::new_err(format!(
+                "Unable to receive a message on message queue {}: {}",
+                self.name,
+                std::io::Error::last_os_error()
+            )));
+        }
+        message_bytes.truncate(len as usize);
+        Ok(message_bytes)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Mqueue {
+    fn drop(&mut self) {
+        unsafe {
+            libc::mq_close(self.mqd);
+            if self.unlink_on_drop {
+                if let Ok(c_name) = Self::c_name(&self.name) {
+                    libc::mq_unlink(c_name.as_ptr());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Mqueue {
+    fn unsupported<T>(&self) -> PyResult<T> {
+        Err(InvalidStateError::new_err(format!(
+            "Unable to use message queue {}: POSIX message queues are only supported on Linux",
+            self.name
+        )))
+    }
+
+    pub fn create(name: &str, _max_message_bytes: usize) -> PyResult<Self> {
+        Mqueue {
+            name: name.to_owned(),
+        }
+        .unsupported()
+    }
+
+    pub fn open(name: &str) -> PyResult<Self> {
+        Mqueue {
+            name: name.to_owned(),
+        }
+        .unsupported()
+    }
+
+    pub fn send(&self, _message_bytes: &[u8]) -> PyResult<()> {
+        self.unsupported()
+    }
+
+    pub fn receive(&self) -> PyResult<Vec<u8>> {
+        self.unsupported()
+    }
+}